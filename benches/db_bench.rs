@@ -1,7 +1,10 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use ordb::ObliviousDB;
+#[cfg(target_os = "linux")]
+use ordb::UringStore;
+use ordb::{BlockStorage, MemStore, ORAMTree, ObliviousDB, PageFile};
 use rayon::vec;
 use serde::de::value;
+use std::time::{Duration, Instant};
 
 fn benchmark_db_insert_small_kv(c: &mut Criterion) {
     let db = ObliviousDB::new();
@@ -134,6 +137,110 @@ fn benchmark_db_get_10m_small_val(c: &mut Criterion) {
     });
 }
 
+fn benchmark_db_bulk_load_100k(c: &mut Criterion) {
+    const KEY_SIZE: usize = 32;
+    const VALUE_SIZE: usize = 32;
+    const DB_SIZE: usize = 100_000;
+    let items: Vec<([u8; KEY_SIZE], [u8; VALUE_SIZE])> = (0..DB_SIZE)
+        .map(|_| (rand::random(), rand::random()))
+        .collect();
+    let mut group = c.benchmark_group("db_bulk_load");
+    group.sample_size(10);
+    group.bench_function("db_bulk_load_100k", |b| {
+        b.iter(|| {
+            let db = ObliviousDB::new();
+            db.bulk_load(items.iter().map(|(key, value)| (key, value)))
+                .unwrap();
+            black_box(db)
+        })
+    });
+    group.bench_function("db_insert_100k", |b| {
+        b.iter(|| {
+            let db = ObliviousDB::new();
+            for (key, value) in items.iter() {
+                db.insert(key, value);
+            }
+            black_box(db)
+        })
+    });
+    group.finish();
+}
+
+// latency percentiles of single inserts while the db grows across several doublings of the tree and the stash,
+// which the mean reported by criterion hides
+fn print_insert_latency_percentiles(num_inserts: usize) {
+    const KEY_SIZE: usize = 32;
+    const VALUE_SIZE: usize = 32;
+    let db = ObliviousDB::new();
+    let mut latencies: Vec<Duration> = Vec::with_capacity(num_inserts);
+    for _ in 0..num_inserts {
+        let key = rand::random::<[u8; KEY_SIZE]>();
+        let value = rand::random::<[u8; VALUE_SIZE]>();
+        let start = Instant::now();
+        db.insert(key, value);
+        latencies.push(start.elapsed());
+    }
+    latencies.sort();
+    let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+    println!(
+        "insert latency over {} inserts: p50 {:?} p99 {:?} p99.9 {:?} max {:?}",
+        num_inserts,
+        percentile(0.5),
+        percentile(0.99),
+        percentile(0.999),
+        latencies.last().unwrap()
+    );
+}
+
+fn benchmark_db_insert_across_doubling(c: &mut Criterion) {
+    const KEY_SIZE: usize = 32;
+    const VALUE_SIZE: usize = 32;
+    const DB_SIZE: usize = 200_000;
+    print_insert_latency_percentiles(DB_SIZE);
+    let mut group = c.benchmark_group("db_insert_across_doubling");
+    group.sample_size(10);
+    group.bench_function("db_insert_200k", |b| {
+        b.iter(|| {
+            let db = ObliviousDB::new();
+            for _ in 0..DB_SIZE {
+                let key = rand::random::<[u8; KEY_SIZE]>();
+                let value = rand::random::<[u8; VALUE_SIZE]>();
+                db.insert(key, value);
+            }
+            black_box(db)
+        })
+    });
+    group.finish();
+}
+
+// a tree of about a million pages whose layers are files in the working directory, except for MemStore
+fn build_file_tree<S: BlockStorage>() -> ORAMTree<[u8; 1024], S> {
+    let mut tree = ORAMTree::new(1 << 16);
+    while tree.total_size() < 1 << 20 {
+        tree.scale(16);
+    }
+    tree
+}
+
+fn benchmark_tree_path_storage(c: &mut Criterion) {
+    fn bench_path<S: BlockStorage>(c: &mut Criterion, name: &str) {
+        let mut tree = build_file_tree::<S>();
+        let mut group = c.benchmark_group("tree_path_1m_pages");
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let index = rand::random::<usize>();
+                let (path, _) = tree.read_path(index);
+                tree.write_path(index, black_box(&path));
+            })
+        });
+        group.finish();
+    }
+    bench_path::<MemStore>(c, "mem_store");
+    bench_path::<PageFile>(c, "page_file");
+    #[cfg(target_os = "linux")]
+    bench_path::<UringStore>(c, "uring_store");
+}
+
 criterion_group!(
     benches,
     benchmark_db_insert_solidity,
@@ -142,6 +249,9 @@ criterion_group!(
     benchmark_db_insert_varied_val,
     benchmark_db_get_100k,
     benchmark_db_get_1m,
-    benchmark_db_get_10m_small_val
+    benchmark_db_get_10m_small_val,
+    benchmark_db_bulk_load_100k,
+    benchmark_db_insert_across_doubling,
+    benchmark_tree_path_storage
 );
 criterion_main!(benches);
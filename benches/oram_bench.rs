@@ -0,0 +1,58 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ordb::{Oram, OramScheme, RecOram};
+
+const BLOCKS_PER_PAGE: usize = 64;
+const SCHEMES: [OramScheme; 2] = [OramScheme::Path, OramScheme::Circuit];
+
+fn build_oram(scheme: OramScheme, size: usize) -> RecOram<u64, BLOCKS_PER_PAGE> {
+    let mut oram = RecOram::with_scheme(size, scheme);
+    oram.bulk_build((0..size as u64).map(Some).collect());
+    oram
+}
+
+// prints the bytes of pages moved per access, which the latency measured by criterion does not show
+fn print_bandwidth(scheme: OramScheme, size: usize, num_accesses: usize) {
+    let mut oram = build_oram(scheme, size);
+    let before = oram.bandwidth();
+    for _ in 0..num_accesses {
+        oram.read(rand::random::<usize>() % size);
+    }
+    let after = oram.bandwidth();
+    println!(
+        "{:?}: {:.1} KiB online and {:.1} KiB of eviction per access",
+        scheme,
+        (after.online_bytes - before.online_bytes) as f64 / 1024.0 / num_accesses as f64,
+        (after.evict_bytes - before.evict_bytes) as f64 / 1024.0 / num_accesses as f64
+    );
+}
+
+fn benchmark_oram_read_1m(c: &mut Criterion) {
+    const ORAM_SIZE: usize = 1 << 20;
+    let mut group = c.benchmark_group("oram_read_1m");
+    for scheme in SCHEMES {
+        print_bandwidth(scheme, ORAM_SIZE, 10_000);
+        let mut oram = build_oram(scheme, ORAM_SIZE);
+        group.bench_function(format!("{:?}", scheme), |b| {
+            b.iter(|| black_box(oram.read(rand::random::<usize>() % ORAM_SIZE)))
+        });
+    }
+    group.finish();
+}
+
+fn benchmark_oram_write_1m(c: &mut Criterion) {
+    const ORAM_SIZE: usize = 1 << 20;
+    let mut group = c.benchmark_group("oram_write_1m");
+    for scheme in SCHEMES {
+        let mut oram = build_oram(scheme, ORAM_SIZE);
+        group.bench_function(format!("{:?}", scheme), |b| {
+            b.iter(|| {
+                let uid = rand::random::<usize>() % ORAM_SIZE;
+                oram.write(black_box(uid), rand::random());
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_oram_read_1m, benchmark_oram_write_1m);
+criterion_main!(benches);
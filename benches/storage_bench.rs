@@ -0,0 +1,71 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ordb::{BlockStorage, CipherKind, MemStore, MmapStore, ORAMTree, PageFile};
+
+const PAGE_SIZE: usize = 2048;
+const NUM_PAGES: usize = 1 << 16;
+
+fn open_store<S: BlockStorage>(name: &str) -> S {
+    let path = format!("storage_bench_{}_{}.dat", name, rand::random::<u64>());
+    let store = S::open(path, NUM_PAGES).unwrap();
+    let page = [1u8; PAGE_SIZE];
+    for i in 0..NUM_PAGES {
+        store.write(i, &page).unwrap();
+    }
+    store
+}
+
+fn bench_store<S: BlockStorage>(c: &mut Criterion, name: &str) {
+    let store = open_store::<S>(name);
+    let mut group = c.benchmark_group(format!("storage_{}", name));
+    let mut buf = [0u8; PAGE_SIZE];
+    group.bench_function("read_page", |b| {
+        b.iter(|| {
+            store
+                .read(rand::random::<usize>() % NUM_PAGES, &mut buf)
+                .unwrap();
+            black_box(buf[0])
+        })
+    });
+    let page = [2u8; PAGE_SIZE];
+    group.bench_function("write_page", |b| {
+        b.iter(|| {
+            store
+                .write(rand::random::<usize>() % NUM_PAGES, black_box(&page))
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn benchmark_storage(c: &mut Criterion) {
+    bench_store::<MemStore>(c, "mem_store");
+    bench_store::<PageFile>(c, "page_file");
+    bench_store::<MmapStore>(c, "mmap_store");
+}
+
+// read and write back a path of a tree in memory, so that the time is dominated by the cipher
+fn benchmark_cipher(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cipher_path");
+    for (name, cipher) in [
+        ("plain", CipherKind::Plain),
+        ("aes_256_gcm", CipherKind::Aes256Gcm),
+        ("aes_256_gcm_siv", CipherKind::Aes256GcmSiv),
+        ("chacha20_poly1305", CipherKind::ChaCha20Poly1305),
+    ] {
+        let mut tree = ORAMTree::<[u8; 1024], MemStore>::with_cipher(1 << 10, cipher);
+        while tree.total_size() < NUM_PAGES {
+            tree.scale(16);
+        }
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let index = rand::random::<usize>();
+                let (path, _) = tree.read_path(index);
+                tree.write_path(index, black_box(&path));
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_storage, benchmark_cipher);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+/*
+ * Serve the pages of `RemoteStore` clients, e.g., an enclave running the database, from the untrusted host.
+ *
+ * Usage: block_server <tcp:host:port | unix:path> [directory]
+ *
+ * The pages of each store are kept in a memory-mapped file in the directory, the working directory by default.
+ */
+use ordb::{BlockServer, Listener, MmapStore, ServerAddr};
+use std::process;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("usage: {} <tcp:host:port | unix:path> [directory]", args[0]);
+        process::exit(2);
+    }
+    let addr: ServerAddr = args[1].parse().unwrap();
+    let dir = args.get(2).map_or(".", |dir| dir.as_str());
+    let listener = Listener::bind(&addr).unwrap_or_else(|e| {
+        eprintln!("cannot listen on {}: {}", addr, e);
+        process::exit(1);
+    });
+    println!(
+        "Serving pages in {} on {}",
+        dir,
+        listener.local_addr().unwrap()
+    );
+    if let Err(e) = BlockServer::<MmapStore>::new(dir).serve(listener) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DBError {
+    // the entry found for the key was stored under a different key whose hash collides
+    KeyMismatch,
+    // a typed key or value could not be serialized or deserialized
+    Encoding(String),
+    // the stored value does not have the format expected by the operation
+    InvalidValue(String),
+    // a write would exceed the trusted memory budget even after moving everything possible out of trusted memory
+    MemoryBudgetExceeded { usage: usize, budget: usize },
+    // the stash of the path accessed does not fit the fixed capacity of the oblivious eviction
+    StashOverflow,
+}
+
+impl fmt::Display for DBError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DBError::KeyMismatch => write!(f, "the stored key does not match the requested key"),
+            DBError::Encoding(msg) => write!(f, "encoding error: {}", msg),
+            DBError::InvalidValue(msg) => write!(f, "invalid value: {}", msg),
+            DBError::MemoryBudgetExceeded { usage, budget } => write!(
+                f,
+                "trusted memory usage of {} bytes exceeds the budget of {} bytes",
+                usage, budget
+            ),
+            DBError::StashOverflow => {
+                write!(
+                    f,
+                    "the stash of a path exceeds the capacity of the oblivious eviction"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DBError {}
+
+impl From<bincode::Error> for DBError {
+    fn from(err: bincode::Error) -> Self {
+        DBError::Encoding(err.to_string())
+    }
+}
@@ -1,35 +1,308 @@
+mod error;
 mod oblivious;
 mod params;
 mod storage;
+mod table;
 mod tree;
+mod typed;
 mod utils;
 
+pub use error::DBError;
+pub use oblivious::evict::{BandwidthStats, EvictionMode, OramScheme};
 use oblivious::flexomap::FlexOmap;
+pub use oblivious::flexomap::KeyStorage;
+pub use oblivious::linearoram::LinearOram;
+pub use oblivious::memory::MemoryUsage;
+pub use oblivious::oram::Oram;
+pub use oblivious::recoram::RecOram;
 use std::sync::Mutex;
-pub struct ObliviousDB {
-    flexomap: Mutex<FlexOmap>,
+pub use storage::memstore::MemStore;
+pub use storage::mmapstore::MmapStore;
+pub use storage::pagefile::PageFile;
+pub use storage::remotestore::{BlockServer, Listener, RemoteStore, ServerAddr};
+pub use storage::storage::BlockStorage;
+#[cfg(target_os = "linux")]
+pub use storage::uringstore::UringStore;
+pub use table::{Endian, Table};
+use table::{Tables, DEFAULT_TABLE};
+pub use tree::cipher::{CipherKind, PageCipher, StorageKey};
+pub use tree::dynamictree::ORAMTree;
+pub use typed::TypedDB;
+pub use utils::utils::{RandGen, SimpleVal};
+
+/**
+ * Options of a database instance.
+ */
+#[derive(Clone, Debug)]
+pub struct DBConfig {
+    pub key_storage: KeyStorage,
+    // tables of the position map up to this many bytes are accessed by a linear scan instead of a tree oram
+    pub linear_scan_threshold: usize,
+    // how the orams write back the paths they access; `Oblivious` also hides the eviction from side channels
+    // inside the enclave, at the cost of sorting networks over every path, and gives the stash of every path of the
+    // values a fixed capacity: an access whose path overflows it fails with `DBError::StashOverflow`
+    pub eviction: EvictionMode,
+    // tree oram scheme of the position map once it outgrows the linear scan: `Circuit` evicts two paths in
+    // reverse-lexicographic order after every access instead of the path read, which keeps the stashes small
+    pub scheme: OramScheme,
+    // if set, the orams also evict one path in reverse-lexicographic order every this many accesses, which drains
+    // the stash of every path at a fixed rate
+    pub background_eviction: Option<usize>,
+    // if set, bytes of trusted memory the database may use: when the usage approaches it, the position maps are
+    // recursed deeper and the trees stop growing their top layers, which bounds the stashes, and writes fail with
+    // `DBError::MemoryBudgetExceeded` while it is still exceeded
+    pub memory_budget: Option<usize>,
+    // if set, all the randomness of the database is drawn from a deterministic stream seeded with it, which makes
+    // runs reproducible for tests and fuzzing; it makes the positions predictable, so it must not be set otherwise
+    pub seed: Option<u64>,
+}
+
+impl Default for DBConfig {
+    fn default() -> Self {
+        Self {
+            key_storage: KeyStorage::HashOnly,
+            linear_scan_threshold: params::LINEAR_SCAN_THRESHOLD,
+            eviction: EvictionMode::Greedy,
+            scheme: OramScheme::Path,
+            background_eviction: None,
+            memory_budget: None,
+            seed: None,
+        }
+    }
+}
+
+/**
+ * Oblivious key-value store, whose tree pages are kept in storages of type `S`.
+ */
+pub struct ObliviousDB<S: BlockStorage = MemStore> {
+    tables: Mutex<Tables<S>>,
 }
 
 impl ObliviousDB {
     pub fn new() -> Self {
+        Self::with_config(DBConfig::default())
+    }
+
+    pub fn with_config(config: DBConfig) -> Self {
+        Self::with_storage(config)
+    }
+}
+
+impl<S: BlockStorage> ObliviousDB<S> {
+    /**
+     * Same as `with_config`, with the pages of the trees kept in storages of type `S`, e.g., `MmapStore` to keep them
+     * in files, or `RemoteStore` to keep them on a `BlockServer` set with `RemoteStore::set_server`.
+     */
+    pub fn with_storage(config: DBConfig) -> Self {
         Self {
-            flexomap: Mutex::new(FlexOmap::new()),
+            tables: Mutex::new(Tables::new(FlexOmap::with_options(
+                config.key_storage,
+                config.linear_scan_threshold,
+                config.eviction,
+                config.scheme,
+                config.background_eviction,
+                config.memory_budget,
+                config.seed.map_or_else(RandGen::new, RandGen::from_seed),
+            ))),
         }
     }
 
     pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.flexomap.lock().unwrap().get(key)
+        self.try_get(key).expect("key mismatch")
+    }
+
+    /**
+     * Same as `get`, but returns an error instead of panicking if the entry found belongs to another key whose
+     * hash collides. The check requires `KeyStorage::Fingerprint` or `KeyStorage::Full`.
+     */
+    pub fn try_get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        self.tables.lock().unwrap().get(DEFAULT_TABLE, key)
+    }
+
+    /**
+     * Check whether the key exists. The access pattern is the same as `get`, but the value is not copied out.
+     */
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        let mut tables = self.tables.lock().unwrap();
+        let ret = tables.get_with(DEFAULT_TABLE, key, |_| ());
+        ret.expect("key mismatch").is_some()
+    }
+
+    /**
+     * Return the size of the value in bytes with the same access pattern as `get`.
+     */
+    pub fn get_len(&self, key: &[u8]) -> Option<usize> {
+        let mut tables = self.tables.lock().unwrap();
+        let ret = tables.get_with(DEFAULT_TABLE, key, |value| value.len());
+        ret.expect("key mismatch")
+    }
+
+    /**
+     * Number of entries in the default table.
+     */
+    pub fn len(&self) -> usize {
+        self.tables.lock().unwrap().size(DEFAULT_TABLE)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     pub fn insert<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) {
-        self.flexomap.lock().unwrap().insert(key, value);
+        self.try_insert(key, value).expect("key mismatch");
+    }
+
+    /**
+     * Insert and return the previous value. On a key mismatch (see `try_get`) the stored entry is left untouched.
+     */
+    pub fn try_insert<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        key: K,
+        value: V,
+    ) -> Result<Option<Vec<u8>>, DBError> {
+        let mut tables = self.tables.lock().unwrap();
+        tables.insert(DEFAULT_TABLE, key.as_ref(), value.as_ref())
+    }
+
+    /**
+     * Load the initial state of an empty database into the default table in one pass. The page access pattern only
+     * depends on the number and the total size of the items, use `bulk_load_padded` to only reveal the number. If a
+     * key appears multiple times, the last value is kept.
+     */
+    pub fn bulk_load<K: AsRef<[u8]>, V: AsRef<[u8]>, I: IntoIterator<Item = (K, V)>>(
+        &self,
+        items: I,
+    ) -> Result<(), DBError> {
+        self.tables
+            .lock()
+            .unwrap()
+            .bulk_load(DEFAULT_TABLE, items, None)
+    }
+
+    /**
+     * Same as `bulk_load`, with the trees sized as if every key and value together had `max_item_len` bytes, so that
+     * the page access pattern only depends on the number of items. Fails with `DBError::InvalidValue` if an item is
+     * longer or if the database is not empty, before anything is loaded.
+     */
+    pub fn bulk_load_padded<K: AsRef<[u8]>, V: AsRef<[u8]>, I: IntoIterator<Item = (K, V)>>(
+        &self,
+        items: I,
+        max_item_len: usize,
+    ) -> Result<(), DBError> {
+        self.tables
+            .lock()
+            .unwrap()
+            .bulk_load(DEFAULT_TABLE, items, Some(max_item_len))
     }
 
     pub fn remove(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.flexomap.lock().unwrap().remove(key)
+        self.try_remove(key).expect("key mismatch")
+    }
+
+    /**
+     * Remove and return the value. On a key mismatch (see `try_get`) the stored entry is left untouched.
+     */
+    pub fn try_remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        self.tables.lock().unwrap().remove(DEFAULT_TABLE, key)
+    }
+
+    /**
+     * Read-modify-write the value of a key under one lock with a single access of the oram. The function receives
+     * the current value and returns the new one, or `None` to delete the key. Returns the previous value.
+     */
+    pub fn update<F>(&self, key: &[u8], update_func: F) -> Result<Option<Vec<u8>>, DBError>
+    where
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        let mut tables = self.tables.lock().unwrap();
+        tables.update(DEFAULT_TABLE, key, update_func)
+    }
+
+    /**
+     * Set the value to `new` if the current value equals `expected`, where `None` stands for a missing key.
+     * Returns whether the value is swapped.
+     */
+    pub fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool, DBError> {
+        let mut tables = self.tables.lock().unwrap();
+        tables.compare_and_swap(DEFAULT_TABLE, key, expected, new)
+    }
+
+    /**
+     * Add `delta` to an 8-byte integer value with wrapping, treating a missing key as 0. Returns the previous value.
+     */
+    pub fn fetch_add(&self, key: &[u8], delta: u64, endian: Endian) -> Result<u64, DBError> {
+        let mut tables = self.tables.lock().unwrap();
+        tables.fetch_add(DEFAULT_TABLE, key, delta, endian)
+    }
+
+    /**
+     * Append bytes to the value, creating the key if missing.
+     */
+    pub fn append(&self, key: &[u8], suffix: &[u8]) -> Result<(), DBError> {
+        self.tables
+            .lock()
+            .unwrap()
+            .append(DEFAULT_TABLE, key, suffix)
+    }
+
+    /**
+     * Open a named table. Tables share the same oblivious map, so an access does not reveal the table accessed.
+     * The methods of `ObliviousDB` itself operate on the default table, whose name is reserved: panics if `name` is
+     * empty.
+     */
+    pub fn table(&self, name: &str) -> Table<'_, S> {
+        Table::new(self, name)
+    }
+
+    /**
+     * Visit every key-value pair of the default table. The access pattern is a fixed linear sweep over all the pages, independent of
+     * the content. Requires the database to be created with `KeyStorage::Full`.
+     */
+    pub fn for_each<F: FnMut(&[u8], &[u8])>(&self, f: F) {
+        self.tables.lock().unwrap().for_each(DEFAULT_TABLE, f);
+    }
+
+    /**
+     * Collect all the key-value pairs with a single sweep, see `for_each`.
+     */
+    pub fn iter(&self) -> std::vec::IntoIter<(Vec<u8>, Vec<u8>)> {
+        let mut entries = Vec::new();
+        self.for_each(|key, value| entries.push((key.to_vec(), value.to_vec())));
+        entries.into_iter()
+    }
+
+    /**
+     * Trusted memory currently used by each component of the database.
+     */
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.tables.lock().unwrap().memory_usage()
+    }
+
+    /**
+     * Rotate the storage key without blocking: every page is written under `new_key` from now on, and the pages
+     * still under the previous key are re-encrypted a few at a time on every access, in a fixed order that does not
+     * depend on the keys accessed. Until then both keys are kept; a key rotated to while a rotation is still in
+     * progress is queued, and takes over the same way once that rotation ends. The database starts with a built-in
+     * key, so a deployment rotates to its own key right away.
+     */
+    pub fn rotate_key(&self, new_key: &StorageKey) {
+        self.tables.lock().unwrap().rotate_key(new_key);
+    }
+
+    /**
+     * Re-encrypt at once the pages left under the previous key, e.g., before the previous key is destroyed. Returns
+     * the number of pages visited, 0 if the accesses have already completed the rotation.
+     */
+    pub fn finish_key_rotation(&self) -> usize {
+        self.tables.lock().unwrap().finish_key_rotation()
     }
 
     pub fn print_meta_state(&self) {
-        self.flexomap.lock().unwrap().print_meta_state();
+        self.tables.lock().unwrap().print_meta_state();
     }
 }
@@ -0,0 +1,264 @@
+use super::evict::{EvictionMode, OramScheme};
+use super::linearoram::LinearOram;
+use super::memory::MemoryUsage;
+use super::oram::Oram;
+use super::recoram::RecOram;
+use crate::params::{LINEAR_SCAN_THRESHOLD, POS_MAP_MEMORY_BUDGET};
+use crate::storage::memstore::MemStore;
+use crate::storage::storage::BlockStorage;
+use crate::tree::cipher::StorageKey;
+use crate::utils::utils::{RandGen, SimpleVal};
+
+enum Backend<T: SimpleVal, const N: usize, S: BlockStorage> {
+    Linear(LinearOram<T, N>),
+    Rec(Box<RecOram<T, N, S>>),
+}
+
+/**
+ * Oram that uses a linear scan while the table takes at most `threshold` bytes, and switches to the recursive oram
+ * once a doubling grows it past the threshold. The switch only depends on the size, which is public. The linear scan
+ * is kept in enclave memory, and the trees of the recursive oram in storages of type `S`.
+ */
+pub struct AutoOram<T: SimpleVal, const N: usize, S: BlockStorage = MemStore> {
+    backend: Backend<T, N, S>,
+    threshold: usize,
+    eviction: EvictionMode,
+    scheme: OramScheme,
+    background_eviction: Option<usize>,
+    memory_budget: usize, // bytes of positions kept in enclave memory by the recursive oram
+    key: StorageKey,      // the key of the recursive oram once used
+    rand_gen: RandGen,
+}
+
+impl<T: SimpleVal, const N: usize, S: BlockStorage> AutoOram<T, N, S> {
+    pub fn with_threshold(size: usize, threshold: usize) -> Self {
+        Self::with_options(
+            size,
+            threshold,
+            EvictionMode::Greedy,
+            OramScheme::Path,
+            None,
+            RandGen::new(),
+        )
+    }
+
+    /**
+     * The recursive oram, once used, evicts its tree orams of the given scheme with the given mode, and in the
+     * background every `background_eviction` accesses if set. Its randomness is drawn from a source forked from `rand_gen`.
+     */
+    pub fn with_options(
+        size: usize,
+        threshold: usize,
+        eviction: EvictionMode,
+        scheme: OramScheme,
+        background_eviction: Option<usize>,
+        mut rand_gen: RandGen,
+    ) -> Self {
+        let backend = if Self::fits_linear(size, threshold) {
+            Backend::Linear(LinearOram::new(size))
+        } else {
+            Backend::Rec(Box::new(Self::new_rec_oram(
+                size,
+                threshold,
+                POS_MAP_MEMORY_BUDGET,
+                eviction,
+                scheme,
+                background_eviction,
+                rand_gen.fork(),
+            )))
+        };
+        Self {
+            backend,
+            threshold,
+            eviction,
+            scheme,
+            background_eviction,
+            memory_budget: POS_MAP_MEMORY_BUDGET,
+            key: StorageKey::default(),
+            rand_gen,
+        }
+    }
+
+    fn new_rec_oram(
+        size: usize,
+        threshold: usize,
+        memory_budget: usize,
+        eviction: EvictionMode,
+        scheme: OramScheme,
+        background_eviction: Option<usize>,
+        rand_gen: RandGen,
+    ) -> RecOram<T, N, S> {
+        RecOram::with_options(
+            size,
+            threshold,
+            memory_budget,
+            eviction,
+            scheme,
+            background_eviction,
+            rand_gen,
+        )
+    }
+
+    fn fits_linear(size: usize, threshold: usize) -> bool {
+        size * std::mem::size_of::<T>() <= threshold
+    }
+
+    pub fn is_linear(&self) -> bool {
+        matches!(self.backend, Backend::Linear(_))
+    }
+}
+
+impl<T: SimpleVal, const N: usize, S: BlockStorage> Oram<T> for AutoOram<T, N, S> {
+    fn new(size: usize) -> Self {
+        Self::with_threshold(size, LINEAR_SCAN_THRESHOLD)
+    }
+
+    fn with_rand_gen(size: usize, rand_gen: RandGen) -> Self {
+        Self::with_options(
+            size,
+            LINEAR_SCAN_THRESHOLD,
+            EvictionMode::Greedy,
+            OramScheme::Path,
+            None,
+            rand_gen,
+        )
+    }
+
+    fn update<F>(&mut self, uid: usize, update_func: F)
+    where
+        F: FnOnce(Option<T>) -> Option<T>,
+    {
+        match &mut self.backend {
+            Backend::Linear(oram) => oram.update(uid, update_func),
+            Backend::Rec(oram) => oram.update(uid, update_func),
+        }
+    }
+
+    fn bulk_build(&mut self, vals: Vec<Option<T>>) {
+        match &mut self.backend {
+            Backend::Linear(oram) => oram.bulk_build(vals),
+            Backend::Rec(oram) => oram.bulk_build(vals),
+        }
+    }
+
+    fn double_size_and_fork_self(&mut self) {
+        match &mut self.backend {
+            Backend::Linear(oram) => oram.double_size_and_fork_self(),
+            Backend::Rec(oram) => oram.double_size_and_fork_self(),
+        }
+        let size = self.size();
+        if self.is_linear() && !Self::fits_linear(size, self.threshold) {
+            let mut rec_oram = Self::new_rec_oram(
+                size,
+                self.threshold,
+                self.memory_budget,
+                self.eviction,
+                self.scheme,
+                self.background_eviction,
+                self.rand_gen.fork(),
+            );
+            rec_oram.rotate_key(&self.key);
+            let old_backend =
+                std::mem::replace(&mut self.backend, Backend::Linear(LinearOram::new(0)));
+            if let Backend::Linear(linear_oram) = old_backend {
+                rec_oram.bulk_build(linear_oram.into_vals());
+            }
+            self.backend = Backend::Rec(Box::new(rec_oram));
+        }
+    }
+
+    fn size(&self) -> usize {
+        match &self.backend {
+            Backend::Linear(oram) => oram.size(),
+            Backend::Rec(oram) => oram.size(),
+        }
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        match &self.backend {
+            Backend::Linear(oram) => oram.memory_usage(),
+            Backend::Rec(oram) => oram.memory_usage(),
+        }
+    }
+
+    // the linear scan holds nothing to shrink, and the recursive oram created once it is outgrown starts with the
+    // configured budget, which it shrinks on its own if still over budget
+    fn shrink_memory(&mut self) -> bool {
+        match &mut self.backend {
+            Backend::Linear(_) => false,
+            Backend::Rec(oram) => oram.shrink_memory(),
+        }
+    }
+
+    fn rotate_key(&mut self, key: &StorageKey) {
+        self.key = key.clone();
+        if let Backend::Rec(oram) = &mut self.backend {
+            oram.rotate_key(key);
+        }
+    }
+
+    fn sweep_keys(&mut self, max_pages: usize) -> usize {
+        match &mut self.backend {
+            Backend::Linear(_) => 0,
+            Backend::Rec(oram) => oram.sweep_keys(max_pages),
+        }
+    }
+
+    fn print_meta_state(&self) {
+        println!("AutoOram meta state:");
+        println!("threshold: {} bytes", self.threshold);
+        match &self.backend {
+            Backend::Linear(oram) => oram.print_meta_state(),
+            Backend::Rec(oram) => oram.print_meta_state(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_oram_switch() {
+        let mut oram = AutoOram::<u32, 4>::with_threshold(64, 512);
+        assert!(oram.is_linear());
+        for i in 0..64 {
+            oram.write(i, i as u32 + 1);
+        }
+        oram.double_size_and_fork_self();
+        assert!(oram.is_linear());
+        oram.write(100, 7);
+        oram.double_size_and_fork_self();
+        assert!(!oram.is_linear());
+        assert_eq!(oram.size(), 256);
+        for i in 0..256 {
+            let expected = if i % 128 == 100 {
+                7
+            } else {
+                (i % 64) as u32 + 1
+            };
+            assert_eq!(oram.read(i), Some(expected));
+        }
+        oram.write(200, 9);
+        assert_eq!(oram.read(200), Some(9));
+    }
+
+    #[test]
+    fn test_auto_oram_shrink_linear() {
+        let mut oram = AutoOram::<u32, 4>::with_threshold(64, 512);
+        for _ in 0..100 {
+            assert!(!oram.shrink_memory());
+        }
+        assert_eq!(oram.memory_budget, POS_MAP_MEMORY_BUDGET);
+        oram.double_size_and_fork_self();
+        oram.double_size_and_fork_self();
+        assert!(!oram.is_linear());
+        assert!(oram.shrink_memory());
+    }
+
+    #[test]
+    fn test_auto_oram_large() {
+        let oram = AutoOram::<u32, 4>::with_threshold(1024, 512);
+        assert!(!oram.is_linear());
+    }
+}
@@ -1,12 +1,19 @@
-// use crate::linearoram::LinearOram;
-use super::recoram::RecOram;
-use crate::utils::utils::SimpleVal;
+use super::autooram::AutoOram;
+use super::evict::{EvictionMode, OramScheme};
+use super::memory::MemoryUsage;
+use super::oram::Oram;
+use crate::params::KEY_SIZE;
+use crate::storage::storage::BlockStorage;
+use crate::tree::cipher::StorageKey;
+use crate::utils::ct::{cmov, cswap, ct_eq, ct_eq_pod};
+use crate::utils::utils::{RandGen, SimpleVal};
 use bytemuck::{Pod, Zeroable};
-use rand;
 use rayon::prelude::*;
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+
+pub const FINGERPRINT_SIZE: usize = 16;
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct HashEntry<V: SimpleVal> {
     idx: [usize; 2],
@@ -22,30 +29,15 @@ impl<V: SimpleVal> HashEntry<V> {
     }
 
     pub fn is_match(&self, idx: [usize; 2]) -> bool {
-        for i in 0..2 {
-            if self.idx[i] != idx[i] {
-                return false;
-            }
-        }
-        true
+        ct_eq(self.idx[0], idx[0]) & ct_eq(self.idx[1], idx[1])
     }
 
     pub fn is_empty(&self) -> bool {
         self.val == V::zeroed()
     }
 
-    pub fn remove(&mut self) {
-        self.idx = [0 as usize, 0 as usize];
-        self.val = V::zeroed();
-    }
-
     pub fn eq(&self, other: &Self) -> bool {
-        for i in 0..2 {
-            if self.idx[i] != other.idx[i] {
-                return false;
-            }
-        }
-        self.val == other.val
+        self.is_match(other.idx) & ct_eq_pod(&self.val, &other.val)
     }
 
     pub fn get_val(&self) -> V {
@@ -56,13 +48,17 @@ impl<V: SimpleVal> HashEntry<V> {
         self.val = val;
     }
 
+    pub fn get_idx(&self) -> [usize; 2] {
+        self.idx
+    }
+
     pub fn set_idx(&mut self, idx: [usize; 2]) {
         self.idx = idx;
     }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
-struct HashBkt<V: SimpleVal, const BKT_SIZE: usize> {
+pub struct HashBkt<V: SimpleVal, const BKT_SIZE: usize> {
     entries: [HashEntry<V>; BKT_SIZE],
 }
 unsafe impl<V: SimpleVal, const BKT_SIZE: usize> Zeroable for HashBkt<V, BKT_SIZE> {}
@@ -79,31 +75,102 @@ impl<V: SimpleVal, const BKT_SIZE: usize> HashBkt<V, BKT_SIZE> {
     }
 }
 
-pub struct CuckooHashMap<V: SimpleVal, const BKT_SIZE: usize, const BKT_PER_PAGE: usize> {
-    tables: [RecOram<HashBkt<V, BKT_SIZE>, BKT_PER_PAGE>; 2],
+/**
+ * Two-table cuckoo hash map whose tables are stored in an oram of type `O`. By default small tables are scanned
+ * linearly and large ones use the recursive oram.
+ */
+pub struct CuckooHashMap<
+    V: SimpleVal,
+    const BKT_SIZE: usize,
+    const BKT_PER_PAGE: usize,
+    O: Oram<HashBkt<V, BKT_SIZE>> = AutoOram<HashBkt<V, BKT_SIZE>, BKT_PER_PAGE>,
+> {
+    tables: [O; 2],
     size: usize,
     full_bkt_stash: HashMap<[usize; 2], V>,
-    salt: [u8; 32],
+    salt: StorageKey, // the key of the hash, secret since it maps the keys to their buckets
+    rand_gen: RandGen,
 }
 
 impl<V: SimpleVal, const BKT_SIZE: usize, const BKT_PER_PAGE: usize>
     CuckooHashMap<V, BKT_SIZE, BKT_PER_PAGE>
 {
     pub fn new() -> Self {
+        Self::with_backend(RandGen::new())
+    }
+
+    /**
+     * Tables of at most `linear_scan_threshold` bytes are accessed by a linear scan.
+     */
+    pub fn with_linear_scan_threshold(linear_scan_threshold: usize) -> Self {
+        Self::with_options(
+            linear_scan_threshold,
+            EvictionMode::Greedy,
+            OramScheme::Path,
+            None,
+            RandGen::new(),
+        )
+    }
+}
+
+impl<V: SimpleVal, const BKT_SIZE: usize, const BKT_PER_PAGE: usize, S: BlockStorage>
+    CuckooHashMap<V, BKT_SIZE, BKT_PER_PAGE, AutoOram<HashBkt<V, BKT_SIZE>, BKT_PER_PAGE, S>>
+{
+    /**
+     * Same as `with_linear_scan_threshold`, and tables past the threshold use tree orams of the given scheme, kept
+     * in storages of type `S`, that evict with the given mode, and in the background every `background_eviction`
+     * accesses if set.
+     */
+    pub fn with_options(
+        linear_scan_threshold: usize,
+        eviction: EvictionMode,
+        scheme: OramScheme,
+        background_eviction: Option<usize>,
+        mut rand_gen: RandGen,
+    ) -> Self {
+        let tables = [(); 2].map(|_| {
+            AutoOram::with_options(
+                128,
+                linear_scan_threshold,
+                eviction,
+                scheme,
+                background_eviction,
+                rand_gen.fork(),
+            )
+        });
+        Self::with_tables(tables, rand_gen)
+    }
+}
+
+impl<V: SimpleVal, const BKT_SIZE: usize, const BKT_PER_PAGE: usize, O>
+    CuckooHashMap<V, BKT_SIZE, BKT_PER_PAGE, O>
+where
+    O: Oram<HashBkt<V, BKT_SIZE>>,
+{
+    /**
+     * The salt, the evictions, and the tables that draw randomness are seeded from `rand_gen`.
+     */
+    pub fn with_backend(mut rand_gen: RandGen) -> Self {
+        let tables = [(); 2].map(|_| O::with_rand_gen(128, rand_gen.fork()));
+        Self::with_tables(tables, rand_gen)
+    }
+
+    fn with_tables(tables: [O; 2], mut rand_gen: RandGen) -> Self {
         Self {
-            tables: [
-                RecOram::<HashBkt<V, BKT_SIZE>, BKT_PER_PAGE>::new(128),
-                RecOram::<HashBkt<V, BKT_SIZE>, BKT_PER_PAGE>::new(128),
-            ],
+            tables,
             size: 0,
             full_bkt_stash: HashMap::new(),
-            salt: rand::random::<[u8; 32]>(), // change to secure random
+            salt: StorageKey::from(rand_gen.gen::<[u8; KEY_SIZE]>()),
+            rand_gen,
         }
     }
 
-    fn hash_key<K: AsRef<[u8]>>(&self, key: K) -> [u8; 32] {
+    // salted hash of the key, prefixed with a domain byte so that the bucket indices and the fingerprint come from
+    // independent hashes
+    fn hash_key_in<K: AsRef<[u8]>>(&self, domain: u8, key: K) -> [u8; 32] {
         let mut hasher = Sha256::new();
-        hasher.update(&self.salt);
+        hasher.update(self.salt.as_bytes());
+        hasher.update([domain]);
         hasher.update(key);
         let result = hasher.finalize();
         let mut key_hash = [0 as u8; 32];
@@ -111,6 +178,19 @@ impl<V: SimpleVal, const BKT_SIZE: usize, const BKT_PER_PAGE: usize>
         key_hash
     }
 
+    fn hash_key<K: AsRef<[u8]>>(&self, key: K) -> [u8; 32] {
+        self.hash_key_in(0, key)
+    }
+
+    /**
+     * A second salted hash of the key, separated from the one of the bucket indices, to tell apart keys whose
+     * indices collide.
+     */
+    pub fn key_fingerprint<K: AsRef<[u8]>>(&self, key: K) -> [u8; FINGERPRINT_SIZE] {
+        let key_hash = self.hash_key_in(1, key);
+        key_hash[..FINGERPRINT_SIZE].try_into().unwrap()
+    }
+
     fn get_bkt_idx(key_hash: [u8; 32]) -> [usize; 2] {
         let mut bkt_idx: [usize; 2] = [0 as usize, 0 as usize];
         for i in 0..2 {
@@ -123,6 +203,11 @@ impl<V: SimpleVal, const BKT_SIZE: usize, const BKT_PER_PAGE: usize>
         bkt_idx
     }
 
+    /**
+     * Insert the entry, or overwrite the value if the key is already present. Every call does the same number of
+     * bucket accesses and every slot is handled with `ct_eq`/`cmov`, so neither the position of the key nor the
+     * length of the eviction chain is revealed. Entries still homeless after `MAX_ITER` rounds go to the stash.
+     */
     pub fn insert_hash_entry(&mut self, hash_entry: &HashEntry<V>) -> Option<V> {
         let mut entry = hash_entry.clone();
         // get hash of key
@@ -130,64 +215,109 @@ impl<V: SimpleVal, const BKT_SIZE: usize, const BKT_PER_PAGE: usize>
             self.double_size();
         }
 
-        const MAX_ITER: usize = 10;
+        const MAX_ITER: usize = 4;
         let table_capacity = self.tables[0].size();
         assert!(table_capacity == self.tables[1].size());
         let old_stash_entry = self.full_bkt_stash.remove(&entry.idx);
         self.size -= old_stash_entry.is_some() as usize;
-        let mut ret = None;
+        let mut found_flag = false;
+        let mut old_val = V::zeroed();
         let mut inserted_flag = false;
-        let mut need_evict_flag = false;
+        let empty_entry = HashEntry::new();
 
         for iter in 0..MAX_ITER {
             for i in 0..2 {
+                // once inserted, the entry is kept and the remaining accesses leave the buckets unchanged
                 let bkt_idx = entry.idx[i] % table_capacity;
+                // nothing is evicted by the very first access, and the key is only looked up in the first round
+                // before the eviction of each access, so the entry matched is always the one inserted
+                let need_evict_flag = iter + i != 0;
+                let evict_idx = self.rand_gen.gen::<usize>() % BKT_SIZE;
                 let update_func = |bkt: Option<HashBkt<V, BKT_SIZE>>| {
-                    // println!("Read bkt at table {} index {}", i, bkt_idx);
-                    // println!("bkt: {:?}", bkt);
                     let mut bkt = bkt.unwrap_or_else(|| HashBkt::new());
                     for j in 0..BKT_SIZE {
-                        if iter == 0 && bkt.entries[j].is_match(entry.idx) {
-                            // overwrite the entry
-                            ret = Some(bkt.entries[j].val);
-                            bkt.entries[j].remove();
-                            self.size -= 1;
-                        }
-                        if !inserted_flag && bkt.entries[j].idx[i] % table_capacity != bkt_idx {
-                            // insert the entry
-                            bkt.entries[j] = entry;
-                            self.size += 1;
-                            inserted_flag = true;
-                        }
+                        let slot = &mut bkt.entries[j];
+                        // overwrite the entry
+                        let hit = (iter == 0) & slot.is_match(entry.idx) & !found_flag;
+                        cmov(hit, &mut old_val, &slot.val);
+                        found_flag |= hit;
+                        // a removed slot of bucket 0 still looks occupied, so mark it explicitly
+                        let free_flag = !ct_eq(slot.idx[i] % table_capacity, bkt_idx) | hit;
+                        let insert_flag = free_flag & !inserted_flag;
+                        cmov(hit, &mut slot.idx, &empty_entry.idx);
+                        cmov(hit, &mut slot.val, &empty_entry.val);
+                        cmov(insert_flag, &mut slot.idx, &entry.idx);
+                        cmov(insert_flag, &mut slot.val, &entry.val);
+                        inserted_flag |= insert_flag;
+                        self.size = self.size + insert_flag as usize - hit as usize;
                     }
-                    if !inserted_flag && need_evict_flag {
-                        let evict_idx = rand::random::<usize>() % BKT_SIZE;
-                        std::mem::swap(&mut entry, &mut bkt.entries[evict_idx]);
+                    // need to evict an entry if the entry is not inserted
+                    let evict_flag = need_evict_flag & !inserted_flag;
+                    for j in 0..BKT_SIZE {
+                        let swap_flag = evict_flag & ct_eq(j, evict_idx);
+                        cswap(swap_flag, &mut entry.idx, &mut bkt.entries[j].idx);
+                        cswap(swap_flag, &mut entry.val, &mut bkt.entries[j].val);
                     }
-                    // println!("Write back bkt at table {} index {}", i, bkt_idx);
-                    // println!("bkt: {:?}", bkt);
                     Some(bkt)
                 };
                 self.tables[i].update(bkt_idx, update_func);
-                if ret.is_some() {
-                    assert!(inserted_flag);
-                    return ret;
-                }
-                if iter + i != 0 && inserted_flag {
-                    // the entry is inserted
-                    return old_stash_entry;
-                }
-                need_evict_flag = true; // need to evict an entry if the entry is not inserted
             }
         }
 
-        print!("Cuckoo hash table is full insert to stash\n");
-        // insert the entry to the bkt_full stash
-        self.size += 1;
-        self.full_bkt_stash.insert(entry.idx, entry.val);
+        if !inserted_flag {
+            print!("Cuckoo hash table is full insert to stash\n");
+            // insert the entry to the bkt_full stash
+            self.size += 1;
+            self.full_bkt_stash.insert(entry.idx, entry.val);
+        }
+        if found_flag {
+            return Some(old_val);
+        }
         old_stash_entry
     }
 
+    /**
+     * Build both tables from the given entries in one pass. The tables are pre-sized according to the number of
+     * entries, the cuckoo placement is done in memory, and every bucket is then written to the orams sequentially.
+     * Entries must have distinct indices.
+     */
+    pub fn bulk_build(&mut self, entries: Vec<HashEntry<V>>) {
+        assert_eq!(self.size, 0);
+        while self.capacity() < entries.len() {
+            self.double_size();
+        }
+        const MAX_ITER: usize = 20;
+        let table_capacity = self.tables[0].size();
+        let mut bkts = [
+            vec![HashBkt::<V, BKT_SIZE>::new(); table_capacity],
+            vec![HashBkt::<V, BKT_SIZE>::new(); table_capacity],
+        ];
+        let mut filled = [vec![0usize; table_capacity], vec![0usize; table_capacity]];
+        for hash_entry in entries.iter() {
+            let mut entry = *hash_entry;
+            let mut inserted_flag = false;
+            for iter in 0..MAX_ITER * 2 {
+                let i = iter % 2;
+                let bkt_idx = entry.idx[i] % table_capacity;
+                if filled[i][bkt_idx] < BKT_SIZE {
+                    bkts[i][bkt_idx].entries[filled[i][bkt_idx]] = entry;
+                    filled[i][bkt_idx] += 1;
+                    inserted_flag = true;
+                    break;
+                }
+                let evict_idx = self.rand_gen.gen::<usize>() % BKT_SIZE;
+                std::mem::swap(&mut entry, &mut bkts[i][bkt_idx].entries[evict_idx]);
+            }
+            if !inserted_flag {
+                self.full_bkt_stash.insert(entry.idx, entry.val);
+            }
+        }
+        self.size = entries.len();
+        for (table, table_bkts) in self.tables.iter_mut().zip(bkts) {
+            table.bulk_build(table_bkts.into_iter().map(Some).collect());
+        }
+    }
+
     pub fn compute_hash_entry<K: AsRef<[u8]>>(&self, key: K, value: V) -> HashEntry<V> {
         let key_hash = self.hash_key(key);
         let bkt_idx = Self::get_bkt_idx(key_hash);
@@ -207,81 +337,75 @@ impl<V: SimpleVal, const BKT_SIZE: usize, const BKT_PER_PAGE: usize>
         let bkt_idx = Self::get_bkt_idx(key_hash);
         let table_capacity = self.tables[0].size();
         assert!(table_capacity == self.tables[1].size());
+        let mut found_flag = false;
+        let mut val = V::zeroed();
+        // both tables are always read, and every slot is compared
         for i in 0..2 {
             let bkt = self.tables[i].read(bkt_idx[i] % table_capacity);
-            if let Some(bkt) = bkt {
-                for j in 0..BKT_SIZE {
-                    if bkt.entries[j].is_match(bkt_idx) {
-                        return Some(bkt.entries[j].val);
-                    }
-                }
+            let bkt = bkt.unwrap_or_else(|| HashBkt::new());
+            for j in 0..BKT_SIZE {
+                let hit = bkt.entries[j].is_match(bkt_idx) & !found_flag;
+                cmov(hit, &mut val, &bkt.entries[j].val);
+                found_flag |= hit;
             }
         }
+        if found_flag {
+            return Some(val);
+        }
         self.full_bkt_stash.get(&bkt_idx).cloned()
     }
 
-    pub fn update_hash_entry(&mut self, entry: &HashEntry<V>) -> Option<V> {
+    // overwrite the value of the entry if remove_flag is false, otherwise remove the entry
+    fn update_or_remove_hash_entry(
+        &mut self,
+        entry: &HashEntry<V>,
+        remove_flag: bool,
+    ) -> Option<V> {
         let bkt_idx = entry.idx;
         let table_capacity = self.tables[0].size();
         assert!(table_capacity == self.tables[1].size());
-        let mut old_val = None;
+        let mut found_flag = false;
+        let mut old_val = V::zeroed();
+        let empty_entry = HashEntry::new();
+        // both tables are always accessed, and every slot is compared, so the access does not reveal where the
+        // entry is
         for i in 0..2 {
             let update_func = |bkt: Option<HashBkt<V, BKT_SIZE>>| {
                 let mut bkt = bkt.unwrap_or_else(|| HashBkt::new());
                 for j in 0..BKT_SIZE {
-                    if bkt.entries[j].is_match(bkt_idx) {
-                        old_val = Some(bkt.entries[j].val);
-                        bkt.entries[j].val = entry.val;
-                        return Some(bkt);
-                    }
+                    let hit = bkt.entries[j].is_match(bkt_idx) & !found_flag;
+                    cmov(hit, &mut old_val, &bkt.entries[j].val);
+                    cmov(hit & !remove_flag, &mut bkt.entries[j].val, &entry.val);
+                    cmov(hit & remove_flag, &mut bkt.entries[j].idx, &empty_entry.idx);
+                    cmov(hit & remove_flag, &mut bkt.entries[j].val, &empty_entry.val);
+                    found_flag |= hit;
                 }
                 Some(bkt)
             };
             self.tables[i].update(bkt_idx[i] % table_capacity, update_func);
-            if old_val.is_some() {
-                return old_val;
-            }
         }
-        let stash_res = self.full_bkt_stash.get(&bkt_idx);
+        if found_flag {
+            self.size -= remove_flag as usize;
+            return Some(old_val);
+        }
+        let stash_res = self.full_bkt_stash.get(&bkt_idx).cloned();
         if stash_res.is_some() {
-            old_val = stash_res.cloned();
-            self.full_bkt_stash.insert(bkt_idx, entry.val);
+            if remove_flag {
+                self.full_bkt_stash.remove(&bkt_idx);
+                self.size -= 1;
+            } else {
+                self.full_bkt_stash.insert(bkt_idx, entry.val);
+            }
         }
-        old_val
+        stash_res
+    }
+
+    pub fn update_hash_entry(&mut self, entry: &HashEntry<V>) -> Option<V> {
+        self.update_or_remove_hash_entry(entry, false)
     }
 
-    // todo: avoid duplicate code
     pub fn remove_hash_entry(&mut self, entry: &HashEntry<V>) -> Option<V> {
-        let bkt_idx = entry.idx;
-        let table_capacity = self.tables[0].size();
-        assert!(table_capacity == self.tables[1].size());
-        let mut old_val = None;
-        for i in 0..2 {
-            let update_func = |bkt: Option<HashBkt<V, BKT_SIZE>>| {
-                let mut bkt = bkt.unwrap_or_else(|| HashBkt::new());
-                for j in 0..BKT_SIZE {
-                    if bkt.entries[j].is_match(bkt_idx) {
-                        old_val = Some(bkt.entries[j].val);
-                        bkt.entries[j].remove();
-                        return Some(bkt);
-                    }
-                }
-                Some(bkt)
-            };
-            self.tables[i].update(bkt_idx[i] % table_capacity, update_func);
-            if old_val.is_some() {
-                self.size -= 1;
-                return old_val;
-            }
-        }
-        let stash_res = self.full_bkt_stash.remove(&bkt_idx);
-        if stash_res.is_some() {
-            old_val = stash_res;
-        }
-        if old_val.is_some() {
-            self.size -= 1;
-        }
-        old_val
+        self.update_or_remove_hash_entry(entry, true)
     }
 
     pub fn get_parallel<K: AsRef<[u8]>>(&mut self, key: K) -> Option<V> {
@@ -289,25 +413,28 @@ impl<V: SimpleVal, const BKT_SIZE: usize, const BKT_PER_PAGE: usize>
         let bkt_idx = Self::get_bkt_idx(key_hash);
         let table_capacity = self.tables[0].size();
         assert!(table_capacity == self.tables[1].size());
-        let result = Arc::new(Mutex::new(None));
+        let result = Arc::new(Mutex::new((false, V::zeroed())));
+        // both tables are always read, and every slot is compared
         self.tables
             .par_iter_mut()
             .zip(bkt_idx)
             .for_each(|(table, idx)| {
                 let bkt = table.read(idx % table_capacity);
-                if let Some(bkt) = bkt {
-                    for j in 0..BKT_SIZE {
-                        if bkt.entries[j].is_match(bkt_idx) {
-                            let mut res = result.lock().unwrap();
-                            *res = Some(bkt.entries[j].val);
-                            break;
-                        }
-                    }
+                let bkt = bkt.unwrap_or_else(|| HashBkt::new());
+                let mut found_flag = false;
+                let mut val = V::zeroed();
+                for j in 0..BKT_SIZE {
+                    let hit = bkt.entries[j].is_match(bkt_idx) & !found_flag;
+                    cmov(hit, &mut val, &bkt.entries[j].val);
+                    found_flag |= hit;
                 }
+                let mut res = result.lock().unwrap();
+                cmov(found_flag, &mut res.1, &val);
+                res.0 |= found_flag;
             });
-        let res = result.lock().unwrap();
-        if res.is_some() {
-            return *res;
+        let (found_flag, val) = *result.lock().unwrap();
+        if found_flag {
+            return Some(val);
         }
         self.full_bkt_stash.get(&bkt_idx).cloned()
     }
@@ -326,6 +453,38 @@ impl<V: SimpleVal, const BKT_SIZE: usize, const BKT_PER_PAGE: usize>
         }
     }
 
+    /**
+     * Trusted memory used by both tables, the entries of full buckets are counted as stash.
+     */
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let full_bkt_stash = MemoryUsage {
+            stash: self.full_bkt_stash.len() * std::mem::size_of::<([usize; 2], V)>(),
+            ..Default::default()
+        };
+        self.tables[0].memory_usage() + self.tables[1].memory_usage() + full_bkt_stash
+    }
+
+    /**
+     * See `Oram::shrink_memory`, applied to both tables.
+     */
+    pub fn shrink_memory(&mut self) -> bool {
+        let shrunk = self.tables[0].shrink_memory();
+        self.tables[1].shrink_memory() || shrunk
+    }
+
+    /**
+     * See `Oram::rotate_key`, applied to both tables.
+     */
+    pub fn rotate_key(&mut self, key: &StorageKey) {
+        self.tables[0].rotate_key(key);
+        self.tables[1].rotate_key(key);
+    }
+
+    pub fn sweep_keys(&mut self, max_pages: usize) -> usize {
+        let swept = self.tables[0].sweep_keys(max_pages);
+        swept + self.tables[1].sweep_keys(max_pages - swept)
+    }
+
     pub fn print_meta_state(&self) {
         println!("CuckooHashMap meta state:");
         println!("Size: {}", self.size);
@@ -350,8 +509,10 @@ impl<V: SimpleVal, const BKT_SIZE: usize, const BKT_PER_PAGE: usize>
     // }
 }
 
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::oblivious::linearoram::LinearOram;
 
     #[test]
     fn it_works() {
@@ -375,6 +536,24 @@ mod tests {
         assert_eq!(2, map.size());
     }
 
+    #[test]
+    fn get_parallel_test() {
+        let mut map = CuckooHashMap::<u64, 4, 4>::new();
+        let size = 2000;
+        for i in 0..size {
+            assert_eq!(map.insert(i.to_string(), i + 1), None);
+        }
+        for i in 0..size / 2 {
+            assert_eq!(map.insert(i.to_string(), i + 2), Some(i + 1));
+        }
+        assert_eq!(size as usize, map.size());
+        for i in 0..size {
+            let val = if i < size / 2 { i + 2 } else { i + 1 };
+            assert_eq!(map.get_parallel(i.to_string()), Some(val));
+        }
+        assert_eq!(map.get_parallel("missing"), None);
+    }
+
     #[test]
     fn evict_test() {
         let mut map = CuckooHashMap::<u64, 8, 8>::new();
@@ -400,6 +579,89 @@ mod tests {
         assert_eq!(10000, map.size());
     }
 
+    #[test]
+    fn linear_backend_test() {
+        type LinearBkt = LinearOram<HashBkt<u64, 4>, 4>;
+        let mut map = CuckooHashMap::<u64, 4, 4, LinearBkt>::with_backend(RandGen::from_seed(7));
+        for i in 0..2000 {
+            assert_eq!(map.insert(i.to_string(), i), None);
+        }
+        assert_eq!(map.insert("7", 70), Some(7));
+        for i in 0..2000 {
+            let expected = if i == 7 { 70 } else { i };
+            assert_eq!(map.get(i.to_string()), Some(expected));
+        }
+        assert_eq!(2000, map.size());
+    }
+
+    #[test]
+    fn linear_scan_threshold_test() {
+        let bkt_bytes = std::mem::size_of::<HashBkt<u64, 4>>();
+        let mut map = CuckooHashMap::<u64, 4, 4>::with_linear_scan_threshold(256 * bkt_bytes);
+        assert!(map.tables.iter().all(|table| table.is_linear()));
+        for i in 0..1000 {
+            map.insert(i.to_string(), i);
+        }
+        assert!(map.tables.iter().all(|table| !table.is_linear()));
+        for i in 0..1000 {
+            assert_eq!(map.get(i.to_string()), Some(i));
+        }
+    }
+
+    #[test]
+    fn overwrite_bucket_zero_test() {
+        // the slot of an overwritten entry of bucket 0 must be reused, although its cleared index looks occupied
+        let mut map = CuckooHashMap::<u64, 4, 4>::new();
+        let table_capacity = map.tables[0].size();
+        let keys: Vec<String> = (0..)
+            .map(|i: u64| i.to_string())
+            .filter(|key| {
+                map.compute_hash_entry(key, 0).get_idx()[0].is_multiple_of(table_capacity)
+            })
+            .take(4)
+            .collect();
+        let entries = keys
+            .iter()
+            .zip(1..)
+            .map(|(key, val)| map.compute_hash_entry(key, val))
+            .collect();
+        map.bulk_build(entries);
+        for (key, val) in keys.iter().zip(1..) {
+            assert_eq!(map.insert(key, val + 10), Some(val));
+        }
+        assert_eq!(4, map.size());
+        let bkt = map.tables[0].read(0).unwrap();
+        for (key, val) in keys.iter().zip(11..) {
+            let idx = map.compute_hash_entry(key, 0).get_idx();
+            assert!(bkt
+                .entries
+                .iter()
+                .any(|entry| entry.is_match(idx) && entry.get_val() == val));
+            assert_eq!(map.get(key), Some(val));
+        }
+    }
+
+    #[test]
+    fn bulk_build_test() {
+        let mut map = CuckooHashMap::<u64, 4, 8>::new();
+        let size = 10000;
+        let entries = (0..size)
+            .map(|i| map.compute_hash_entry(i.to_string(), i + 1))
+            .collect();
+        map.bulk_build(entries);
+        assert_eq!(size as usize, map.size());
+        for i in 0..size {
+            assert_eq!(map.get(i.to_string()), Some(i + 1));
+        }
+        for i in size..size * 2 {
+            map.insert(i.to_string(), i + 1);
+        }
+        for i in 0..size * 2 {
+            assert_eq!(map.get(i.to_string()), Some(i + 1));
+        }
+        assert_eq!(size as usize * 2, map.size());
+    }
+
     #[test]
     fn scale_and_dup_test() {
         let mut map = CuckooHashMap::<u64, 8, 8>::new();
@@ -0,0 +1,36 @@
+/**
+ * How the blocks of an accessed path and its stash are written back.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionMode {
+    // sort and place the blocks greedily; fast, but the branches and the memory accesses inside the enclave depend
+    // on where the real blocks are
+    Greedy,
+    // place the blocks with sorting networks and constant-time scans over the path and a fixed-size stash, so that
+    // the control flow does not depend on the blocks
+    Oblivious,
+}
+
+/**
+ * Tree oram scheme of the fixed-size block orams.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OramScheme {
+    // the whole accessed path is read and evicted, as in path oram
+    Path,
+    // the block accessed is moved to the stash, and after every write back two paths in reverse-lexicographic order
+    // are evicted by moving at most one block per level, as in circuit oram, which keeps the stash small
+    Circuit,
+}
+
+/**
+ * Bytes of tree pages moved between the storage and the enclave by an oram since it was created. The pages are
+ * read and written whole, whatever part of them the scheme uses.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BandwidthStats {
+    // bytes of the pages read to answer accesses
+    pub online_bytes: usize,
+    // bytes of the pages written back after accesses, and read and written to evict
+    pub evict_bytes: usize,
+}
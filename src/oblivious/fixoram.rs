@@ -1,8 +1,18 @@
 use std::vec;
 
-use crate::params::{KEY_SIZE, MAX_CACHE_SIZE, MIN_SEGMENT_SIZE, PAGE_SIZE};
+use super::evict::{BandwidthStats, EvictionMode, OramScheme};
+use super::memory::MemoryUsage;
+use crate::params::{
+    KEY_SIZE, KEY_SWEEP_RATE, MAX_CACHE_SIZE, MIN_SEGMENT_SIZE, OBLIVIOUS_STASH_SIZE, PAGE_SIZE,
+};
+use crate::storage::memstore::MemStore;
+use crate::storage::storage::BlockStorage;
+use crate::tree::cipher::{CipherKind, StorageKey};
 use crate::tree::dynamictree::{calc_deepest, ORAMTree};
-use crate::utils::utils::SimpleVal;
+use crate::tree::forkvec::ForkVec;
+use crate::utils::ct::{cmov, ct_eq, ct_lt, ct_select};
+use crate::utils::osort::bitonic_sort_by_key;
+use crate::utils::utils::{wipe_vec, RandGen, SimpleVal};
 use bytemuck::{Pod, Zeroable};
 
 pub const BUFFER_SIZE: usize = PAGE_SIZE - 2 * std::mem::size_of::<u16>() - KEY_SIZE;
@@ -20,8 +30,15 @@ impl BlockId {
             uid: 0,
         }
     }
+
+    pub fn ct_eq(&self, other: &Self) -> bool {
+        ct_eq(self.page_idx, other.page_idx) & ct_eq(self.uid, other.uid)
+    }
 }
 
+unsafe impl Zeroable for BlockId {}
+unsafe impl Pod for BlockId {}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 struct Page<T: SimpleVal, const N: usize> {
@@ -44,32 +61,46 @@ impl<T: SimpleVal, const N: usize> Page<T, N> {
         self.data[idx as usize] = *entry;
     }
 
+    // scans all the slots, so that the timing does not reveal where the entry is
     fn read_and_remove_entry(&mut self, meta_data: &BlockId) -> Option<T> {
+        let empty_id = BlockId::new();
+        let mut found_flag = false;
+        let mut ret = T::zeroed();
         for i in 0..N {
-            if self.indices[i] == *meta_data && self.indices[i] != BlockId::new() {
-                self.indices[i] = BlockId::new();
-                let ret = Some(self.data[i]);
-                return ret;
-            }
+            let hit =
+                self.indices[i].ct_eq(meta_data) & !self.indices[i].ct_eq(&empty_id) & !found_flag;
+            cmov(hit, &mut ret, &self.data[i]);
+            cmov(hit, &mut self.indices[i].page_idx, &empty_id.page_idx);
+            cmov(hit, &mut self.indices[i].uid, &empty_id.uid);
+            found_flag |= hit;
         }
-        None
+        found_flag.then_some(ret)
     }
 }
 
 #[derive(Clone)]
-struct StashEntry<T> {
+struct StashEntry<T: SimpleVal> {
     pub kvs: Vec<(BlockId, T)>,
 }
 
+// the blocks are plaintext, including those left past the length by the removals
+impl<T: SimpleVal> Drop for StashEntry<T> {
+    fn drop(&mut self) {
+        wipe_vec(&mut self.kvs);
+    }
+}
+
 impl<T: SimpleVal> StashEntry<T> {
     fn new() -> Self {
         Self { kvs: Vec::new() }
     }
 }
 
-struct Stash<T> {
-    stash: Vec<StashEntry<T>>,
-    versions: Vec<u8>,
+// one bucket per page of the top layer, which has at most MAX_CACHE_SIZE pages, so the stash metadata does not
+// grow with the number of pages
+struct Stash<T: SimpleVal> {
+    stash: ForkVec<StashEntry<T>>,
+    versions: ForkVec<u8>,
     size: usize,
     log_size: u8,
     num_kvs: usize,
@@ -81,8 +112,8 @@ impl<T: SimpleVal> Stash<T> {
     pub fn new(init_size: usize) -> Self {
         assert!((init_size & (init_size - 1)) == 0); // must be power of 2
         let log_init_size = init_size.trailing_zeros() as u8;
-        let stash = vec![StashEntry::new(); init_size];
-        let versions = vec![log_init_size; init_size];
+        let stash = ForkVec::from_elem(StashEntry::new(), init_size);
+        let versions = ForkVec::from_elem(log_init_size, init_size);
         Self {
             stash,
             versions,
@@ -100,21 +131,10 @@ impl<T: SimpleVal> Stash<T> {
             return;
         }
         assert_eq!(self.size, self.stash.len());
-        let old_size = self.size;
-        self.stash.resize(new_size, StashEntry::new());
-        self.versions.resize(new_size, 0u8);
-        // copy the versions
-        let scale_factor = new_size / old_size;
-        for i in 1..scale_factor {
-            unsafe {
-                std::ptr::copy(
-                    self.versions.as_ptr(),
-                    self.versions
-                        .as_mut_ptr()
-                        .offset(i as isize * old_size as isize),
-                    old_size,
-                );
-            }
+        // the versions and the entries are forked lazily, the entries are split on their first access anyway
+        while self.stash.len() < new_size {
+            self.stash.double_with(StashEntry::new());
+            self.versions.double_and_fork();
         }
         self.size = new_size;
         self.log_size = new_size.trailing_zeros() as u8;
@@ -139,6 +159,7 @@ impl<T: SimpleVal> Stash<T> {
             std::mem::swap(&mut self.stash[to_idx].kvs, &mut kvs_after_split[i]);
             self.versions[to_idx] = self.log_size;
         }
+        kvs_after_split.iter_mut().for_each(wipe_vec);
     }
 
     pub fn get_mut(&mut self, idx: usize) -> &mut Vec<(BlockId, T)> {
@@ -169,6 +190,19 @@ impl<T: SimpleVal> Stash<T> {
             + self.size * (std::mem::size_of::<StashEntry<T>>() + 1)
     }
 }
+// a slot of the path or the stash during the oblivious eviction
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct EvictSlot<T: SimpleVal> {
+    id: BlockId,
+    real: usize,
+    key: usize, // the deepest level of the block when placing, and the destination slot when routing
+    val: T,
+}
+
+unsafe impl<T: SimpleVal> Zeroable for EvictSlot<T> {}
+unsafe impl<T: SimpleVal> Pod for EvictSlot<T> {}
+
 #[derive(Clone)]
 struct EvictInfo {
     is_from_stash: bool,
@@ -176,44 +210,393 @@ struct EvictInfo {
     offset: u16,
 }
 
-pub struct FixOram<T: SimpleVal, const N: usize> {
-    tree: ORAMTree<Page<T, N>>,
+/**
+ * Oram of fixed-size blocks, whose tree pages are kept in storages of type `S`.
+ */
+pub struct FixOram<T: SimpleVal, const N: usize, S: BlockStorage = MemStore> {
+    tree: ORAMTree<Page<T, N>, S>,
     stash: Stash<T>,
     num_entry: usize,
     evict_infos_cache: Vec<Vec<EvictInfo>>, // a cache to store the src position of entries to evict
     empty_slots_cache: Vec<Vec<u16>>,       // a cache to store the empty slots in the path
     stash_remain_cache: Vec<u16>,           // cache the idx of stash entries that are not evicted
+    eviction: EvictionMode,
+    scheme: OramScheme,
+    evict_count: usize, // number of paths evicted in reverse-lexicographic order
+    background_eviction: Option<usize>,
+    num_accesses: usize,
+    stats: BandwidthStats,
 }
 
+// the deepest block of a page on an eviction path
+struct DeepestBlock {
+    slot: usize,
+    deepest: usize, // NONE if the page has no real block
+    has_free: bool,
+}
+
+const NONE: usize = usize::MAX;
+
 impl<T: SimpleVal, const N: usize> FixOram<T, N> {
     pub fn new() -> Self {
+        Self::with_eviction(EvictionMode::Greedy)
+    }
+
+    pub fn with_eviction(eviction: EvictionMode) -> Self {
+        Self::with_options(eviction, OramScheme::Path, None, RandGen::new())
+    }
+}
+
+impl<T: SimpleVal, const N: usize, S: BlockStorage> FixOram<T, N, S> {
+    /**
+     * The eviction mode only applies to the path scheme, the circuit scheme always evicts with a fixed sequence of
+     * operations per level.
+     * With `background_eviction` set to k, the path scheme also evicts one path in reverse-lexicographic order
+     * every k accesses. The circuit scheme already evicts in that order and ignores it.
+     * The nonces of the tree are drawn from `rand_gen`.
+     */
+    pub fn with_options(
+        eviction: EvictionMode,
+        scheme: OramScheme,
+        background_eviction: Option<usize>,
+        rand_gen: RandGen,
+    ) -> Self {
+        assert!(background_eviction != Some(0));
         Self {
-            tree: ORAMTree::new(MAX_CACHE_SIZE),
+            tree: ORAMTree::with_options(MAX_CACHE_SIZE, CipherKind::default(), rand_gen),
             stash: Stash::new(MIN_SEGMENT_SIZE),
             num_entry: 0,
             evict_infos_cache: vec![Vec::new(); 48],
             empty_slots_cache: vec![Vec::new(); 48],
             stash_remain_cache: Vec::new(),
+            eviction,
+            scheme,
+            evict_count: 0,
+            background_eviction,
+            num_accesses: 0,
+            stats: BandwidthStats::default(),
+        }
+    }
+
+    pub fn eviction(&self) -> EvictionMode {
+        self.eviction
+    }
+
+    pub fn scheme(&self) -> OramScheme {
+        self.scheme
+    }
+
+    pub fn background_eviction(&self) -> Option<usize> {
+        self.background_eviction
+    }
+
+    pub fn bandwidth(&self) -> BandwidthStats {
+        self.stats
+    }
+
+    pub fn stash_len(&self) -> usize {
+        self.stash.num_kvs()
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            stash: self.stash.num_bytes(),
+            ..MemoryUsage::tree(&self.tree)
         }
     }
 
+    /**
+     * Stop growing the top layer of the tree, so that the tree grows by adding layers below it. The stash keeps one
+     * bucket per page of the top layer, so its metadata stops growing as well. Returns whether the top layer could
+     * still grow before.
+     */
+    pub fn limit_top_layer(&mut self) -> bool {
+        self.tree.limit_top_layer()
+    }
+
+    /**
+     * Write the pages under `key` from now on. The pages still under the previous key are rewritten a few at a time
+     * on every access, in a fixed order, see `ORAMTree::sweep_keys`.
+     */
+    pub fn rotate_key(&mut self, key: &StorageKey) {
+        self.tree.rotate_key(key);
+    }
+
+    pub fn sweep_keys(&mut self, max_pages: usize) -> usize {
+        self.tree.sweep_keys(max_pages)
+    }
+
+    pub fn max_top_layer_size(&self) -> usize {
+        self.tree.max_top_layer_size()
+    }
+
+    pub fn set_max_top_layer_size(&mut self, max_size: usize) {
+        self.tree.set_max_top_layer_size(max_size);
+    }
+
+    fn capacity_bytes(&self) -> f64 {
+        (self.tree.total_size() * BUFFER_SIZE) as f64
+    }
+
     fn num_bytes(&self) -> usize {
         self.num_entry * (std::mem::size_of::<(BlockId, T)>()) + self.stash.num_bytes()
     }
 
     fn scale_if_load_high(&mut self) {
-        let load_factor = self.num_bytes() as f64 / (self.tree.total_size() * BUFFER_SIZE) as f64;
+        let load_factor = self.num_bytes() as f64 / self.capacity_bytes();
         if load_factor > 0.7 {
             println!(
                 "load bytes: {} total bytes: {}",
                 self.num_bytes(),
-                self.tree.total_size() * BUFFER_SIZE
+                self.capacity_bytes()
             );
             self.scale();
         }
     }
 
     fn retrieve(&mut self, id: &BlockId) -> Option<T> {
+        match (self.scheme, self.eviction) {
+            (OramScheme::Path, EvictionMode::Greedy) => {
+                self.retrieve_path(id, Self::retrieve_greedy)
+            }
+            (OramScheme::Path, EvictionMode::Oblivious) => {
+                self.retrieve_path(id, Self::retrieve_oblivious)
+            }
+            (OramScheme::Circuit, _) => self.retrieve_circuit(id),
+        }
+    }
+
+    fn retrieve_path(
+        &mut self,
+        id: &BlockId,
+        f: fn(&mut Self, &BlockId) -> Option<T>,
+    ) -> Option<T> {
+        // the whole path is read, and written back after the eviction
+        let path_bytes = self.tree.layer_sizes().len() * PAGE_SIZE;
+        self.stats.online_bytes += path_bytes;
+        self.stats.evict_bytes += path_bytes;
+        f(self, id)
+    }
+
+    // evicts after the blocks of `num_write_backs` write backs have been inserted into the stash, and sweeps the
+    // pages of a key rotation in progress
+    fn evict_after_write_back(&mut self, num_write_backs: usize) {
+        self.tree.sweep_keys(KEY_SWEEP_RATE);
+        match self.scheme {
+            OramScheme::Path => {
+                self.num_accesses += 1;
+                if let Some(every) = self.background_eviction {
+                    if self.num_accesses.is_multiple_of(every) {
+                        self.evict_path();
+                    }
+                }
+            }
+            OramScheme::Circuit => {
+                for _ in 0..2 * num_write_backs {
+                    self.evict_circuit();
+                }
+            }
+        }
+    }
+
+    // the next path in reverse-lexicographic order, which visits every page of a layer with n pages once every n
+    // evictions, whatever the sizes of the other layers
+    fn next_evict_path(&mut self) -> usize {
+        let path_idx = self.tree.reverse_lex_path(self.evict_count);
+        self.evict_count += 1;
+        path_idx
+    }
+
+    /**
+     * Background eviction of the path scheme. The next path in reverse-lexicographic order is evicted as if a block
+     * that does not exist was read from it, so that the stash of every top-level page is drained at a fixed rate
+     * whichever paths the accesses happen to take.
+     */
+    fn evict_path(&mut self) {
+        // no block has the maximum uid
+        let id = BlockId {
+            page_idx: self.next_evict_path(),
+            uid: usize::MAX,
+        };
+        self.stats.evict_bytes += 2 * self.tree.layer_sizes().len() * PAGE_SIZE;
+        match self.eviction {
+            EvictionMode::Greedy => self.retrieve_greedy(&id),
+            EvictionMode::Oblivious => self.retrieve_oblivious(&id),
+        };
+    }
+
+    fn layer_log_sizes(layer_sizes: &[usize]) -> Vec<u8> {
+        layer_sizes
+            .iter()
+            .map(|x| x.trailing_zeros() as u8)
+            .collect()
+    }
+
+    /**
+     * Circuit oram access: the block is removed from the path and the stash, and the path is written back
+     * without moving any other block. The block is then inserted into the stash by the caller, and the
+     * evictions after the write back move it down the tree.
+     */
+    fn retrieve_circuit(&mut self, id: &BlockId) -> Option<T> {
+        let path_idx = id.page_idx;
+        let (mut path, _) = self.tree.read_path(path_idx);
+        let path_bytes = path.len() * PAGE_SIZE;
+        self.stats.online_bytes += path_bytes;
+        self.stats.evict_bytes += path_bytes;
+        let mut found_flag = false;
+        let mut result = T::zeroed();
+        for page in path.iter_mut() {
+            let entry = page.read_and_remove_entry(id);
+            cmov(entry.is_some(), &mut result, &entry.unwrap_or(T::zeroed()));
+            found_flag |= entry.is_some();
+        }
+        let stash_vec = self.stash.get_mut(path_idx);
+        let stash_len = stash_vec.len();
+        stash_vec.retain(|(block_id, value)| {
+            let hit = block_id.ct_eq(id) & !found_flag;
+            cmov(hit, &mut result, value);
+            found_flag |= hit;
+            !hit
+        });
+        self.stash.num_kvs -= stash_len - stash_vec.len();
+        self.tree.write_path(path_idx, &path);
+        wipe_vec(&mut path);
+        found_flag.then_some(result)
+    }
+
+    // the slot of the block of the page that can go deepest on the path, in one constant-time scan of the page
+    fn deepest_block(
+        page: &Page<T, N>,
+        path_idx: usize,
+        level: usize,
+        layer_log_sizes: &Vec<u8>,
+    ) -> DeepestBlock {
+        let empty_id = BlockId::new();
+        let mut ret = DeepestBlock {
+            slot: 0,
+            deepest: NONE,
+            has_free: false,
+        };
+        for j in 0..N {
+            let deepest =
+                calc_deepest(page.indices[j].page_idx, path_idx, layer_log_sizes) as usize;
+            // stale copies of blocks that belong to a sibling page after fork are free slots
+            let free = page.indices[j].ct_eq(&empty_id) | ct_lt(level, deepest);
+            let better = !free & ct_lt(deepest, ret.deepest);
+            ret.slot = ct_select(better, j, ret.slot);
+            ret.deepest = ct_select(better, deepest, ret.deepest);
+            ret.has_free |= free;
+        }
+        ret
+    }
+
+    // moves the block into a free slot of the page if cond is set, there must be one
+    fn insert_into_free_slot(
+        cond: bool,
+        page: &mut Page<T, N>,
+        block: &(BlockId, T),
+        path_idx: usize,
+        level: usize,
+        layer_log_sizes: &Vec<u8>,
+    ) {
+        let empty_id = BlockId::new();
+        let mut done = false;
+        for j in 0..N {
+            let deepest =
+                calc_deepest(page.indices[j].page_idx, path_idx, layer_log_sizes) as usize;
+            let free = page.indices[j].ct_eq(&empty_id) | ct_lt(level, deepest);
+            let write = cond & free & !done;
+            cmov(write, &mut page.indices[j], &block.0);
+            cmov(write, &mut page.data[j], &block.1);
+            done |= write;
+        }
+        assert!(done || !cond);
+    }
+
+    /**
+     * Evicts the next path in reverse-lexicographic order as in circuit oram. Two metadata passes find, for
+     * every level of the path and the stash, the block that can be moved deepest and whether it should be
+     * moved, and a single top-down pass then moves at most one block per level while holding one block.
+     */
+    fn evict_circuit(&mut self) {
+        let path_idx = self.next_evict_path();
+        let (mut path, layer_sizes) = self.tree.read_path(path_idx);
+        let num_layer = layer_sizes.len();
+        self.stats.evict_bytes += 2 * num_layer * PAGE_SIZE;
+        let layer_log_sizes = Self::layer_log_sizes(&layer_sizes);
+
+        // the stash is the level above the top of the path
+        let blocks: Vec<DeepestBlock> = path
+            .iter()
+            .enumerate()
+            .map(|(level, page)| Self::deepest_block(page, path_idx, level, &layer_log_sizes))
+            .collect();
+        let stash_vec = self.stash.get_mut(path_idx);
+        let mut stash_best = DeepestBlock {
+            slot: 0,
+            deepest: NONE,
+            has_free: true,
+        };
+        for (j, (block_id, _)) in stash_vec.iter().enumerate() {
+            let deepest = calc_deepest(block_id.page_idx, path_idx, &layer_log_sizes) as usize;
+            let better = ct_lt(deepest, stash_best.deepest);
+            stash_best.slot = ct_select(better, j, stash_best.slot);
+            stash_best.deepest = ct_select(better, deepest, stash_best.deepest);
+        }
+
+        // from the top down, the level above each level whose block can go deepest, if it can reach the level
+        let mut deepest_src = vec![NONE; num_layer + 1];
+        let mut goal = stash_best.deepest;
+        let mut src = num_layer;
+        for level in (0..num_layer).rev() {
+            deepest_src[level] = ct_select(!ct_lt(level, goal), src, NONE);
+            let better = ct_lt(blocks[level].deepest, goal);
+            goal = ct_select(better, blocks[level].deepest, goal);
+            src = ct_select(better, level, src);
+        }
+
+        // from the bottom up, the level each level moves its block to, so that every block moved has room
+        let mut target = vec![NONE; num_layer + 1];
+        let mut dest = NONE;
+        let mut src = NONE;
+        for level in 0..=num_layer {
+            let is_src = ct_eq(level, src);
+            target[level] = ct_select(is_src, dest, target[level]);
+            dest = ct_select(is_src, NONE, dest);
+            src = ct_select(is_src, NONE, src);
+            let has_free = level < num_layer && blocks[level].has_free;
+            let take = ((ct_eq(dest, NONE) & has_free) | !ct_eq(target[level], NONE))
+                & !ct_eq(deepest_src[level], NONE);
+            src = ct_select(take, deepest_src[level], src);
+            dest = ct_select(take, level, dest);
+        }
+
+        // from the top down, pick up the block of each level with a target and drop the block held at its target
+        let mut hold = (BlockId::new(), T::zeroed());
+        let mut hold_dest = NONE;
+        if target[num_layer] != NONE {
+            hold = stash_vec.swap_remove(stash_best.slot);
+            hold_dest = target[num_layer];
+            self.stash.num_kvs -= 1;
+        }
+        for level in (0..num_layer).rev() {
+            let page = &mut path[level];
+            let drop = ct_eq(level, hold_dest);
+            let to_write = hold;
+            hold_dest = ct_select(drop, NONE, hold_dest);
+            let pick = !ct_eq(target[level], NONE);
+            let slot = blocks[level].slot;
+            cmov(pick, &mut hold.0, &page.indices[slot]);
+            cmov(pick, &mut hold.1, &page.data[slot]);
+            cmov(pick, &mut page.indices[slot], &BlockId::new());
+            hold_dest = ct_select(pick, target[level], hold_dest);
+            Self::insert_into_free_slot(drop, page, &to_write, path_idx, level, &layer_log_sizes);
+        }
+        self.tree.write_path(path_idx, &path);
+        wipe_vec(&mut path);
+    }
+
+    fn retrieve_greedy(&mut self, id: &BlockId) -> Option<T> {
         let path_idx = id.page_idx;
         let (mut path, layer_sizes) = self.tree.read_path(path_idx);
         let num_layer = layer_sizes.len();
@@ -221,13 +604,13 @@ impl<T: SimpleVal, const N: usize> FixOram<T, N> {
             .iter()
             .map(|x| x.trailing_zeros() as u8)
             .collect();
-        let mut result: Option<T> = None;
+        let mut found_flag = false;
+        let mut result = T::zeroed();
 
         for (i, page) in path.iter_mut().enumerate() {
             let entry = page.read_and_remove_entry(id);
-            if entry.is_some() {
-                result = entry;
-            }
+            cmov(entry.is_some(), &mut result, &entry.unwrap_or(T::zeroed()));
+            found_flag |= entry.is_some();
             for j in 0..N {
                 let page_idx = page.indices[j].page_idx;
                 let deepest = calc_deepest(page_idx, path_idx, &layer_log_sizes);
@@ -244,17 +627,18 @@ impl<T: SimpleVal, const N: usize> FixOram<T, N> {
         }
         let stash_vec = self.stash.get_mut(path_idx);
         for (i, (block_id, value)) in stash_vec.iter().enumerate() {
-            if block_id == id {
-                result = Some(value.clone());
-            } else {
-                let deepest = calc_deepest(block_id.page_idx, path_idx, &layer_log_sizes);
-                assert!(deepest < num_layer as u8);
-                self.evict_infos_cache[deepest as usize].push(EvictInfo {
-                    is_from_stash: true,
-                    src: 0,
-                    offset: i as u16,
-                });
-            }
+            let hit = block_id.ct_eq(id);
+            cmov(hit, &mut result, value);
+            found_flag |= hit;
+            let deepest = calc_deepest(block_id.page_idx, path_idx, &layer_log_sizes);
+            assert!(deepest < num_layer as u8);
+            // every block is pushed, the block found to the level past the path, which is neither evicted nor kept
+            let deepest = ct_select(hit, num_layer as u8, deepest);
+            self.evict_infos_cache[deepest as usize].push(EvictInfo {
+                is_from_stash: true,
+                src: 0,
+                offset: i as u16,
+            });
         }
 
         let mut curr_evict_info_level = 0;
@@ -335,9 +719,139 @@ impl<T: SimpleVal, const N: usize> FixOram<T, N> {
             self.evict_infos_cache[i].clear();
             self.empty_slots_cache[i].clear();
         }
+        self.evict_infos_cache[num_layer].clear();
         self.stash_remain_cache.clear();
         self.tree.write_path(path_idx, &path);
-        result
+        wipe_vec(&mut path);
+        found_flag.then_some(result)
+    }
+
+    /**
+     * Same as `retrieve_greedy`, but the path and the stash of the path, padded to `OBLIVIOUS_STASH_SIZE` blocks,
+     * are processed with a fixed sequence of operations. The blocks are sorted by their deepest level with a
+     * bitonic network, assigned to the deepest free slot in one constant-time scan, and then routed to their slots
+     * with a second sort, so neither the branches nor the memory accesses depend on where the real blocks are.
+     * The stash only takes more than `OBLIVIOUS_STASH_SIZE` blocks on overflow.
+     */
+    fn retrieve_oblivious(&mut self, id: &BlockId) -> Option<T> {
+        let path_idx = id.page_idx;
+        let (mut path, layer_sizes) = self.tree.read_path(path_idx);
+        let num_layer = layer_sizes.len();
+        let layer_log_sizes: Vec<u8> = layer_sizes
+            .iter()
+            .map(|x| x.trailing_zeros() as u8)
+            .collect();
+        let empty_id = BlockId::new();
+        let stash_vec = self.stash.get_mut(path_idx);
+        let stash_len = stash_vec.len();
+        let stash_cap = stash_len.max(OBLIVIOUS_STASH_SIZE);
+        let path_cap = num_layer * N;
+        let mut found_flag = false;
+        let mut result = T::zeroed();
+
+        // collect the slots of the path followed by the padded stash; the block read, the empty slots and the
+        // blocks that belong to a sibling page after fork become dummies
+        let mut slots: Vec<EvictSlot<T>> = Vec::with_capacity(path_cap + stash_cap);
+        for page in path.iter() {
+            for j in 0..N {
+                slots.push(EvictSlot {
+                    id: page.indices[j],
+                    real: 0,
+                    key: 0,
+                    val: page.data[j],
+                });
+            }
+        }
+        for j in 0..stash_cap {
+            let (block_id, val) = stash_vec.get(j).copied().unwrap_or((empty_id, T::zeroed()));
+            slots.push(EvictSlot {
+                id: block_id,
+                real: 0,
+                key: 0,
+                val,
+            });
+        }
+        for (k, slot) in slots.iter_mut().enumerate() {
+            // a block stays valid at the level of its slot or above it, and stash blocks anywhere on the path
+            let max_level = (k / N).min(num_layer - 1);
+            let is_empty = slot.id.ct_eq(&empty_id);
+            let hit = slot.id.ct_eq(id) & !is_empty & !found_flag;
+            cmov(hit, &mut result, &slot.val);
+            found_flag |= hit;
+            let deepest = calc_deepest(slot.id.page_idx, path_idx, &layer_log_sizes) as usize;
+            let real = !is_empty & !hit & !ct_lt(max_level, deepest);
+            slot.real = real as usize;
+            slot.key = ct_select(real, deepest, usize::MAX);
+        }
+
+        // assign the blocks, from the deepest level up, to the deepest level that still has a free slot
+        bitonic_sort_by_key(&mut slots, &|slot: &EvictSlot<T>| slot.key);
+        let mut level = 0;
+        let mut count = 0;
+        let mut level_counts = vec![0; num_layer];
+        for slot in slots.iter_mut() {
+            let real = ct_eq(slot.real, 1);
+            let deepest = slot.key;
+            let skip = ct_lt(level, deepest);
+            let mut new_level = ct_select(skip, deepest, level);
+            let mut new_count = ct_select(skip, 0, count);
+            let full = ct_eq(new_count, N) & ct_lt(new_level, num_layer);
+            new_level += full as usize;
+            new_count = ct_select(full, 0, new_count);
+            // blocks that do not fit in the path go to the stash
+            let in_path = ct_lt(new_level, num_layer);
+            // dummies are at the maximum level, their destination is discarded
+            let path_dest = new_level.wrapping_mul(N).wrapping_add(new_count);
+            let dest = ct_select(in_path, path_dest, path_cap + new_count);
+            for (l, level_count) in level_counts.iter_mut().enumerate() {
+                *level_count += (real & ct_eq(new_level, l)) as usize;
+            }
+            level = ct_select(real, new_level, level);
+            count = ct_select(real, new_count + 1, count);
+            slot.key = ct_select(real, dest, usize::MAX);
+        }
+        let overflow = ct_select(ct_eq(level, num_layer), count, 0);
+
+        // the dummies fill the free slots of the path level by level, and then pad the stash
+        let mut hole_starts = vec![0; num_layer];
+        let mut num_holes = 0;
+        for l in 0..num_layer {
+            hole_starts[l] = num_holes;
+            num_holes += N - level_counts[l];
+        }
+        let mut dummy_rank: usize = 0;
+        for slot in slots.iter_mut() {
+            let dummy = !ct_eq(slot.real, 1);
+            // the ranks are only subtracted when in range, other results are discarded
+            let mut dest = (path_cap + overflow).wrapping_add(dummy_rank.wrapping_sub(num_holes));
+            for l in 0..num_layer {
+                let in_level = !ct_lt(dummy_rank, hole_starts[l])
+                    & ct_lt(dummy_rank, hole_starts[l] + N - level_counts[l]);
+                let level_dest =
+                    (l * N + level_counts[l]).wrapping_add(dummy_rank.wrapping_sub(hole_starts[l]));
+                dest = ct_select(in_level, level_dest, dest);
+            }
+            slot.key = ct_select(dummy, dest, slot.key);
+            cmov(dummy, &mut slot.id, &empty_id);
+            cmov(dummy, &mut slot.val, &T::zeroed());
+            dummy_rank += dummy as usize;
+        }
+
+        // every slot now has a distinct destination, so sorting by it routes the blocks to their slots
+        bitonic_sort_by_key(&mut slots, &|slot: &EvictSlot<T>| slot.key);
+        for (i, page) in path.iter_mut().enumerate() {
+            for j in 0..N {
+                page.indices[j] = slots[i * N + j].id;
+                page.data[j] = slots[i * N + j].val;
+            }
+        }
+        stash_vec.clear();
+        stash_vec.extend(slots[path_cap..].iter().map(|slot| (slot.id, slot.val)));
+        stash_vec.truncate(overflow);
+        self.stash.num_kvs -= stash_len - overflow;
+        self.tree.write_path(path_idx, &path);
+        wipe_vec(&mut path);
+        found_flag.then_some(result)
     }
 
     pub fn update<F>(&mut self, id: &BlockId, update_func: F, new_page_id: usize)
@@ -363,31 +877,75 @@ impl<T: SimpleVal, const N: usize> FixOram<T, N> {
             self.stash
                 .insert(new_page_id, new_id, result.unwrap().clone());
         }
+        self.evict_after_write_back(1);
         self.scale_if_load_high();
     }
 
+    /**
+     * Read a block and write back the blocks returned by `update_func` as (value, uid, page). A `None` value is a
+     * dummy write: nothing is stored, but it counts for the eviction like a real one, so that the evictions only depend on the
+     * number of write backs.
+     */
     pub fn update_and_write_multiple<F>(&mut self, id: &BlockId, update_func: F)
     where
-        F: FnOnce(Option<T>, usize) -> Vec<(T, usize, usize)>,
+        F: FnOnce(Option<T>, usize) -> Vec<(Option<T>, usize, usize)>,
     {
         let result = self.retrieve(id);
         let found_flag = result.is_some();
         let write_backs = update_func(result, id.uid);
-        self.num_entry += write_backs.len();
+        let num_write_backs = write_backs.len().max(1);
         if found_flag {
             self.num_entry -= 1;
         }
         for (result, new_uid, new_page_id) in write_backs {
-            let new_id = BlockId {
-                page_idx: new_page_id,
-                uid: new_uid,
-            };
-
-            self.stash.insert(new_page_id, new_id, result);
+            if let Some(result) = result {
+                let new_id = BlockId {
+                    page_idx: new_page_id,
+                    uid: new_uid,
+                };
+                self.num_entry += 1;
+                self.stash.insert(new_page_id, new_id, result);
+            }
         }
+        self.evict_after_write_back(num_write_backs);
         self.scale_if_load_high();
     }
 
+    /**
+     * Build the oram from the given blocks in one pass. The tree is scaled for `num_blocks` blocks, at least as many
+     * as given, and every page is then written sequentially, so the page access pattern only depends on
+     * `num_blocks`, and not on how many of them are present.
+     */
+    pub fn bulk_build(&mut self, blocks: Vec<(BlockId, T)>, num_blocks: usize) {
+        assert_eq!(self.num_entry, 0);
+        assert!(blocks.len() <= num_blocks);
+        self.num_entry = num_blocks;
+        while self.num_bytes() as f64 > self.capacity_bytes() * 0.7 {
+            self.scale();
+        }
+        self.num_entry = blocks.len();
+        let mut pending = blocks;
+        for (level, layer_size) in self.tree.layer_sizes().into_iter().enumerate() {
+            let mut pages = vec![Page::new(); layer_size];
+            let mut filled = vec![0u16; layer_size];
+            let mut rest = Vec::new();
+            for (block_id, value) in pending {
+                let page_idx = block_id.page_idx % layer_size;
+                if (filled[page_idx] as usize) < N {
+                    pages[page_idx].insert(filled[page_idx], &block_id, &value);
+                    filled[page_idx] += 1;
+                } else {
+                    rest.push((block_id, value));
+                }
+            }
+            self.tree.write_layer(level, &pages);
+            pending = rest;
+        }
+        for (block_id, value) in pending {
+            self.stash.insert(block_id.page_idx, block_id, value);
+        }
+    }
+
     fn scale(&mut self) {
         let target_branching_factor = N;
         println!("Scaling to branching factor {}", target_branching_factor);
@@ -453,6 +1011,7 @@ impl<T: SimpleVal, const N: usize> FixOram<T, N> {
     }
 }
 
+#[cfg(test)]
 mod tests {
     use super::*;
     use rand::random;
@@ -507,4 +1066,197 @@ mod tests {
         }
         page_oram.print_meta_state();
     }
+
+    #[test]
+    fn test_fix_oram_bulk_build() {
+        const BLOCK_PER_PAGE: usize =
+            (BUFFER_SIZE / (std::mem::size_of::<(BlockId, u128)>())) as usize;
+        let mut page_oram = FixOram::<u128, BLOCK_PER_PAGE>::new();
+        let size = 200000;
+        let mut ref_vec: Vec<(BlockId, u128)> = (0..size)
+            .map(|i| {
+                let id = BlockId {
+                    page_idx: random::<usize>(),
+                    uid: i,
+                };
+                (id, random::<u128>())
+            })
+            .collect();
+        page_oram.bulk_build(ref_vec.clone(), size);
+        assert_eq!(page_oram.get_all().len(), size);
+        for (entry, value) in ref_vec.iter_mut() {
+            let new_page_id = random();
+            let result = page_oram.read(entry, new_page_id);
+            assert_eq!(result, Some(*value));
+            entry.page_idx = new_page_id;
+        }
+        for (entry, value) in ref_vec.iter() {
+            assert_eq!(page_oram.read(entry, random()), Some(*value));
+        }
+    }
+
+    #[test]
+    fn test_fix_oram_oblivious() {
+        const BLOCK_PER_PAGE: usize =
+            (BUFFER_SIZE / (std::mem::size_of::<(BlockId, u128)>())) as usize;
+        let mut page_oram = FixOram::<u128, BLOCK_PER_PAGE>::with_eviction(EvictionMode::Oblivious);
+        let round = 5000;
+        let mut ref_vec: Vec<(BlockId, u128)> = Vec::new();
+        for i in 0..round {
+            let entry = BlockId {
+                page_idx: random::<usize>(),
+                uid: i + 1,
+            };
+            let value = random::<u128>();
+            let new_page_id = random::<usize>();
+            page_oram.write(&entry, &value, new_page_id);
+            ref_vec.push((
+                BlockId {
+                    page_idx: new_page_id,
+                    uid: entry.uid,
+                },
+                value,
+            ));
+        }
+        for _ in 0..3 {
+            for (entry, value) in ref_vec.iter_mut() {
+                let new_page_id = random();
+                assert_eq!(page_oram.read(entry, new_page_id), Some(*value));
+                entry.page_idx = new_page_id;
+            }
+        }
+        assert_eq!(page_oram.get_all().len(), round);
+    }
+
+    #[test]
+    fn test_fix_oram_schemes() {
+        const BLOCK_PER_PAGE: usize =
+            (BUFFER_SIZE / (std::mem::size_of::<(BlockId, u128)>())) as usize;
+        for scheme in [OramScheme::Path, OramScheme::Circuit] {
+            let mut page_oram = FixOram::<u128, BLOCK_PER_PAGE>::with_options(
+                EvictionMode::Greedy,
+                scheme,
+                None,
+                RandGen::from_seed(7),
+            );
+            let round = 20000;
+            let mut ref_vec: Vec<(BlockId, u128)> = Vec::new();
+            for i in 0..round {
+                let entry = BlockId {
+                    page_idx: random::<usize>(),
+                    uid: i + 1,
+                };
+                let value = random::<u128>();
+                let new_page_id = random::<usize>();
+                page_oram.write(&entry, &value, new_page_id);
+                ref_vec.push((
+                    BlockId {
+                        page_idx: new_page_id,
+                        uid: entry.uid,
+                    },
+                    value,
+                ));
+            }
+            let before = page_oram.bandwidth();
+            for _ in 0..3 {
+                for (entry, value) in ref_vec.iter_mut() {
+                    let new_page_id = random();
+                    assert_eq!(page_oram.read(entry, new_page_id), Some(*value));
+                    entry.page_idx = new_page_id;
+                }
+            }
+            assert_eq!(page_oram.get_all().len(), round);
+            let after = page_oram.bandwidth();
+            let num_layer = page_oram.tree.layer_sizes().len();
+            // every scheme reads the whole path of each access
+            let online = after.online_bytes - before.online_bytes;
+            assert_eq!(online, 3 * round * num_layer * PAGE_SIZE);
+            assert!(after.evict_bytes - before.evict_bytes >= online);
+            println!(
+                "{:?}: stash {} blocks, bandwidth {:?}",
+                scheme,
+                page_oram.stash.num_kvs(),
+                after
+            );
+        }
+    }
+
+    #[test]
+    fn test_fix_oram_background_eviction() {
+        const BLOCK_PER_PAGE: usize =
+            (BUFFER_SIZE / (std::mem::size_of::<(BlockId, u128)>())) as usize;
+        let round = 50000;
+        let mut stash_lens = Vec::new();
+        for background_eviction in [None, Some(1)] {
+            let mut page_oram = FixOram::<u128, BLOCK_PER_PAGE>::with_options(
+                EvictionMode::Greedy,
+                OramScheme::Path,
+                background_eviction,
+                RandGen::from_seed(7),
+            );
+            let mut ref_vec: Vec<(BlockId, u128)> = Vec::new();
+            for i in 0..round {
+                let entry = BlockId {
+                    page_idx: random::<usize>(),
+                    uid: i + 1,
+                };
+                let value = random::<u128>();
+                let new_page_id = random::<usize>();
+                page_oram.write(&entry, &value, new_page_id);
+                ref_vec.push((
+                    BlockId {
+                        page_idx: new_page_id,
+                        uid: entry.uid,
+                    },
+                    value,
+                ));
+            }
+            let mut max_stash_len = 0;
+            for (entry, value) in ref_vec.iter_mut() {
+                let new_page_id = random();
+                assert_eq!(page_oram.read(entry, new_page_id), Some(*value));
+                entry.page_idx = new_page_id;
+                max_stash_len = max_stash_len.max(page_oram.stash_len());
+            }
+            assert_eq!(page_oram.get_all().len(), round);
+            println!("{:?}: max stash {}", background_eviction, max_stash_len);
+            stash_lens.push(max_stash_len);
+        }
+        assert!(stash_lens[1] < stash_lens[0]);
+    }
+
+    #[test]
+    fn test_fix_oram_key_rotation() {
+        const BLOCK_PER_PAGE: usize = BUFFER_SIZE / std::mem::size_of::<(BlockId, u128)>();
+        let mut page_oram = FixOram::<u128, BLOCK_PER_PAGE>::new();
+        let mut ref_vec: Vec<(BlockId, u128)> = Vec::new();
+        for i in 0..1000 {
+            let entry = BlockId {
+                page_idx: random::<usize>(),
+                uid: i + 1,
+            };
+            let new_page_id = random::<usize>();
+            page_oram.write(&entry, &(i as u128), new_page_id);
+            ref_vec.push((
+                BlockId {
+                    page_idx: new_page_id,
+                    uid: entry.uid,
+                },
+                i as u128,
+            ));
+        }
+        page_oram.rotate_key(&StorageKey::from([1; 32]));
+        // the accesses sweep the whole tree, after which the previous key is dropped
+        let num_pages = page_oram.tree.total_size();
+        for round in 0..num_pages / KEY_SWEEP_RATE + 1 {
+            let (entry, value) = &mut ref_vec[round % 1000];
+            let new_page_id = random();
+            assert_eq!(page_oram.read(entry, new_page_id), Some(*value));
+            entry.page_idx = new_page_id;
+        }
+        assert_eq!(page_oram.sweep_keys(1), 0);
+        for (entry, value) in ref_vec.iter() {
+            assert_eq!(page_oram.read(entry, random()), Some(*value));
+        }
+    }
 }
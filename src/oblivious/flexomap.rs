@@ -1,66 +1,479 @@
 use num::integer::Roots;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 
-use super::cuckoo::CuckooHashMap;
+use super::autooram::AutoOram;
+use super::cuckoo::{CuckooHashMap, HashBkt, HashEntry, FINGERPRINT_SIZE};
+use super::evict::{EvictionMode, OramScheme};
 use super::fixoram::BUFFER_SIZE;
 use super::flexoram::FlexOram;
+use super::memory::MemoryUsage;
+use super::oram::Oram;
+use crate::error::DBError;
+use crate::params::{LINEAR_SCAN_THRESHOLD, MIN_SEGMENT_SIZE};
+use crate::storage::memstore::MemStore;
+use crate::storage::storage::BlockStorage;
+use crate::tree::cipher::StorageKey;
+use crate::utils::ct::ct_starts_with;
+use crate::utils::utils::RandGen;
+use zeroize::{Zeroize, Zeroizing};
 const HASH_ENTRY_PER_PAGE: usize = BUFFER_SIZE / 24;
 const BKT_PER_PAGE: usize = (HASH_ENTRY_PER_PAGE / 16 + 4).next_power_of_two();
 const BKT_SIZE: usize = (BUFFER_SIZE / BKT_PER_PAGE - 16) / 24;
-pub struct FlexOmap {
-    flexoram: FlexOram,
-    pos_map: CuckooHashMap<usize, BKT_SIZE, BKT_PER_PAGE>,
+
+/**
+ * What is kept of the original key in each oram entry.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyStorage {
+    // only the salted hash of the key is kept
+    HashOnly,
+    // a second salted hash of the key is stored in front of the value and verified on access
+    Fingerprint,
+    // the full key is stored in front of the value and verified on access, so that entries can be enumerated
+    Full,
+}
+
+/**
+ * Bucket of the position map, i.e., the block type of the oram backing the position map.
+ */
+pub type PosMapBkt = HashBkt<usize, BKT_SIZE>;
+
+/**
+ * Oblivious map with variable-size values, whose tree pages are kept in storages of type `S`. The position map is
+ * stored in an oram of type `O`, by default a linear scan while it is small and the recursive oram afterwards.
+ */
+pub struct FlexOmap<
+    S: BlockStorage = MemStore,
+    O: Oram<PosMapBkt> = AutoOram<PosMapBkt, BKT_PER_PAGE, S>,
+> {
+    flexoram: FlexOram<S>,
+    pos_map: CuckooHashMap<usize, BKT_SIZE, BKT_PER_PAGE, O>,
+    key_storage: KeyStorage,
+    memory_budget: Option<usize>,
+    rand_gen: RandGen, // draws the positions of the entries
 }
 
 impl FlexOmap {
     pub fn new() -> Self {
+        Self::with_key_storage(KeyStorage::HashOnly)
+    }
+
+    pub fn with_key_storage(key_storage: KeyStorage) -> Self {
+        Self::with_options(
+            key_storage,
+            LINEAR_SCAN_THRESHOLD,
+            EvictionMode::Greedy,
+            OramScheme::Path,
+            None,
+            None,
+            RandGen::new(),
+        )
+    }
+}
+
+impl<S: BlockStorage> FlexOmap<S> {
+    /**
+     * With `memory_budget` set, the trusted memory is checked before every write, see `check_memory_budget`. All the
+     * randomness of the map is drawn from sources forked from `rand_gen`. `scheme` selects the tree orams of the
+     * position map once it no longer fits a linear scan.
+     */
+    pub fn with_options(
+        key_storage: KeyStorage,
+        linear_scan_threshold: usize,
+        eviction: EvictionMode,
+        scheme: OramScheme,
+        background_eviction: Option<usize>,
+        memory_budget: Option<usize>,
+        mut rand_gen: RandGen,
+    ) -> Self {
         Self {
-            flexoram: FlexOram::new(),
-            pos_map: CuckooHashMap::new(),
+            flexoram: FlexOram::with_options(eviction, background_eviction, rand_gen.fork()),
+            pos_map: CuckooHashMap::with_options(
+                linear_scan_threshold,
+                eviction,
+                scheme,
+                background_eviction,
+                rand_gen.fork(),
+            ),
+            key_storage,
+            memory_budget,
+            rand_gen,
         }
     }
+}
 
-    pub fn insert<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, value: V) -> Option<Vec<u8>> {
-        let new_page_id = rand::random::<usize>();
+impl<S: BlockStorage, O: Oram<PosMapBkt>> FlexOmap<S, O> {
+    pub fn with_backend(key_storage: KeyStorage, mut rand_gen: RandGen) -> Self {
+        Self {
+            flexoram: FlexOram::with_options(EvictionMode::Greedy, None, rand_gen.fork()),
+            pos_map: CuckooHashMap::with_backend(rand_gen.fork()),
+            key_storage,
+            memory_budget: None,
+            rand_gen,
+        }
+    }
+
+    // the key tags are stored in front of the values, so they are counted with the blocks in the stash and the trees
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.flexoram.memory_usage() + self.pos_map.memory_usage()
+    }
+
+    /**
+     * Write every page under `key` from now on. The pages under the previous key are rewritten a few at a time on
+     * every access of each tree, in a fixed order, so the rotation does not block and does not depend on the keys
+     * accessed. A key rotated to while a rotation is still in progress is queued until it ends.
+     */
+    pub fn rotate_key(&mut self, key: &StorageKey) {
+        self.flexoram.rotate_key(key);
+        self.pos_map.rotate_key(key);
+    }
+
+    /**
+     * Rewrite all the pages still under the previous key at once. Returns the number of pages visited, 0 if the
+     * accesses have already completed the rotation.
+     */
+    pub fn finish_key_rotation(&mut self) -> usize {
+        let mut swept = 0;
+        loop {
+            let pages = self.flexoram.sweep_keys(MIN_SEGMENT_SIZE)
+                + self.pos_map.sweep_keys(MIN_SEGMENT_SIZE);
+            if pages == 0 {
+                return swept;
+            }
+            swept += pages;
+        }
+    }
+
+    /**
+     * Once the trusted memory reaches 90% of the budget, move memory out of the enclave until it is back under that
+     * mark or nothing is left to move. Fails if the budget is exceeded anyway, before the write is performed.
+     */
+    fn check_memory_budget(&mut self) -> Result<(), DBError> {
+        let budget = match self.memory_budget {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+        let mut usage = self.memory_usage().total();
+        while usage * 10 > budget * 9 {
+            let flexoram_limited = self.flexoram.limit_top_layer();
+            if !self.pos_map.shrink_memory() && !flexoram_limited {
+                break;
+            }
+            usage = self.memory_usage().total();
+        }
+        if usage > budget {
+            return Err(DBError::MemoryBudgetExceeded { usage, budget });
+        }
+        Ok(())
+    }
+
+    // bytes stored in front of the value to identify the key:
+    // nothing, a fingerprint, or [key len (u16, little endian)][key] if the full key is stored
+    fn key_tag(&self, key: &[u8]) -> Vec<u8> {
+        match self.key_storage {
+            KeyStorage::HashOnly => Vec::new(),
+            KeyStorage::Fingerprint => self.pos_map.key_fingerprint(key).to_vec(),
+            KeyStorage::Full => {
+                assert!(key.len() <= u16::MAX as usize, "key too long");
+                let mut tag = Vec::with_capacity(2 + key.len());
+                tag.extend_from_slice(&(key.len() as u16).to_le_bytes());
+                tag.extend_from_slice(key);
+                tag
+            }
+        }
+    }
+
+    fn split_key<'a>(&self, raw: &'a [u8]) -> (Option<&'a [u8]>, usize) {
+        match self.key_storage {
+            KeyStorage::HashOnly => (None, 0),
+            KeyStorage::Fingerprint => (None, FINGERPRINT_SIZE),
+            KeyStorage::Full => {
+                let key_len = u16::from_le_bytes([raw[0], raw[1]]) as usize;
+                (Some(&raw[2..2 + key_len]), 2 + key_len)
+            }
+        }
+    }
+
+    // the access of the oram: verify the tag of the entry found, strip it from the returned value,
+    // and keep the entry untouched if it belongs to another key
+    fn verified_update<F>(
+        &mut self,
+        hash_entry: &HashEntry<usize>,
+        key_tag: &[u8],
+        update_func: F,
+        new_page_id: usize,
+    ) -> Result<Option<Vec<u8>>, DBError>
+    where
+        F: FnOnce(Option<Vec<u8>>) -> Option<Vec<u8>>,
+    {
+        let mut ret = Ok(None);
+        // the raw values stay in buffers wiped when dropped, only the copies handed to the caller are not
+        let verified_update_func = |raw: Option<Zeroizing<Vec<u8>>>| match raw {
+            Some(raw) if !ct_starts_with(&raw, key_tag) => {
+                ret = Err(DBError::KeyMismatch);
+                Some(raw)
+            }
+            raw => {
+                let old_value = raw.map(|raw| raw[key_tag.len()..].to_vec());
+                ret = Ok(old_value.clone());
+                update_func(old_value).map(|value| {
+                    let value = Zeroizing::new(value);
+                    Zeroizing::new([key_tag, &value].concat())
+                })
+            }
+        };
+        self.flexoram
+            .update(hash_entry, verified_update_func, new_page_id)?;
+        ret
+    }
+
+    // on a stash overflow the entry stays where it was, so put its position back in the position map, or remove the
+    // position inserted for a new key
+    fn restore_position(&mut self, hash_entry: &mut HashEntry<usize>, old_page_id: Option<usize>) {
+        match old_page_id {
+            Some(id) => {
+                hash_entry.set_val(id);
+                self.pos_map.insert_hash_entry(hash_entry);
+            }
+            None => {
+                self.pos_map.remove_hash_entry(hash_entry);
+            }
+        }
+    }
+
+    pub fn try_insert<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> Result<Option<Vec<u8>>, DBError> {
+        self.check_memory_budget()?;
+        let new_page_id = self.rand_gen.gen::<usize>();
         // println!("key {:?} insert to new page id {:?}", key, new_page_id);
-        let mut hash_entry = self.pos_map.compute_hash_entry(key, new_page_id);
+        let mut hash_entry = self.pos_map.compute_hash_entry(&key, new_page_id);
 
         let old_page_id_option = self.pos_map.insert_hash_entry(&hash_entry);
         let old_page_id = match old_page_id_option {
             Some(id) => id,
-            None => rand::random::<usize>(),
+            None => self.rand_gen.gen::<usize>(),
         };
         hash_entry.set_val(old_page_id);
 
-        self.flexoram
-            .read_and_write(&hash_entry, value, new_page_id)
+        let key_tag = self.key_tag(key.as_ref());
+        let overwrite_func = |_| Some(value.as_ref().to_vec());
+        let ret = self.verified_update(&hash_entry, &key_tag, overwrite_func, new_page_id);
+        if ret == Err(DBError::StashOverflow) {
+            self.restore_position(&mut hash_entry, old_page_id_option);
+        }
+        ret
     }
 
-    pub fn get<K: AsRef<[u8]>>(&mut self, key: K) -> Option<Vec<u8>> {
-        let new_page_id = rand::random::<usize>();
+    pub fn try_get<K: AsRef<[u8]>>(&mut self, key: K) -> Result<Option<Vec<u8>>, DBError> {
+        self.try_get_with(key, |value| value.to_vec())
+    }
+
+    /**
+     * Same access pattern as `try_get`, but only returns what `f` extracts from the value, without copying it out.
+     */
+    pub fn try_get_with<K: AsRef<[u8]>, R, F: FnOnce(&[u8]) -> R>(
+        &mut self,
+        key: K,
+        f: F,
+    ) -> Result<Option<R>, DBError> {
+        let new_page_id = self.rand_gen.gen::<usize>();
         // println!("key {:?} get and insert to new pos {:?}", key, new_page_id);
-        let mut hash_entry = self.pos_map.compute_hash_entry(key, new_page_id);
+        let mut hash_entry = self.pos_map.compute_hash_entry(&key, new_page_id);
         let old_page_id_option = self.pos_map.update_hash_entry(&hash_entry);
         // println!("old_page_id_option: {:?}", old_page_id_option);
         let old_page_id = match old_page_id_option {
             Some(id) => id,
-            None => rand::random::<usize>(),
+            None => self.rand_gen.gen::<usize>(),
         };
         hash_entry.set_val(old_page_id);
-        self.flexoram.read(&hash_entry, new_page_id)
+        let key_tag = self.key_tag(key.as_ref());
+        let mut ret = Ok(None);
+        let read_func = |raw: Option<Zeroizing<Vec<u8>>>| {
+            match &raw {
+                Some(raw) if !ct_starts_with(raw, &key_tag) => ret = Err(DBError::KeyMismatch),
+                Some(raw) => ret = Ok(Some(f(&raw[key_tag.len()..]))),
+                None => {}
+            }
+            raw
+        };
+        if let Err(err) = self.flexoram.update(&hash_entry, read_func, new_page_id) {
+            self.restore_position(&mut hash_entry, old_page_id_option);
+            return Err(err);
+        }
+        ret
     }
 
-    pub fn remove<K: AsRef<[u8]>>(&mut self, key: K) -> Option<Vec<u8>> {
-        let new_page_id = rand::random::<usize>();
+    pub fn try_remove<K: AsRef<[u8]>>(&mut self, key: K) -> Result<Option<Vec<u8>>, DBError> {
+        let new_page_id = self.rand_gen.gen::<usize>();
         // println!("key {:?} get and insert to new pos {:?}", key, new_page_id);
-        let mut hash_entry = self.pos_map.compute_hash_entry(key, new_page_id);
+        let mut hash_entry = self.pos_map.compute_hash_entry(&key, new_page_id);
         let old_page_id_option = self.pos_map.remove_hash_entry(&hash_entry);
         // println!("old_page_id_option: {:?}", old_page_id_option);
         let old_page_id = match old_page_id_option {
             Some(id) => id,
-            None => rand::random::<usize>(),
+            None => self.rand_gen.gen::<usize>(),
         };
         hash_entry.set_val(old_page_id);
-        self.flexoram.remove(&hash_entry)
+        let key_tag = self.key_tag(key.as_ref());
+        let ret = self.verified_update(&hash_entry, &key_tag, |_| None, new_page_id);
+        if ret == Err(DBError::StashOverflow) {
+            self.restore_position(&mut hash_entry, old_page_id_option);
+        } else if ret.is_err() {
+            // the entry of the other key is kept, so restore its position
+            hash_entry.set_val(new_page_id);
+            self.pos_map.insert_hash_entry(&hash_entry);
+        }
+        ret
+    }
+
+    /**
+     * Read-modify-write the value of a key with a single access of the oram. Returns the old value and whether the
+     * key exists afterwards. The position map is accessed twice in any case, so the pattern does not reveal whether
+     * the key is created, kept or deleted.
+     */
+    pub fn try_update<K: AsRef<[u8]>, F>(
+        &mut self,
+        key: K,
+        update_func: F,
+    ) -> Result<(Option<Vec<u8>>, bool), DBError>
+    where
+        F: FnOnce(Option<Vec<u8>>) -> Option<Vec<u8>>,
+    {
+        self.check_memory_budget()?;
+        let new_page_id = self.rand_gen.gen::<usize>();
+        let mut hash_entry = self.pos_map.compute_hash_entry(&key, new_page_id);
+        let old_page_id_option = self.pos_map.insert_hash_entry(&hash_entry);
+        let old_page_id = match old_page_id_option {
+            Some(id) => id,
+            None => self.rand_gen.gen::<usize>(),
+        };
+        hash_entry.set_val(old_page_id);
+
+        let key_tag = self.key_tag(key.as_ref());
+        let mut exist_flag = false;
+        let tracked_update_func = |old_value| {
+            let new_value = update_func(old_value);
+            exist_flag = new_value.is_some();
+            new_value
+        };
+        let ret = self.verified_update(&hash_entry, &key_tag, tracked_update_func, new_page_id);
+        if ret == Err(DBError::StashOverflow) {
+            self.restore_position(&mut hash_entry, old_page_id_option);
+            return Err(DBError::StashOverflow);
+        }
+        hash_entry.set_val(new_page_id);
+        if ret.is_ok() && !exist_flag {
+            self.pos_map.remove_hash_entry(&hash_entry);
+        } else {
+            // dummy access
+            self.pos_map.update_hash_entry(&hash_entry);
+        }
+        ret.map(|old_value| (old_value, exist_flag))
+    }
+
+    pub fn insert<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, value: V) -> Option<Vec<u8>> {
+        self.try_insert(key, value).expect("key mismatch")
+    }
+
+    pub fn get<K: AsRef<[u8]>>(&mut self, key: K) -> Option<Vec<u8>> {
+        self.try_get(key).expect("key mismatch")
+    }
+
+    pub fn remove<K: AsRef<[u8]>>(&mut self, key: K) -> Option<Vec<u8>> {
+        self.try_remove(key).expect("key mismatch")
+    }
+
+    /**
+     * Load the given key-value pairs into an empty map, building the position map and the oram directly instead of
+     * inserting the pairs one by one. If a key appears multiple times, the last value is kept. Fails on keys whose
+     * hashes collide if a key tag is stored. The page access pattern depends on the number of pairs and on their
+     * total size, see `bulk_load_padded` to hide the sizes.
+     */
+    pub fn bulk_load<K: AsRef<[u8]>, V: AsRef<[u8]>, I: IntoIterator<Item = (K, V)>>(
+        &mut self,
+        items: I,
+    ) -> Result<(), DBError> {
+        self.bulk_load_padded(items, None)
+    }
+
+    /**
+     * Same as `bulk_load`, and with `max_item_len` set, the oram is sized as if every key and value together had
+     * `max_item_len` bytes, so that the page access pattern only depends on the number of pairs. Fails with
+     * `DBError::InvalidValue` on a longer pair or if the map is not empty, before anything is loaded.
+     */
+    pub fn bulk_load_padded<K: AsRef<[u8]>, V: AsRef<[u8]>, I: IntoIterator<Item = (K, V)>>(
+        &mut self,
+        items: I,
+        max_item_len: Option<usize>,
+    ) -> Result<(), DBError> {
+        if self.size() != 0 {
+            return Err(DBError::InvalidValue(format!(
+                "bulk load into a map holding {} entries",
+                self.size()
+            )));
+        }
+        self.check_memory_budget()?;
+        let mut entry_offsets = HashMap::new();
+        let mut entries = Vec::new();
+        for (key, value) in items {
+            let item_len = key.as_ref().len() + value.as_ref().len();
+            if let Some(max_item_len) = max_item_len.filter(|max| item_len > *max) {
+                return Err(DBError::InvalidValue(format!(
+                    "item of {} bytes exceeds the maximum of {} bytes",
+                    item_len, max_item_len
+                )));
+            }
+            let hash_entry = self
+                .pos_map
+                .compute_hash_entry(&key, self.rand_gen.gen::<usize>());
+            let key_tag = self.key_tag(key.as_ref());
+            let raw_value = [&key_tag, value.as_ref()].concat();
+            match entry_offsets.entry(hash_entry.get_idx()) {
+                Entry::Occupied(offset) => {
+                    let (_, old_value): &mut (_, Vec<u8>) = &mut entries[*offset.get()];
+                    if !ct_starts_with(old_value, &key_tag) {
+                        return Err(DBError::KeyMismatch);
+                    }
+                    old_value.zeroize();
+                    *old_value = raw_value;
+                }
+                Entry::Vacant(offset) => {
+                    offset.insert(entries.len());
+                    entries.push((hash_entry, raw_value));
+                }
+            }
+        }
+        self.pos_map
+            .bulk_build(entries.iter().map(|(hash_entry, _)| *hash_entry).collect());
+        // the tag in front of a value is at most its key with the key length, or a fingerprint
+        let max_tag_overhead = match self.key_storage {
+            KeyStorage::HashOnly => 0,
+            KeyStorage::Fingerprint => FINGERPRINT_SIZE,
+            KeyStorage::Full => std::mem::size_of::<u16>(),
+        };
+        self.flexoram
+            .bulk_build(entries, max_item_len.map(|len| len + max_tag_overhead));
+        Ok(())
+    }
+
+    /**
+     * Visit every key-value pair by sweeping over all the pages and the stash of the oram.
+     * Requires the full keys to be stored.
+     */
+    pub fn for_each<F: FnMut(&[u8], &[u8])>(&self, mut f: F) {
+        assert_eq!(
+            self.key_storage,
+            KeyStorage::Full,
+            "enumerating entries requires the full keys to be stored"
+        );
+        self.flexoram.for_each(|_, raw| {
+            let (key, value_offset) = self.split_key(raw);
+            f(key.unwrap(), &raw[value_offset..]);
+        });
     }
 
     pub fn size(&self) -> usize {
@@ -80,8 +493,10 @@ impl FlexOmap {
     // }
 }
 
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::oblivious::linearoram::LinearOram;
 
     #[test]
     fn test_flex_omap_simple() {
@@ -94,6 +509,30 @@ mod tests {
         assert_eq!(result, Some(value));
     }
 
+    #[test]
+    fn test_seeded_randomness() {
+        // the positions of the keys after the same accesses, including those of the doublings of the tables
+        let positions = |seed: u64| {
+            let mut map: FlexOmap = FlexOmap::with_options(
+                KeyStorage::HashOnly,
+                LINEAR_SCAN_THRESHOLD,
+                EvictionMode::Greedy,
+                OramScheme::Path,
+                None,
+                None,
+                RandGen::from_seed(seed),
+            );
+            for i in 0..1000 {
+                map.insert(i.to_string(), [i as u8; 16]);
+            }
+            (0..1000)
+                .map(|i| map.pos_map.get(i.to_string()).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(positions(1), positions(1));
+        assert_ne!(positions(1), positions(2));
+    }
+
     #[test]
     fn test_flex_omap_dup() {
         let mut flex_omap = FlexOmap::new();
@@ -148,6 +587,261 @@ mod tests {
         map.print_meta_state();
     }
 
+    #[test]
+    fn linear_backend_test() {
+        let mut map = FlexOmap::<MemStore, LinearOram<PosMapBkt, BKT_PER_PAGE>>::with_backend(
+            KeyStorage::Full,
+            RandGen::from_seed(7),
+        );
+        for i in 0..1000 {
+            map.insert(i.to_string(), vec![i as u8; i % 100]);
+        }
+        map.remove("3");
+        for i in 0..1000 {
+            let expected = if i == 3 {
+                None
+            } else {
+                Some(vec![i as u8; i % 100])
+            };
+            assert_eq!(map.get(i.to_string()), expected);
+        }
+        let mut count = 0;
+        map.for_each(|_, _| count += 1);
+        assert_eq!(999, count);
+    }
+
+    #[test]
+    fn bulk_load_test() {
+        let mut map = FlexOmap::new();
+        let size = 100000;
+        map.bulk_load((0..size).map(|i| (i.to_string(), vec![i as u8; i % 100])))
+            .unwrap();
+        assert_eq!(size, map.size());
+        for i in 0..size {
+            assert_eq!(map.get(i.to_string()), Some(vec![i as u8; i % 100]));
+        }
+        for i in 0..size / 2 {
+            let res = map.insert(i.to_string(), vec![(i + 1) as u8; 40]);
+            assert_eq!(res, Some(vec![i as u8; i % 100]));
+            let remove_key = (i + size / 2).to_string();
+            assert_eq!(
+                map.remove(remove_key),
+                Some(vec![(i + size / 2) as u8; (i + size / 2) % 100])
+            );
+        }
+        for i in 0..size / 2 {
+            assert_eq!(map.get(i.to_string()), Some(vec![(i + 1) as u8; 40]));
+        }
+        assert_eq!(size / 2, map.size());
+    }
+
+    #[test]
+    fn bulk_load_padded_test() {
+        let mut short = FlexOmap::new();
+        let mut long = FlexOmap::new();
+        let size = 20000;
+        short
+            .bulk_load_padded((0..size).map(|i| (i.to_string(), vec![1; 10])), Some(500))
+            .unwrap();
+        long.bulk_load_padded((0..size).map(|i| (i.to_string(), vec![2; 400])), Some(500))
+            .unwrap();
+        // the trees only depend on the number of items
+        assert_eq!(
+            short.flexoram.memory_usage().memory_storage,
+            long.flexoram.memory_usage().memory_storage
+        );
+        assert_eq!(short.get("7"), Some(vec![1; 10]));
+        assert_eq!(long.get("7"), Some(vec![2; 400]));
+        let mut map = FlexOmap::new();
+        let err = map
+            .bulk_load_padded(vec![("a", vec![0; 100])], Some(10))
+            .unwrap_err();
+        assert!(matches!(err, DBError::InvalidValue(_)));
+        assert_eq!(map.size(), 0);
+    }
+
+    #[test]
+    fn bulk_load_dup_test() {
+        let mut map = FlexOmap::new();
+        map.bulk_load(vec![("a", vec![1]), ("b", vec![2]), ("a", vec![3])])
+            .unwrap();
+        assert_eq!(2, map.size());
+        assert_eq!(map.get("a"), Some(vec![3]));
+        assert_eq!(map.get("b"), Some(vec![2]));
+    }
+
+    #[test]
+    fn bulk_load_non_empty_test() {
+        let mut map = FlexOmap::new();
+        map.insert("a", vec![1]);
+        let err = map.bulk_load(vec![("b", vec![2])]).unwrap_err();
+        assert!(matches!(err, DBError::InvalidValue(_)));
+        assert_eq!(1, map.size());
+        assert_eq!(map.get("a"), Some(vec![1]));
+        assert_eq!(map.get("b"), None);
+    }
+
+    #[test]
+    fn for_each_test() {
+        let mut map = FlexOmap::with_key_storage(KeyStorage::Full);
+        let mut ref_map = std::collections::HashMap::new();
+        let size = 30000;
+        for i in 0..size {
+            map.insert(i.to_string(), vec![i as u8; i % 300]);
+            ref_map.insert(i.to_string().into_bytes(), vec![i as u8; i % 300]);
+        }
+        for i in (0..size).step_by(3) {
+            assert_eq!(
+                map.remove(i.to_string()),
+                ref_map.remove(i.to_string().as_bytes())
+            );
+            let key = (i + 1).to_string();
+            let res = map.insert(&key, vec![7; 10]);
+            assert_eq!(res, ref_map.insert(key.into_bytes(), vec![7; 10]));
+        }
+        for i in 0..size {
+            let key = i.to_string();
+            assert_eq!(map.get(&key), ref_map.get(key.as_bytes()).cloned());
+        }
+        let mut visited = std::collections::HashMap::new();
+        map.for_each(|key, value| {
+            assert!(visited.insert(key.to_vec(), value.to_vec()).is_none());
+        });
+        assert_eq!(visited, ref_map);
+    }
+
+    #[test]
+    fn full_key_tag_test() {
+        // the stored tags do not depend on the endianness of the host
+        let map = FlexOmap::with_key_storage(KeyStorage::Full);
+        let key = vec![7u8; 258];
+        let tag = map.key_tag(&key);
+        assert_eq!(tag[..2], [2, 1]);
+        assert_eq!(map.split_key(&tag), (Some(&key[..]), 260));
+    }
+
+    #[test]
+    fn for_each_bulk_load_test() {
+        let mut map = FlexOmap::with_key_storage(KeyStorage::Full);
+        map.bulk_load((0..1000).map(|i| (i.to_string(), vec![i as u8; 8])))
+            .unwrap();
+        let mut count = 0;
+        map.for_each(|key, value| {
+            let i: usize = std::str::from_utf8(key).unwrap().parse().unwrap();
+            assert_eq!(value, vec![i as u8; 8]);
+            count += 1;
+        });
+        assert_eq!(count, 1000);
+    }
+
+    #[test]
+    fn update_test() {
+        let mut map = FlexOmap::with_key_storage(KeyStorage::Fingerprint);
+        for i in 0..1000 {
+            map.insert(i.to_string(), vec![i as u8; 4]);
+        }
+        for i in 0..2000 {
+            let res = map.try_update(i.to_string(), |old| match old {
+                Some(mut value) if i % 2 == 0 => {
+                    value.push(1);
+                    Some(value)
+                }
+                Some(_) => None,
+                None => Some(vec![0]),
+            });
+            let expected_old = if i < 1000 {
+                Some(vec![i as u8; 4])
+            } else {
+                None
+            };
+            assert_eq!(res, Ok((expected_old, i >= 1000 || i % 2 == 0)));
+        }
+        for i in 0..2000 {
+            let expected = match i {
+                i if i >= 1000 => Some(vec![0]),
+                i if i % 2 == 0 => Some(vec![i as u8, i as u8, i as u8, i as u8, 1]),
+                _ => None,
+            };
+            assert_eq!(map.get(i.to_string()), expected);
+        }
+        assert_eq!(1500, map.size());
+    }
+
+    #[test]
+    fn get_with_test() {
+        let mut map = FlexOmap::with_key_storage(KeyStorage::Fingerprint);
+        for i in 0..500 {
+            map.insert(i.to_string(), vec![1; i]);
+        }
+        for i in 0..1000 {
+            let expected = if i < 500 { Some(i) } else { None };
+            assert_eq!(
+                map.try_get_with(i.to_string(), |value| value.len()),
+                Ok(expected)
+            );
+        }
+        alias_key(&mut map, "a", "b", b"value");
+        assert_eq!(map.try_get_with("b", |_| ()), Err(DBError::KeyMismatch));
+        assert_eq!(map.try_get("b"), Err(DBError::KeyMismatch));
+    }
+
+    // make key b resolve to the entry of key a, as if their hashes collided
+    #[cfg(test)]
+    fn alias_key(map: &mut FlexOmap, a: &str, b: &str, value: &[u8]) {
+        let page_id = map.rand_gen.gen::<usize>();
+        let mut hash_entry = map.pos_map.compute_hash_entry(b, page_id);
+        map.pos_map.insert_hash_entry(&hash_entry);
+        hash_entry.set_val(map.rand_gen.gen::<usize>());
+        let raw_value = [&map.key_tag(a.as_bytes()), value].concat();
+        map.flexoram.write(&hash_entry, &raw_value, page_id);
+    }
+
+    #[test]
+    fn key_mismatch_test() {
+        for key_storage in [KeyStorage::Fingerprint, KeyStorage::Full] {
+            let mut map = FlexOmap::with_key_storage(key_storage);
+            for i in 0..100 {
+                map.insert(i.to_string(), vec![i as u8; 10]);
+            }
+            alias_key(&mut map, "a", "b", &[1, 2, 3]);
+            assert_eq!(map.try_get("b"), Err(DBError::KeyMismatch));
+            assert_eq!(map.try_insert("b", [4]), Err(DBError::KeyMismatch));
+            assert_eq!(map.try_remove("b"), Err(DBError::KeyMismatch));
+            assert_eq!(map.try_get("b"), Err(DBError::KeyMismatch));
+            assert_eq!(map.try_get("a"), Ok(None));
+            for i in 0..100 {
+                assert_eq!(map.try_get(i.to_string()), Ok(Some(vec![i as u8; 10])));
+            }
+            assert_eq!(101, map.size());
+        }
+    }
+
+    #[test]
+    fn key_mismatch_hash_only_test() {
+        let mut map = FlexOmap::new();
+        alias_key(&mut map, "a", "b", &[1, 2, 3]);
+        assert_eq!(map.try_get("b"), Ok(Some(vec![1, 2, 3])));
+    }
+
+    #[test]
+    fn fingerprint_test() {
+        let mut map = FlexOmap::with_key_storage(KeyStorage::Fingerprint);
+        for i in 0..10000 {
+            map.insert(i.to_string(), vec![i as u8; i % 100]);
+        }
+        for i in 0..5000 {
+            assert_eq!(map.remove(i.to_string()), Some(vec![i as u8; i % 100]));
+        }
+        for i in 0..10000 {
+            let expected = if i < 5000 {
+                None
+            } else {
+                Some(vec![i as u8; i % 100])
+            };
+            assert_eq!(map.get(i.to_string()), expected);
+        }
+    }
+
     #[test]
     fn omap_fix_size() {
         let mut map = FlexOmap::new();
@@ -204,4 +898,37 @@ mod tests {
         assert_eq!(size, map.size());
         map.print_meta_state();
     }
+
+    #[test]
+    fn test_flex_omap_memory_budget() {
+        // the pages of the trees are kept in the memory of the process, so they count towards the budget
+        let initial = FlexOmap::new().memory_usage();
+        assert!(initial.memory_storage > 0);
+        assert_eq!(initial.untrusted_storage, 0);
+        let budget = initial.total() + (1 << 19);
+        let mut map: FlexOmap = FlexOmap::with_options(
+            KeyStorage::HashOnly,
+            LINEAR_SCAN_THRESHOLD,
+            EvictionMode::Greedy,
+            OramScheme::Path,
+            None,
+            Some(budget),
+            RandGen::from_seed(7),
+        );
+        assert!(map.memory_usage().total() < budget);
+        // the stashes and the trees grow with the entries, until the writes are refused
+        let mut num_inserted = 0;
+        let err = loop {
+            match map.try_insert(num_inserted.to_string(), [1u8; 8]) {
+                Ok(_) => num_inserted += 1,
+                Err(err) => break err,
+            }
+        };
+        assert!(num_inserted > 0);
+        assert!(matches!(err, DBError::MemoryBudgetExceeded { budget: b, .. } if b == budget));
+        assert_eq!(map.size(), num_inserted);
+        for i in 0..num_inserted {
+            assert_eq!(map.get(i.to_string()), Some(vec![1u8; 8]));
+        }
+    }
 }
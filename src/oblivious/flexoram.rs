@@ -6,11 +6,29 @@
 use std::vec;
 
 use super::cuckoo::HashEntry;
-use crate::params::{KEY_SIZE, MAX_CACHE_SIZE, MIN_SEGMENT_SIZE, PAGE_SIZE};
+use super::evict::EvictionMode;
+use super::memory::MemoryUsage;
+use crate::error::DBError;
+use crate::params::{
+    KEY_SIZE, KEY_SWEEP_RATE, MAX_CACHE_SIZE, MIN_SEGMENT_SIZE, OBLIVIOUS_STASH_BYTES,
+    OBLIVIOUS_STASH_SIZE, PAGE_SIZE,
+};
+use crate::storage::memstore::MemStore;
+use crate::storage::storage::BlockStorage;
+use crate::tree::cipher::{CipherKind, StorageKey};
 use crate::tree::dynamictree::{calc_deepest, ORAMTree};
+use crate::tree::forkvec::ForkVec;
+use crate::utils::ct::{cmov, ct_eq, ct_eq_bytes, ct_lt, ct_select};
+use crate::utils::osort::{bitonic_sort_by_key, compact_bytes};
+use crate::utils::utils::{wipe_vec, RandGen};
 use bytemuck::{Pod, Zeroable};
+use zeroize::Zeroizing;
 
 const BUFFER_SIZE: usize = PAGE_SIZE - 2 * std::mem::size_of::<u16>() - KEY_SIZE;
+// every entry takes at least its meta data and its length
+const MAX_ENTRY_PER_PAGE: usize = BUFFER_SIZE / (std::mem::size_of::<HashEntry<usize>>() + 2);
+// the deepest level of an entry that is dropped during an access, past any path
+const DROPPED_LEVEL: u8 = u8::MAX;
 #[repr(C)]
 #[derive(Clone, Copy)]
 struct Page {
@@ -82,19 +100,54 @@ impl Page {
         true
     }
 
-    fn read_entry_and_retrieve_rest(
+    // entries are packed without padding, so the meta data may be unaligned
+    fn read_meta(&self, offset: usize) -> HashEntry<usize> {
+        unsafe {
+            std::ptr::read_unaligned(self.buffer.as_ptr().add(offset) as *const HashEntry<usize>)
+        }
+    }
+
+    // visit the entries in the page that belong to the page at idx of a layer with the given size
+    fn for_each_valid_entry<F: FnMut(&HashEntry<usize>, &[u8])>(
+        &self,
+        idx: usize,
+        layer_size: usize,
+        f: &mut F,
+    ) {
+        const META_SIZE: usize = std::mem::size_of::<HashEntry<usize>>();
+        let mut ptr = 0;
+        while ptr < self.filled_bytes as usize {
+            let entry_size = u16::from_ne_bytes([
+                self.buffer[ptr + META_SIZE],
+                self.buffer[ptr + META_SIZE + 1],
+            ]) as usize;
+            let next_ptr = ptr + META_SIZE + 2 + entry_size;
+            let meta_data = self.read_meta(ptr);
+            // after fork, a page may still hold entries that belong to its sibling
+            if meta_data.get_val() % layer_size == idx {
+                f(&meta_data, &self.buffer[ptr + META_SIZE + 2..next_ptr]);
+            }
+            ptr = next_ptr;
+        }
+    }
+
+    // compares every entry in constant time and returns whether the entry is found, with the offset and the length
+    // of its value; the other entries are added to rest
+    fn find_entry_and_retrieve_rest(
         &self,
         meta_data: &HashEntry<usize>,
         rest: &mut Vec<SortEntry>,
         self_level: u8,
         layer_log_sizes: &Vec<u8>,
-    ) -> Option<Vec<u8>> {
+    ) -> (bool, usize, usize) {
         let mut ptr = 0 as usize;
         const META_SIZE: usize = std::mem::size_of::<HashEntry<usize>>();
         let meta_bytes = unsafe {
             std::slice::from_raw_parts(meta_data as *const HashEntry<usize> as *const u8, META_SIZE)
         };
-        let mut ret: Option<Vec<u8>> = None;
+        let mut found_flag = false;
+        let mut value_offset = 0;
+        let mut value_len = 0;
         while ptr < self.filled_bytes as usize {
             // compare meta data with the bytes starting from page[ptr]
             let entry_size = u16::from_ne_bytes([
@@ -103,42 +156,31 @@ impl Page {
             ]) as usize;
             let full_entry_size = (META_SIZE + 2 + entry_size) as u16;
             let next_ptr = ptr + full_entry_size as usize;
-            if ret == None && &self.buffer[ptr..ptr + META_SIZE] == meta_bytes {
-                // found the entry
-                let value = self.buffer[ptr + META_SIZE + 2..next_ptr].to_vec();
-                ret = Some(value);
-            } else {
-                // let self_idx = self.buffer[ptr..ptr + META_SIZE]
-                let entry_meta_bytes = unsafe {
-                    std::slice::from_raw_parts(
-                        self.buffer.as_ptr().offset(ptr as isize) as *const u8,
-                        META_SIZE,
-                    )
-                };
-                let entry_meta =
-                    unsafe { &*(entry_meta_bytes.as_ptr() as *const HashEntry<usize>) };
-                let self_idx = entry_meta.get_val();
-                let deepest = calc_deepest(self_idx, meta_data.get_val(), layer_log_sizes);
-                if deepest <= self_level as u8 {
-                    // after fork, some entries may become invalid, i.e., cannot be placed in the current page
-                    // we simply remove them
-                    rest.push(SortEntry {
-                        deepest,
-                        src: self_level,
-                        len: full_entry_size,
-                        offset: ptr as u32,
-                    });
-                }
-            }
+            let hit = ct_eq_bytes(&self.buffer[ptr..ptr + META_SIZE], meta_bytes) & !found_flag;
+            cmov(hit, &mut value_offset, &(ptr + META_SIZE + 2));
+            cmov(hit, &mut value_len, &entry_size);
+            found_flag |= hit;
+            let self_idx = self.read_meta(ptr).get_val();
+            let deepest = calc_deepest(self_idx, meta_data.get_val(), layer_log_sizes);
+            // the entry found is not kept, and after fork, some entries may become invalid, i.e., cannot be placed
+            // in the current page, we simply remove them: every entry is pushed, and those dropped get a level past
+            // the path, so they are never placed nor kept in the stash
+            let dropped = hit | ct_lt(self_level as usize, deepest as usize);
+            rest.push(SortEntry {
+                deepest: ct_select(dropped, DROPPED_LEVEL, deepest),
+                src: self_level,
+                len: full_entry_size,
+                offset: ptr as u32,
+            });
             ptr = next_ptr;
         }
-        ret
+        (found_flag, value_offset, value_len)
     }
 }
 
 #[derive(Clone)]
 struct StashEntry {
-    pub kvs: Vec<(HashEntry<usize>, Vec<u8>)>,
+    pub kvs: Vec<(HashEntry<usize>, Zeroizing<Vec<u8>>)>, // the values are wiped when dropped
 }
 
 impl StashEntry {
@@ -147,9 +189,11 @@ impl StashEntry {
     }
 }
 
+// one bucket per page of the top layer, which has at most MAX_CACHE_SIZE pages, so the stash metadata does not
+// grow with the number of pages
 struct Stash {
-    stash: Vec<StashEntry>,
-    versions: Vec<u8>,
+    stash: ForkVec<StashEntry>,
+    versions: ForkVec<u8>,
     size: usize,
     log_size: u8,
     num_bytes: usize,
@@ -157,13 +201,14 @@ struct Stash {
 }
 
 impl Stash {
-    const STASH_ENTRY_META_SIZE: usize = std::mem::size_of::<(HashEntry<usize>, Vec<u8>)>();
+    const STASH_ENTRY_META_SIZE: usize =
+        std::mem::size_of::<(HashEntry<usize>, Zeroizing<Vec<u8>>)>();
 
     pub fn new(init_size: usize) -> Self {
         assert!((init_size & (init_size - 1)) == 0); // must be power of 2
         let log_init_size = init_size.trailing_zeros() as u8;
-        let stash = vec![StashEntry::new(); init_size];
-        let versions = vec![log_init_size; init_size];
+        let stash = ForkVec::from_elem(StashEntry::new(), init_size);
+        let versions = ForkVec::from_elem(log_init_size, init_size);
         Self {
             stash,
             versions,
@@ -182,21 +227,10 @@ impl Stash {
             return;
         }
         assert_eq!(self.size, self.stash.len());
-        let old_size = self.size;
-        self.stash.resize(new_size, StashEntry::new());
-        self.versions.resize(new_size, 0u8);
-        // copy the versions
-        let scale_factor = new_size / old_size;
-        for i in 1..scale_factor {
-            unsafe {
-                std::ptr::copy(
-                    self.versions.as_ptr(),
-                    self.versions
-                        .as_mut_ptr()
-                        .offset(i as isize * old_size as isize),
-                    old_size,
-                );
-            }
+        // the versions and the entries are forked lazily, the entries are split on their first access anyway
+        while self.stash.len() < new_size {
+            self.stash.double_with(StashEntry::new());
+            self.versions.double_and_fork();
         }
         self.size = new_size;
         self.log_size = new_size.trailing_zeros() as u8;
@@ -223,11 +257,11 @@ impl Stash {
         }
     }
 
-    pub fn get_and_remove(&mut self, idx: usize) -> Vec<(HashEntry<usize>, Vec<u8>)> {
+    pub fn get_and_remove(&mut self, idx: usize) -> Vec<(HashEntry<usize>, Zeroizing<Vec<u8>>)> {
         let stash_idx = idx % self.size;
         self.split_entry(stash_idx); // potentially split the entry
         assert_eq!((1 << self.versions[stash_idx]), self.size);
-        let mut ret: Vec<(HashEntry<usize>, Vec<u8>)> = Vec::new();
+        let mut ret: Vec<(HashEntry<usize>, Zeroizing<Vec<u8>>)> = Vec::new();
         std::mem::swap(&mut self.stash[stash_idx].kvs, &mut ret);
         for (_, value) in ret.iter() {
             self.num_bytes -= value.len() + Self::STASH_ENTRY_META_SIZE;
@@ -236,7 +270,7 @@ impl Stash {
         ret
     }
 
-    pub fn insert(&mut self, idx: usize, entry: HashEntry<usize>, value: Vec<u8>) {
+    pub fn insert(&mut self, idx: usize, entry: HashEntry<usize>, value: Zeroizing<Vec<u8>>) {
         let stash_idx = idx % self.size;
         self.split_entry(stash_idx); // potentially split the entry
                                      // we don't perform merge during insert
@@ -245,7 +279,7 @@ impl Stash {
         self.stash[stash_idx].kvs.push((entry, value));
     }
 
-    pub fn concat(&mut self, idx: usize, entries: Vec<(HashEntry<usize>, Vec<u8>)>) {
+    pub fn concat(&mut self, idx: usize, entries: Vec<(HashEntry<usize>, Zeroizing<Vec<u8>>)>) {
         let stash_idx = idx % self.size;
         self.split_entry(stash_idx); // potentially split the entry
         self.num_kvs += entries.len();
@@ -271,13 +305,28 @@ impl Stash {
     pub fn num_bytes(&self) -> usize {
         self.num_bytes + self.stash.len() * std::mem::size_of::<StashEntry>()
     }
+
+    pub fn for_each<F: FnMut(&HashEntry<usize>, &[u8])>(&self, f: &mut F) {
+        for stash_entry in self.stash.iter() {
+            for (entry, value) in stash_entry.kvs.iter() {
+                f(entry, value);
+            }
+        }
+    }
 }
 
-pub struct FlexOram {
-    tree: ORAMTree<Page>,
+/**
+ * Oram of variable-size entries, whose tree pages are kept in storages of type `S`.
+ */
+pub struct FlexOram<S: BlockStorage = MemStore> {
+    tree: ORAMTree<Page, S>,
     stash: Stash,
     num_entry: usize,
     num_bytes: usize,
+    eviction: EvictionMode,
+    background_eviction: Option<usize>,
+    num_accesses: usize,
+    evict_count: usize, // number of paths evicted in reverse-lexicographic order
 }
 #[derive(Clone)]
 struct SortEntry {
@@ -287,35 +336,135 @@ struct SortEntry {
     offset: u32,
 }
 
+// an entry of the path or the stash during the oblivious eviction
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct EvictTag {
+    index: usize, // position of the tag when parsing
+    real: usize,
+    key: usize, // the deepest level of the entry when placing, and then the level it is placed in
+    len: usize, // size of the serialized entry
+    src: usize, // offset of the serialized entry in the bytes of the path and the stash, if any
+}
+
+unsafe impl Zeroable for EvictTag {}
+unsafe impl Pod for EvictTag {}
+
 impl FlexOram {
     pub fn new() -> Self {
+        Self::with_eviction(EvictionMode::Greedy)
+    }
+
+    pub fn with_eviction(eviction: EvictionMode) -> Self {
+        Self::with_options(eviction, None, RandGen::new())
+    }
+}
+
+impl<S: BlockStorage> FlexOram<S> {
+    /**
+     * With `background_eviction` set to k, one path in reverse-lexicographic order is also evicted every k
+     * accesses. The stash of a top-level page is then drained at least once every k times the number of top-level
+     * pages accesses, which bounds its size by the entries written back in that window, whichever paths the
+     * accesses take. The nonces of the tree are drawn from `rand_gen`.
+     */
+    pub fn with_options(
+        eviction: EvictionMode,
+        background_eviction: Option<usize>,
+        rand_gen: RandGen,
+    ) -> Self {
+        assert!(background_eviction != Some(0));
         Self {
-            tree: ORAMTree::new(MAX_CACHE_SIZE),
+            tree: ORAMTree::with_options(MAX_CACHE_SIZE, CipherKind::default(), rand_gen),
             stash: Stash::new(MIN_SEGMENT_SIZE),
             num_entry: 0,
             num_bytes: 0,
+            eviction,
+            background_eviction,
+            num_accesses: 0,
+            evict_count: 0,
         }
     }
 
-    pub fn update<F>(&mut self, entry: &HashEntry<usize>, update_func: F, new_page_id: usize)
-    where
-        F: FnOnce(Option<Vec<u8>>) -> Option<Vec<u8>>,
-    {
+    pub fn stash_len(&self) -> usize {
+        self.stash.num_kvs()
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            stash: self.stash.num_bytes(),
+            ..MemoryUsage::tree(&self.tree)
+        }
+    }
+
+    /**
+     * Stop growing the top layer of the tree, so that the tree grows by adding layers below it. The stash keeps one
+     * bucket per page of the top layer, so its metadata stops growing as well. Returns whether the top layer could
+     * still grow before.
+     */
+    pub fn limit_top_layer(&mut self) -> bool {
+        self.tree.limit_top_layer()
+    }
+
+    /**
+     * Write the pages under `key` from now on. The pages still under the previous key are rewritten a few at a time
+     * on every access, in a fixed order, see `ORAMTree::sweep_keys`.
+     */
+    pub fn rotate_key(&mut self, key: &StorageKey) {
+        self.tree.rotate_key(key);
+    }
+
+    pub fn sweep_keys(&mut self, max_pages: usize) -> usize {
+        self.tree.sweep_keys(max_pages)
+    }
+
+    fn retrieve(
+        &mut self,
+        entry: &HashEntry<usize>,
+    ) -> Result<Option<Zeroizing<Vec<u8>>>, DBError> {
+        match self.eviction {
+            EvictionMode::Greedy => Ok(self.retrieve_greedy(entry)),
+            EvictionMode::Oblivious => self.retrieve_oblivious(entry),
+        }
+    }
+
+    // evicts the next path in reverse-lexicographic order, as if an entry that does not exist was read from it, and
+    // sweeps the pages of a key rotation in progress
+    fn evict_in_background(&mut self) {
+        self.tree.sweep_keys(KEY_SWEEP_RATE);
+        self.num_accesses += 1;
+        if let Some(every) = self.background_eviction {
+            if self.num_accesses.is_multiple_of(every) {
+                // real entries never have the index of an empty entry
+                let mut entry = HashEntry::new();
+                entry.set_val(self.tree.reverse_lex_path(self.evict_count));
+                self.evict_count += 1;
+                // a path whose stash overflows is left as it is, and reported by the next access that reads it
+                let _ = self.retrieve(&entry);
+            }
+        }
+    }
+
+    // remove the entry from the path and the stash, and evict the rest of the path greedily
+    fn retrieve_greedy(&mut self, entry: &HashEntry<usize>) -> Option<Zeroizing<Vec<u8>>> {
         let page_idx = entry.get_val();
-        let (path, layer_sizes) = self.tree.read_path(page_idx);
+        let (mut path, layer_sizes) = self.tree.read_path(page_idx);
         let num_layer = layer_sizes.len();
         let layer_log_sizes: Vec<u8> = layer_sizes
             .iter()
             .map(|x| x.trailing_zeros() as u8)
             .collect();
-        let mut result: Option<Vec<u8>> = None;
+        let mut found_flag = false;
+        let mut found_layer = 0;
+        let mut value_offset = 0;
+        let mut value_len = 0;
         let mut rest: Vec<SortEntry> = Vec::new();
         for (i, page) in path.iter().enumerate() {
-            let page_result =
-                page.read_entry_and_retrieve_rest(entry, &mut rest, i as u8, &layer_log_sizes);
-            if page_result.is_some() {
-                result = page_result;
-            }
+            let (hit, offset, len) =
+                page.find_entry_and_retrieve_rest(entry, &mut rest, i as u8, &layer_log_sizes);
+            cmov(hit, &mut found_layer, &i);
+            cmov(hit, &mut value_offset, &offset);
+            cmov(hit, &mut value_len, &len);
+            found_flag |= hit;
         }
         let stash_vec = self.stash.get_and_remove(page_idx);
         // if stash_vec.len() >= 65536 {
@@ -325,24 +474,34 @@ impl FlexOram {
         // }
         const META_SIZE: usize = std::mem::size_of::<HashEntry<usize>>();
         // for (chunk_idx, stash_vec) in stash_vecs.iter().enumerate() {
+        let mut stash_found_flag = false;
+        let mut stash_found_idx = 0;
         for (i, (stash_entry, value)) in stash_vec.iter().enumerate() {
-            if stash_entry == entry {
-                result = Some(value.clone());
-            } else {
-                let deepest = calc_deepest(stash_entry.get_val(), page_idx, &layer_log_sizes);
-                if deepest >= num_layer as u8 {
-                    // after the stash forks, some entries may become invalid, i.e., cannot be placed in the current sub vector
-                    continue;
-                }
-                rest.push(SortEntry {
-                    deepest,
-                    src: num_layer as u8,
-                    len: (META_SIZE + 2 + value.len()) as u16,
-                    offset: i as u32,
-                });
-            }
+            let hit = stash_entry.eq(entry);
+            cmov(hit, &mut stash_found_idx, &i);
+            stash_found_flag |= hit;
+            let deepest = calc_deepest(stash_entry.get_val(), page_idx, &layer_log_sizes);
+            // the entry found is not kept, and after the stash forks, some entries may become invalid, i.e., cannot
+            // be placed in the current sub vector; they are dropped the same way as in the pages
+            let dropped = hit | !ct_lt(deepest as usize, num_layer);
+            rest.push(SortEntry {
+                deepest: ct_select(dropped, DROPPED_LEVEL, deepest),
+                src: num_layer as u8,
+                len: (META_SIZE + 2 + value.len()) as u16,
+                offset: i as u32,
+            });
             // }
         }
+        // the value is copied out once, after all the pages and the stash are scanned
+        let result = if stash_found_flag {
+            Some(stash_vec[stash_found_idx].1.clone())
+        } else if found_flag {
+            Some(Zeroizing::new(
+                path[found_layer].buffer[value_offset..value_offset + value_len].to_vec(),
+            ))
+        } else {
+            None
+        };
 
         // now sort the rest of the entries by the deepest level
         rest.sort_by(|a, b| a.deepest.cmp(&b.deepest));
@@ -372,7 +531,7 @@ impl FlexOram {
                     page.insert(stash_entry, value);
                 } else {
                     // copy from page
-                    let src_page = path[entry.src as usize];
+                    let src_page = &path[entry.src as usize];
                     let raw_bytes =
                         unsafe { src_page.buffer.as_ptr().offset(entry.offset as isize) };
                     page.insert_raw_bytes(raw_bytes, entry.len as usize);
@@ -389,19 +548,284 @@ impl FlexOram {
                 let (stash_entry, value) = &stash_vec[entry.offset as usize];
                 new_stash_vec.push((stash_entry.clone(), value.clone()));
             } else {
-                let src_page = path[entry.src as usize];
-                let meta_bytes = unsafe { src_page.buffer.as_ptr().offset(entry.offset as isize) };
-                let meta_data = unsafe { &*(meta_bytes as *const HashEntry<usize>) };
-                let value = src_page.buffer[entry.offset as usize + META_SIZE + 2
-                    ..(entry.offset as usize + entry.len as usize)]
-                    .to_vec();
+                let src_page = &path[entry.src as usize];
+                let meta_data = src_page.read_meta(entry.offset as usize);
+                let value = Zeroizing::new(
+                    src_page.buffer[entry.offset as usize + META_SIZE + 2
+                        ..(entry.offset as usize + entry.len as usize)]
+                        .to_vec(),
+                );
                 new_stash_vec.push((meta_data.clone(), value));
             }
         }
         self.stash.concat(page_idx, new_stash_vec);
 
-        // write back path
+        // write back path, and wipe the plaintext pages
         self.tree.write_path(page_idx, &new_path);
+        wipe_vec(&mut path);
+        wipe_vec(&mut new_path);
+        result
+    }
+
+    /**
+     * Same as `retrieve_greedy`, with a control flow and memory accesses that do not depend on the entries. Every
+     * page is parsed in windows of the smallest entry size, each holding at most one entry start, whose header is
+     * picked out of its window by a constant-time scan, and the stash is padded with dummy entries to
+     * `OBLIVIOUS_STASH_SIZE` entries serialized in `OBLIVIOUS_STASH_BYTES` bytes, so that the work only depends on the
+     * number of levels of the path. The entries are sorted by their deepest level with a bitonic network and assigned
+     * a level with next fit in one constant-time scan. Each byte then learns the level of its entry in a pass over the bytes in order, and
+     * each page of the new path, as well as the overflow to the stash, is the bytes of its level moved to the front
+     * with `compact_bytes`. The value read is moved to the front the same way and cut to its length in constant time.
+     * Fails with `DBError::StashOverflow` if the stash of the path does not fit its capacity before or after the
+     * eviction, in which case the path and the stash are left as they were.
+     */
+    fn retrieve_oblivious(
+        &mut self,
+        entry: &HashEntry<usize>,
+    ) -> Result<Option<Zeroizing<Vec<u8>>>, DBError> {
+        let page_idx = entry.get_val();
+        const META_SIZE: usize = std::mem::size_of::<HashEntry<usize>>();
+        const HEADER_SIZE: usize = META_SIZE + 2;
+        // bytes of values that fit next to the headers of all the entries of the stash, dummy or not
+        const STASH_VALUE_BYTES: usize = OBLIVIOUS_STASH_BYTES - OBLIVIOUS_STASH_SIZE * HEADER_SIZE;
+        let stash_vec = self.stash.get_and_remove(page_idx);
+        let stash_value_bytes: usize = stash_vec.iter().map(|(_, value)| value.len()).sum();
+        if stash_vec.len() > OBLIVIOUS_STASH_SIZE || stash_value_bytes > STASH_VALUE_BYTES {
+            self.stash.concat(page_idx, stash_vec);
+            return Err(DBError::StashOverflow);
+        }
+        let (mut path, layer_sizes) = self.tree.read_path(page_idx);
+        let num_layer = layer_sizes.len();
+        let layer_log_sizes: Vec<u8> = layer_sizes
+            .iter()
+            .map(|x| x.trailing_zeros() as u8)
+            .collect();
+        let meta_bytes = unsafe {
+            std::slice::from_raw_parts(entry as *const HashEntry<usize> as *const u8, META_SIZE)
+        };
+        let num_tags = num_layer * MAX_ENTRY_PER_PAGE + OBLIVIOUS_STASH_SIZE;
+        let mut found_flag = false;
+        let mut value_src = 0;
+        let mut value_len = 0;
+
+        // the bytes of the path followed by the serialized stash, allocated at once so that no copy is left behind
+        let stash_start = num_layer * BUFFER_SIZE;
+        let mut src = Zeroizing::new(Vec::with_capacity(stash_start + OBLIVIOUS_STASH_BYTES));
+        for page in path.iter() {
+            src.extend_from_slice(&page.buffer);
+        }
+        let mut tags: Vec<EvictTag> = Vec::with_capacity(num_tags);
+        for (i, page) in path.iter().enumerate() {
+            // the k-th window of HEADER_SIZE bytes holds at most one entry start, which is `next` if it is in it
+            let mut next = 0;
+            for k in 0..MAX_ENTRY_PER_PAGE {
+                let base = i * BUFFER_SIZE + k * HEADER_SIZE;
+                let offset = i * BUFFER_SIZE + next;
+                let valid =
+                    ct_lt(next, (k + 1) * HEADER_SIZE) & ct_lt(next, page.filled_bytes as usize);
+                let mut header = [0u8; HEADER_SIZE];
+                for j in 0..HEADER_SIZE {
+                    // past the last window, the header read is cut short, and only used as a dummy
+                    let mut candidate = [0u8; HEADER_SIZE];
+                    let end = (base + j + HEADER_SIZE).min(src.len());
+                    candidate[..end - base - j].copy_from_slice(&src[base + j..end]);
+                    cmov(ct_eq(base + j, offset), &mut header, &candidate);
+                }
+                let entry_size =
+                    u16::from_ne_bytes([header[META_SIZE], header[META_SIZE + 1]]) as usize;
+                let full_entry_size = HEADER_SIZE + entry_size;
+                let hit = valid & ct_eq_bytes(&header[..META_SIZE], meta_bytes) & !found_flag;
+                cmov(hit, &mut value_src, &(offset + HEADER_SIZE));
+                cmov(hit, &mut value_len, &entry_size);
+                found_flag |= hit;
+                let self_idx =
+                    unsafe { std::ptr::read_unaligned(header.as_ptr() as *const HashEntry<usize>) }
+                        .get_val();
+                let deepest = calc_deepest(self_idx, page_idx, &layer_log_sizes) as usize;
+                // after fork, some entries cannot be placed in the current page, we simply remove them
+                let real = valid & !hit & !ct_lt(i, deepest);
+                tags.push(EvictTag {
+                    index: tags.len(),
+                    real: real as usize,
+                    key: ct_select(real, deepest, usize::MAX),
+                    len: full_entry_size,
+                    src: ct_select(valid, offset, usize::MAX),
+                });
+                next += ct_select(valid, full_entry_size, 0);
+            }
+        }
+        // the dummy entries have the index of an empty entry and an empty value
+        let dummy = (HashEntry::new(), Zeroizing::new(Vec::new()));
+        for j in 0..OBLIVIOUS_STASH_SIZE {
+            let offset = src.len();
+            let real_entry = ct_lt(j, stash_vec.len());
+            let (stash_entry, value) = stash_vec.get(j).unwrap_or(&dummy);
+            let meta = unsafe {
+                std::slice::from_raw_parts(
+                    stash_entry as *const HashEntry<usize> as *const u8,
+                    META_SIZE,
+                )
+            };
+            src.extend_from_slice(meta);
+            src.extend_from_slice(&(value.len() as u16).to_ne_bytes());
+            src.extend_from_slice(value);
+            let hit = real_entry & stash_entry.eq(entry) & !found_flag;
+            cmov(hit, &mut value_src, &(offset + HEADER_SIZE));
+            cmov(hit, &mut value_len, &value.len());
+            found_flag |= hit;
+            let deepest = calc_deepest(stash_entry.get_val(), page_idx, &layer_log_sizes) as usize;
+            // after the stash forks, some entries cannot be placed in the current sub vector
+            let real = real_entry & !hit & ct_lt(deepest, num_layer);
+            tags.push(EvictTag {
+                index: tags.len(),
+                real: real as usize,
+                key: ct_select(real, deepest, usize::MAX),
+                len: HEADER_SIZE + value.len(),
+                src: offset,
+            });
+        }
+        src.resize(stash_start + OBLIVIOUS_STASH_BYTES, 0);
+
+        // the value read, moved to the front of a buffer as long as any value of the path or the stash
+        let window = (BUFFER_SIZE - HEADER_SIZE)
+            .max(STASH_VALUE_BYTES)
+            .min(src.len());
+        let value_end = value_src + value_len;
+        let value = compact_bytes(&src, &|pos| {
+            found_flag & !ct_lt(pos, value_src) & ct_lt(pos, value_end)
+        });
+        let value_len = ct_select(found_flag, value_len, 0);
+        let mut result = Zeroizing::new(value[..window].to_vec());
+        for (i, byte) in result.iter_mut().enumerate() {
+            *byte = ct_select(ct_lt(i, value_len), *byte, 0);
+        }
+        result.truncate(value_len);
+
+        // place the entries, from the deepest level up, in the deepest level they fit in; the entries that do not
+        // fit in the path go to the stash, as if they were placed in a level past the path
+        bitonic_sort_by_key(&mut tags, &|tag: &EvictTag| tag.key);
+        let mut level = 0;
+        let mut fill = 0;
+        let mut num_overflow = 0;
+        let mut overflow_value_bytes = 0;
+        let mut level_fills = vec![0; num_layer];
+        for tag in tags.iter_mut() {
+            let real = ct_eq(tag.real, 1);
+            let skip = ct_lt(level, tag.key);
+            let mut new_level = ct_select(skip, tag.key, level);
+            let mut new_fill = ct_select(skip, 0, fill);
+            let full = ct_lt(BUFFER_SIZE, new_fill + tag.len) & ct_lt(new_level, num_layer);
+            new_level += full as usize;
+            new_fill = ct_select(full, 0, new_fill);
+            // an entry larger than a page stays in the stash
+            let fits = !ct_lt(BUFFER_SIZE, new_fill + tag.len);
+            let in_path = ct_lt(new_level, num_layer) & fits;
+            for (l, level_fill) in level_fills.iter_mut().enumerate() {
+                let hit = real & in_path & ct_eq(new_level, l);
+                *level_fill = ct_select(hit, new_fill + tag.len, *level_fill);
+            }
+            num_overflow += (real & !in_path) as usize;
+            overflow_value_bytes += ct_select(real & !in_path, tag.len - HEADER_SIZE, 0);
+            level = ct_select(real, new_level, level);
+            fill = ct_select(real, new_fill + tag.len, fill);
+            tag.key = ct_select(in_path, new_level, num_layer);
+            // the bytes of the entries that are not placed, the value read among them, are dropped
+            tag.len = ct_select(real, tag.len, 0);
+        }
+        if num_overflow > OBLIVIOUS_STASH_SIZE || overflow_value_bytes > STASH_VALUE_BYTES {
+            self.stash.concat(page_idx, stash_vec);
+            wipe_vec(&mut path);
+            return Err(DBError::StashOverflow);
+        }
+
+        // back in the order of the bytes, each byte takes the level of the last entry starting at or before it, unless
+        // it is past the end of that entry; in a page, that entry starts in the window of the byte or before
+        bitonic_sort_by_key(&mut tags, &|tag: &EvictTag| tag.index);
+        let mut byte_levels: Zeroizing<Vec<usize>> = Zeroizing::new(Vec::with_capacity(src.len()));
+        let mut byte_level = 0;
+        let mut end = 0;
+        let mut visit = |pos: usize, tag: &EvictTag| {
+            let start = ct_eq(tag.src, pos);
+            byte_level = ct_select(start, tag.key, byte_level);
+            end = ct_select(start, tag.src.wrapping_add(tag.len), end);
+            byte_levels.push(ct_select(ct_lt(pos, end), byte_level, usize::MAX));
+        };
+        for i in 0..num_layer {
+            for q in 0..BUFFER_SIZE {
+                let k = (q / HEADER_SIZE).min(MAX_ENTRY_PER_PAGE - 1);
+                visit(i * BUFFER_SIZE + q, &tags[i * MAX_ENTRY_PER_PAGE + k]);
+            }
+        }
+        // every entry of the stash, dummy or not, takes at least HEADER_SIZE bytes, so as in a page each window of
+        // HEADER_SIZE bytes of the stash holds at most one entry start, picked out of the tags of the stash
+        let stash_tags = &tags[num_layer * MAX_ENTRY_PER_PAGE..];
+        for base in (stash_start..src.len()).step_by(HEADER_SIZE) {
+            let mut tag = EvictTag::zeroed();
+            tag.src = usize::MAX;
+            for stash_tag in stash_tags {
+                let start = ct_lt(stash_tag.src.wrapping_sub(base), HEADER_SIZE);
+                tag.src = ct_select(start, stash_tag.src, tag.src);
+                tag.key = ct_select(start, stash_tag.key, tag.key);
+                tag.len = ct_select(start, stash_tag.len, tag.len);
+            }
+            for pos in base..(base + HEADER_SIZE).min(src.len()) {
+                visit(pos, &tag);
+            }
+        }
+
+        let mut new_path: Vec<Page> = vec![Page::new(); num_layer];
+        for (l, page) in new_path.iter_mut().enumerate() {
+            let bytes = compact_bytes(&src, &|pos| ct_eq(byte_levels[pos], l));
+            page.buffer.copy_from_slice(&bytes[..BUFFER_SIZE]);
+            page.filled_bytes = level_fills[l] as u16;
+        }
+
+        // the entries that overflow are moved to the front of a buffer of the size of the stash, and read out of it in
+        // a fixed number of rounds, past the last one as empty entries. Each value is read at the offset where the
+        // values before it end, which the stash does not hide as it keeps every value in an allocation of its length
+        let overflow = compact_bytes(&src, &|pos| ct_eq(byte_levels[pos], num_layer));
+        let mut new_stash_vec = Vec::with_capacity(num_overflow);
+        let mut offset = 0;
+        for k in 0..OBLIVIOUS_STASH_SIZE {
+            let meta_data = unsafe {
+                std::ptr::read_unaligned(overflow.as_ptr().add(offset) as *const HashEntry<usize>)
+            };
+            let entry_size = u16::from_ne_bytes([
+                overflow[offset + META_SIZE],
+                overflow[offset + META_SIZE + 1],
+            ]) as usize;
+            let value = Zeroizing::new(
+                overflow[offset + HEADER_SIZE..offset + HEADER_SIZE + entry_size].to_vec(),
+            );
+            offset += HEADER_SIZE + entry_size;
+            // only the real entries are kept
+            if k < num_overflow {
+                new_stash_vec.push((meta_data, value));
+            }
+        }
+        self.stash.concat(page_idx, new_stash_vec);
+        self.tree.write_path(page_idx, &new_path);
+        wipe_vec(&mut path);
+        wipe_vec(&mut new_path);
+        Ok(found_flag.then_some(result))
+    }
+
+    /**
+     * Read-modify-write an entry. The values are passed in and out in buffers wiped when dropped, and so are the
+     * values in the stash. With the oblivious eviction, fails with `DBError::StashOverflow` before `update_func` is
+     * called if the stash of the path does not fit its capacity, and the oram is left as it was.
+     */
+    pub fn update<F>(
+        &mut self,
+        entry: &HashEntry<usize>,
+        update_func: F,
+        new_page_id: usize,
+    ) -> Result<(), DBError>
+    where
+        F: FnOnce(Option<Zeroizing<Vec<u8>>>) -> Option<Zeroizing<Vec<u8>>>,
+    {
+        let result = self.retrieve(entry)?;
+        const META_SIZE: usize = std::mem::size_of::<HashEntry<usize>>();
         if result.is_some() {
             self.num_entry -= 1;
             self.num_bytes -= result.as_ref().unwrap().len() + META_SIZE;
@@ -415,6 +839,7 @@ impl FlexOram {
             new_entry.set_val(new_page_id);
             self.stash.insert(new_page_id, new_entry, result_unwrap);
         }
+        self.evict_in_background();
         let load_factor = self.num_bytes as f64 / (self.tree.total_size() * BUFFER_SIZE) as f64;
         if load_factor > 0.5 {
             println!(
@@ -424,6 +849,7 @@ impl FlexOram {
             );
             self.scale();
         }
+        Ok(())
     }
 
     fn scale(&mut self) {
@@ -435,19 +861,78 @@ impl FlexOram {
         self.stash.scale(new_stash_size)
     }
 
-    pub fn read(&mut self, entry: &HashEntry<usize>, new_page_id: usize) -> Option<Vec<u8>> {
+    /**
+     * Build the oram from the given entries in one pass. The tree is scaled according to the number of entries and
+     * their total size, or, with `padded_len` set, as if every value had `padded_len` bytes. Every page is then
+     * written sequentially, so the page access pattern only depends on the size of the input, or only on the number
+     * of entries if padded.
+     */
+    pub fn bulk_build(
+        &mut self,
+        entries: Vec<(HashEntry<usize>, Vec<u8>)>,
+        padded_len: Option<usize>,
+    ) {
+        assert_eq!(self.num_entry, 0);
+        if entries.is_empty() {
+            return;
+        }
+        const META_SIZE: usize = std::mem::size_of::<HashEntry<usize>>();
+        self.num_entry = entries.len();
+        self.num_bytes = entries
+            .iter()
+            .map(|(_, value)| value.len() + META_SIZE)
+            .sum();
+        let sized_bytes = match padded_len {
+            Some(len) => {
+                assert!(entries.iter().all(|(_, value)| value.len() <= len));
+                entries.len() * (len + META_SIZE)
+            }
+            None => self.num_bytes,
+        };
+        while sized_bytes as f64 > (self.tree.total_size() * BUFFER_SIZE) as f64 * 0.5 {
+            self.scale();
+        }
+        // place large entries first, as in the greedy eviction
+        let mut pending: Vec<_> = entries
+            .into_iter()
+            .map(|(entry, value)| (entry, Zeroizing::new(value)))
+            .collect();
+        pending.sort_by_key(|(_, value)| std::cmp::Reverse(value.len()));
+        for (level, layer_size) in self.tree.layer_sizes().into_iter().enumerate() {
+            let mut pages = vec![Page::new(); layer_size];
+            let mut rest = Vec::new();
+            for (entry, value) in pending {
+                if !pages[entry.get_val() % layer_size].insert(&entry, &value) {
+                    rest.push((entry, value));
+                }
+            }
+            self.tree.write_layer(level, &pages);
+            pending = rest;
+        }
+        for (entry, value) in pending {
+            self.stash.insert(entry.get_val(), entry, value);
+        }
+    }
+
+    pub fn read(
+        &mut self,
+        entry: &HashEntry<usize>,
+        new_page_id: usize,
+    ) -> Option<Zeroizing<Vec<u8>>> {
         let mut ret = None;
-        let dummy_func = |x: Option<Vec<u8>>| {
+        let dummy_func = |x: Option<Zeroizing<Vec<u8>>>| {
             ret = x.clone();
             x
         };
-        self.update(entry, dummy_func, new_page_id);
+        self.update(entry, dummy_func, new_page_id)
+            .expect("stash overflow");
         ret
     }
 
     pub fn write(&mut self, entry: &HashEntry<usize>, value: &Vec<u8>, new_page_id: usize) {
-        let overwrite_func = |_| Some(value.clone());
-        self.update(entry, overwrite_func, new_page_id);
+        let overwrite_func = |_| Some(Zeroizing::new(value.clone()));
+        self.update(entry, overwrite_func, new_page_id)
+            .expect("stash overflow");
     }
 
     pub fn read_and_write<V: AsRef<[u8]>>(
@@ -455,26 +940,38 @@ impl FlexOram {
         entry: &HashEntry<usize>,
         value: V,
         new_page_id: usize,
-    ) -> Option<Vec<u8>> {
+    ) -> Option<Zeroizing<Vec<u8>>> {
         let mut ret = None;
-        let overwrite_func = |x: Option<Vec<u8>>| {
+        let overwrite_func = |x: Option<Zeroizing<Vec<u8>>>| {
             ret = x;
-            Some(value.as_ref().to_vec())
+            Some(Zeroizing::new(value.as_ref().to_vec()))
         };
-        self.update(entry, overwrite_func, new_page_id);
+        self.update(entry, overwrite_func, new_page_id)
+            .expect("stash overflow");
         ret
     }
 
-    pub fn remove(&mut self, entry: &HashEntry<usize>) -> Option<Vec<u8>> {
+    pub fn remove(&mut self, entry: &HashEntry<usize>) -> Option<Zeroizing<Vec<u8>>> {
         let mut ret = None;
         let remove_func = |x| {
             ret = x;
             None
         };
-        self.update(entry, remove_func, 0);
+        self.update(entry, remove_func, 0).expect("stash overflow");
         ret
     }
 
+    /**
+     * Visit every entry in the oram. All the pages are read in a fixed order followed by the whole stash,
+     * so the access pattern does not depend on the content.
+     */
+    pub fn for_each<F: FnMut(&HashEntry<usize>, &[u8])>(&self, mut f: F) {
+        self.tree.for_each_page(|idx, layer_size, page| {
+            page.for_each_valid_entry(idx, layer_size, &mut f);
+        });
+        self.stash.for_each(&mut f);
+    }
+
     pub fn print_meta_state(&self) {
         println!("FlexOram meta state:");
         // println!("FlexOram pages count: {}", self.pages.capacity());
@@ -513,6 +1010,7 @@ impl FlexOram {
     // }
 }
 
+#[cfg(test)]
 mod tests {
     use super::*;
     use rand::random;
@@ -527,7 +1025,7 @@ mod tests {
         flex_oram.write(&entry, &value, new_page_id);
 
         let result = flex_oram.read(&entry, new_page_id);
-        assert_eq!(result, Some(value));
+        assert_eq!(result, Some(Zeroizing::new(value)));
     }
 
     #[test]
@@ -551,10 +1049,175 @@ mod tests {
             for (entry, value) in ref_vec.iter_mut() {
                 let new_page_id = random();
                 let result = flex_oram.read(&entry, new_page_id);
-                assert_eq!(result, Some(value.clone()));
+                assert_eq!(result, Some(Zeroizing::new(value.clone())));
                 entry.set_val(new_page_id)
             }
         }
         flex_oram.print_meta_state();
     }
+
+    #[test]
+    fn test_flex_oram_for_each() {
+        let mut flex_oram = FlexOram::new();
+        let mut ref_map = std::collections::HashMap::new();
+        for i in 0..50000 {
+            let mut entry = HashEntry::new();
+            entry.set_idx([i, i]);
+            let value = vec![i as u8; i % 200];
+            flex_oram.write(&entry, &value, random());
+            ref_map.insert(i, value);
+        }
+        let mut count = 0;
+        flex_oram.for_each(|entry, value| {
+            assert_eq!(ref_map.get(&entry.get_idx()[0]).unwrap(), value);
+            count += 1;
+        });
+        assert_eq!(count, ref_map.len());
+    }
+
+    #[test]
+    fn test_flex_oram_oblivious() {
+        let mut flex_oram = FlexOram::with_eviction(EvictionMode::Oblivious);
+        let round = 5000;
+        let mut ref_vec: Vec<(HashEntry<usize>, Vec<u8>)> = Vec::new();
+        for i in 0..round {
+            let mut entry = HashEntry::new();
+            entry.set_idx([i, random()]);
+            let val_len = random::<usize>() % 100;
+            let value: Vec<u8> = (0..val_len).map(|_| random::<u8>()).collect();
+            let new_page_id = random::<usize>();
+            flex_oram.write(&entry, &value, new_page_id);
+            entry.set_val(new_page_id);
+            ref_vec.push((entry, value));
+        }
+        for _ in 0..3 {
+            for (entry, value) in ref_vec.iter_mut() {
+                let new_page_id = random();
+                assert_eq!(
+                    flex_oram.read(entry, new_page_id),
+                    Some(Zeroizing::new(value.clone()))
+                );
+                entry.set_val(new_page_id)
+            }
+        }
+        for (entry, value) in ref_vec.iter().step_by(2) {
+            assert_eq!(flex_oram.remove(entry), Some(Zeroizing::new(value.clone())));
+        }
+        let mut count = 0;
+        flex_oram.for_each(|entry, value| {
+            assert_eq!(entry.get_idx()[0] % 2, 1);
+            assert_eq!(*value, ref_vec[entry.get_idx()[0]].1);
+            count += 1;
+        });
+        assert_eq!(count, round / 2);
+    }
+
+    #[test]
+    fn test_flex_oram_oblivious_large_values() {
+        // values larger than a page stay in the stash, and are read past the window of a page
+        let mut flex_oram = FlexOram::with_eviction(EvictionMode::Oblivious);
+        let mut ref_vec: Vec<(HashEntry<usize>, Vec<u8>)> = Vec::new();
+        for i in 0..200 {
+            let mut entry = HashEntry::new();
+            entry.set_idx([i, random()]);
+            let val_len = match i % 4 {
+                0 => 0,
+                1 => BUFFER_SIZE + random::<usize>() % 1000,
+                _ => random::<usize>() % 500,
+            };
+            let value: Vec<u8> = (0..val_len).map(|_| random::<u8>()).collect();
+            let new_page_id = random::<usize>();
+            flex_oram.write(&entry, &value, new_page_id);
+            entry.set_val(new_page_id);
+            ref_vec.push((entry, value));
+        }
+        for _ in 0..3 {
+            for (entry, value) in ref_vec.iter_mut() {
+                let new_page_id = random();
+                assert_eq!(
+                    flex_oram.read(entry, new_page_id),
+                    Some(Zeroizing::new(value.clone()))
+                );
+                entry.set_val(new_page_id)
+            }
+        }
+        let mut missing = HashEntry::new();
+        missing.set_idx([200, random()]);
+        assert_eq!(flex_oram.read(&missing, random()), None);
+    }
+
+    #[test]
+    fn test_flex_oram_oblivious_stash_overflow() {
+        // values larger than a page stay in the stash of path 0 until they no longer fit in its capacity
+        let mut flex_oram = FlexOram::with_eviction(EvictionMode::Oblivious);
+        let mut entries = Vec::new();
+        for i in 0..3 {
+            let mut entry = HashEntry::new();
+            entry.set_idx([i + 1, 0]);
+            flex_oram.write(&entry, &vec![i as u8; BUFFER_SIZE + 1000], 0);
+            entries.push(entry);
+        }
+        assert_eq!(flex_oram.stash_len(), 3);
+        let mut called = false;
+        let ret = flex_oram.update(
+            &entries[0],
+            |x| {
+                called = true;
+                x
+            },
+            0,
+        );
+        assert_eq!(ret, Err(DBError::StashOverflow));
+        assert!(!called);
+        // nothing is lost
+        assert_eq!(flex_oram.stash_len(), 3);
+        let mut count = 0;
+        flex_oram.for_each(|entry, value| {
+            assert_eq!(
+                *value,
+                vec![entry.get_idx()[0] as u8 - 1; BUFFER_SIZE + 1000]
+            );
+            count += 1;
+        });
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_flex_oram_background_eviction() {
+        let round = 20000;
+        let mut stash_lens = Vec::new();
+        for background_eviction in [None, Some(1)] {
+            let mut flex_oram: FlexOram = FlexOram::with_options(
+                EvictionMode::Greedy,
+                background_eviction,
+                RandGen::from_seed(7),
+            );
+            let mut ref_vec: Vec<(HashEntry<usize>, Vec<u8>)> = Vec::new();
+            for i in 0..round {
+                let mut entry = HashEntry::new();
+                entry.set_idx([i + 1, random()]);
+                let value: Vec<u8> = (0..random::<usize>() % 100).map(|_| random()).collect();
+                let new_page_id = random::<usize>();
+                flex_oram.write(&entry, &value, new_page_id);
+                entry.set_val(new_page_id);
+                ref_vec.push((entry, value));
+            }
+            let mut max_stash_len = 0;
+            for (entry, value) in ref_vec.iter_mut() {
+                let new_page_id = random();
+                assert_eq!(
+                    flex_oram.read(entry, new_page_id),
+                    Some(Zeroizing::new(value.clone()))
+                );
+                entry.set_val(new_page_id);
+                max_stash_len = max_stash_len.max(flex_oram.stash_len());
+            }
+            let mut count = 0;
+            flex_oram.for_each(|_, _| count += 1);
+            assert_eq!(count, round);
+            println!("{:?}: max stash {}", background_eviction, max_stash_len);
+            stash_lens.push(max_stash_len);
+        }
+        assert!(stash_lens[1] < stash_lens[0]);
+    }
 }
@@ -1,58 +1,93 @@
+use super::memory::MemoryUsage;
+use super::oram::Oram;
+use crate::utils::ct::{cmov, ct_eq};
 use crate::utils::utils::SimpleVal;
+
+/**
+ * Oram that scans every block on each access with conditional moves, so the memory trace and the running time do
+ * not depend on the uid. Faster than the tree orams for small tables.
+ */
 pub struct LinearOram<T: SimpleVal, const N: usize> {
-    val: Vec<T>,
+    vals: Vec<T>,
+    valid_flags: Vec<u8>,
 }
 
-impl<T: SimpleVal, const N: usize> LinearOram<T, N> {
-    pub fn new(size: usize) -> Self {
+impl<T: SimpleVal, const N: usize> Oram<T> for LinearOram<T, N> {
+    fn new(size: usize) -> Self {
         Self {
-            val: vec![T::zeroed(); size],
+            vals: vec![T::zeroed(); size],
+            valid_flags: vec![0; size],
         }
     }
 
-    pub fn update<F>(&mut self, uid: usize, update_func: F)
+    fn update<F>(&mut self, uid: usize, update_func: F)
     where
         F: FnOnce(Option<T>) -> Option<T>,
     {
-        self.val[uid] = update_func(Some(self.val[uid])).unwrap();
+        assert!(uid < self.size());
+        let mut val = T::zeroed();
+        let mut valid_flag = 0u8;
+        for i in 0..self.size() {
+            let hit = ct_eq(i, uid);
+            cmov(hit, &mut val, &self.vals[i]);
+            cmov(hit, &mut valid_flag, &self.valid_flags[i]);
+        }
+        let new_val = update_func((valid_flag != 0).then_some(val));
+        let new_valid_flag = new_val.is_some() as u8;
+        let new_val = new_val.unwrap_or(T::zeroed());
+        for i in 0..self.size() {
+            let hit = ct_eq(i, uid);
+            cmov(hit, &mut self.vals[i], &new_val);
+            cmov(hit, &mut self.valid_flags[i], &new_valid_flag);
+        }
     }
 
-    pub fn read(&mut self, uid: usize) -> Option<T> {
-        let mut ret = None;
-        let update_func = |val: Option<T>| {
-            ret = val;
-            val
-        };
-        self.update(uid, update_func);
-        ret
+    fn bulk_build(&mut self, vals: Vec<Option<T>>) {
+        assert_eq!(vals.len(), self.size());
+        self.valid_flags = vals.iter().map(|val| val.is_some() as u8).collect();
+        self.vals = vals
+            .into_iter()
+            .map(|val| val.unwrap_or(T::zeroed()))
+            .collect();
     }
 
-    pub fn write(&mut self, uid: usize, val: T) {
-        let update_func = |_: Option<T>| Some(val);
-        self.update(uid, update_func);
+    fn double_size_and_fork_self(&mut self) {
+        let size = self.size();
+        self.vals.extend_from_within(0..size);
+        self.valid_flags.extend_from_within(0..size);
     }
 
-    pub fn double_size_and_fork_self(&mut self) {
-        self.val.extend_from_within(0..self.size());
+    fn size(&self) -> usize {
+        self.vals.len()
     }
 
-    pub fn size(&self) -> usize {
-        self.val.len()
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            linear_scan: self.size() * (std::mem::size_of::<T>() + 1),
+            ..Default::default()
+        }
     }
 
-    // pub fn print_state(&self) {
-    //     println!("LinearOram state:");
-    //     for i in 0..self.size() {
-    //         println!("{}: {:?}", i, self.val[i]);
-    //     }
-    // }
-
-    pub fn print_meta_state(&self) {
+    fn print_meta_state(&self) {
         println!("LinearOram meta state:");
         println!("size: {}", self.size());
     }
 }
 
+impl<T: SimpleVal, const N: usize> LinearOram<T, N> {
+    /**
+     * Take out all the blocks, with `None` for the uids never written, e.g., to move them into another oram.
+     */
+    pub fn into_vals(self) -> Vec<Option<T>> {
+        self.vals
+            .into_iter()
+            .zip(self.valid_flags)
+            .map(|(val, valid_flag)| (valid_flag != 0).then_some(val))
+            .collect()
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
     use rand::random;
@@ -0,0 +1,87 @@
+use crate::params::PAGE_SIZE;
+use crate::storage::storage::BlockStorage;
+use crate::tree::dynamictree::ORAMTree;
+use bytemuck::{Pod, Zeroable};
+use std::ops::{Add, AddAssign};
+
+/**
+ * Bytes of memory used by each component, summed over all the orams. Only the trusted memory is counted in `total`:
+ * the pages of the trees are encrypted, but those kept in the memory of the process (`MemStore`) still take trusted
+ * memory inside an enclave, while those of the other storages are outside it.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    // blocks waiting in the stashes and the bucket metadata of the stashes
+    pub stash: usize,
+    // positions kept in the base levels of the recursive position maps
+    pub position_map: usize,
+    // blocks of the orams that are small enough to be scanned linearly
+    pub linear_scan: usize,
+    // pages of the trees and of their hash trees kept in the memory of the process, and the tops of the hash trees
+    pub memory_storage: usize,
+    // bookkeeping of the tables, i.e., the names and the sizes of the tables
+    pub tables: usize,
+    // pages of the trees and of their hash trees in untrusted storage
+    pub untrusted_storage: usize,
+}
+
+impl MemoryUsage {
+    /**
+     * Bytes of trusted memory.
+     */
+    pub fn total(&self) -> usize {
+        self.stash + self.position_map + self.linear_scan + self.memory_storage + self.tables
+    }
+
+    /**
+     * The usage of `bytes` of tree pages, in trusted memory if `in_memory`.
+     */
+    pub fn tree_pages(bytes: usize, in_memory: bool) -> Self {
+        if in_memory {
+            Self {
+                memory_storage: bytes,
+                ..Default::default()
+            }
+        } else {
+            Self {
+                untrusted_storage: bytes,
+                ..Default::default()
+            }
+        }
+    }
+
+    /**
+     * The usage of the pages of a tree and of the hash trees of its layers, whose tops are kept in trusted memory
+     * whatever the storage.
+     */
+    pub fn tree<T: Clone + Copy + Pod + Zeroable, S: BlockStorage>(tree: &ORAMTree<T, S>) -> Self {
+        let (hash_pages, hash_bytes) = tree.hash_usage();
+        let mut usage = Self::tree_pages(
+            (tree.total_size() + hash_pages) * PAGE_SIZE,
+            tree.in_memory(),
+        );
+        usage.memory_storage += hash_bytes;
+        usage
+    }
+}
+
+impl Add for MemoryUsage {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            stash: self.stash + other.stash,
+            position_map: self.position_map + other.position_map,
+            linear_scan: self.linear_scan + other.linear_scan,
+            memory_storage: self.memory_storage + other.memory_storage,
+            tables: self.tables + other.tables,
+            untrusted_storage: self.untrusted_storage + other.untrusted_storage,
+        }
+    }
+}
+
+impl AddAssign for MemoryUsage {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
@@ -1,6 +1,12 @@
+pub mod autooram;
 pub mod cuckoo;
+pub mod evict;
 pub mod fixoram;
 pub mod flexomap;
 pub mod flexoram;
 pub mod linearoram;
+pub mod memory;
+#[cfg(test)]
+mod model;
+pub mod oram;
 pub mod recoram;
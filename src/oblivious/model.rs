@@ -0,0 +1,341 @@
+// Property-based tests: random sequences of operations are run through the maps and the orams and through a
+// reference model, and proptest shrinks a failing sequence to a minimal one. The randomness of the structures is
+// seeded from the case, so that a shrunk sequence replays with the same positions.
+use std::collections::HashMap;
+
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+use super::cuckoo::CuckooHashMap;
+use super::evict::{EvictionMode, OramScheme};
+use super::fixoram::{BlockId, FixOram, BUFFER_SIZE};
+use super::flexomap::{FlexOmap, KeyStorage};
+use super::oram::Oram;
+use super::recoram::{RecOram, BASE_ENTRY_SIZE};
+use crate::params::LINEAR_SCAN_THRESHOLD;
+use crate::storage::memstore::MemStore;
+use crate::utils::utils::RandGen;
+use crate::{DBConfig, ObliviousDB};
+
+// the small keys are drawn from this many keys, so that the sequences hit the same keys often
+const NUM_KEYS: u16 = 32;
+// every bulk of keys inserted by `Fill` is tagged by one of this many tags, a tag overwrites its previous bulk
+const NUM_FILL_TAGS: u8 = 4;
+
+#[derive(Clone, Debug)]
+enum MapOp {
+    Insert(u16, Vec<u8>),
+    Get(u16),
+    Remove(u16),
+    // insert `count` keys of the bulk `tag` with values of `len` bytes, to grow the position map and the tree past
+    // their scaling thresholds
+    Fill { tag: u8, count: usize, len: usize },
+}
+
+fn map_op(max_fill: usize) -> impl Strategy<Value = MapOp> {
+    prop_oneof![
+        4 => (0..NUM_KEYS, prop::collection::vec(any::<u8>(), 0..3 * BUFFER_SIZE / 2))
+            .prop_map(|(key, value)| MapOp::Insert(key, value)),
+        3 => (0..NUM_KEYS).prop_map(MapOp::Get),
+        2 => (0..NUM_KEYS).prop_map(MapOp::Remove),
+        1 => (0..NUM_FILL_TAGS, 0..max_fill, 0..BUFFER_SIZE)
+            .prop_map(|(tag, count, len)| MapOp::Fill { tag, count, len }),
+    ]
+}
+
+fn small_key(key: u16) -> Vec<u8> {
+    format!("key-{}", key).into_bytes()
+}
+
+fn fill_entries(tag: u8, count: usize, len: usize) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> {
+    (0..count).map(move |i| {
+        let value = (i as u32)
+            .to_le_bytes()
+            .iter()
+            .copied()
+            .cycle()
+            .take(len)
+            .collect();
+        (format!("fill-{}-{}", tag, i).into_bytes(), value)
+    })
+}
+
+/**
+ * Run `ops` through a map given by its insert, get and remove, checking every result against a `HashMap`, and
+ * read back every key at the end. The maps store the values of the ops as given by `to_val`. `insert` returns the
+ * previous value if the map reports it.
+ */
+fn check_map_ops<V: Clone + PartialEq + std::fmt::Debug>(
+    ops: &[MapOp],
+    to_val: impl Fn(&[u8]) -> V,
+    mut insert: impl FnMut(&[u8], V) -> Option<Option<V>>,
+    mut get: impl FnMut(&[u8]) -> Option<V>,
+    mut remove: impl FnMut(&[u8]) -> Option<V>,
+) -> Result<(), TestCaseError> {
+    let mut model: HashMap<Vec<u8>, V> = HashMap::new();
+    for (step, op) in ops.iter().enumerate() {
+        match op {
+            MapOp::Insert(key, value) => {
+                let key = small_key(*key);
+                let value = to_val(value);
+                let prev = model.insert(key.clone(), value.clone());
+                if let Some(ret) = insert(&key, value) {
+                    prop_assert_eq!(ret, prev, "insert at step {}", step);
+                }
+            }
+            MapOp::Get(key) => {
+                let key = small_key(*key);
+                prop_assert_eq!(get(&key), model.get(&key).cloned(), "get at step {}", step);
+            }
+            MapOp::Remove(key) => {
+                let key = small_key(*key);
+                prop_assert_eq!(remove(&key), model.remove(&key), "remove at step {}", step);
+            }
+            MapOp::Fill { tag, count, len } => {
+                for (key, value) in fill_entries(*tag, *count, *len) {
+                    let value = to_val(&value);
+                    let prev = model.insert(key.clone(), value.clone());
+                    if let Some(ret) = insert(&key, value) {
+                        prop_assert_eq!(ret, prev, "fill at step {}", step);
+                    }
+                }
+            }
+        }
+    }
+    for (key, value) in model.iter() {
+        prop_assert_eq!(get(key), Some(value.clone()));
+    }
+    for key in 0..NUM_KEYS {
+        let key = small_key(key);
+        if !model.contains_key(&key) {
+            prop_assert_eq!(get(&key), None);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+enum OramOp {
+    Write(Index, u32),
+    Read(Index),
+    Remove(Index),
+    // double the size, forking the content of the lower half into the upper half
+    Double,
+}
+
+fn oram_op() -> impl Strategy<Value = OramOp> {
+    prop_oneof![
+        4 => (any::<Index>(), any::<u32>()).prop_map(|(uid, val)| OramOp::Write(uid, val)),
+        4 => any::<Index>().prop_map(OramOp::Read),
+        1 => any::<Index>().prop_map(OramOp::Remove),
+        1 => Just(OramOp::Double),
+    ]
+}
+
+// a block of the fix oram large enough that only a few fit in a page, so that the tree scales after a few thousand
+// writes
+type FixVal = [u64; 96];
+const FIX_PER_PAGE: usize = BUFFER_SIZE / std::mem::size_of::<(BlockId, FixVal)>();
+
+#[derive(Clone, Debug)]
+enum FixOp {
+    Write(usize, u64, usize),
+    Read(usize, usize),
+    Remove(usize, usize),
+    // write `count` blocks with uids past the small ones
+    Fill(usize),
+}
+
+fn fix_op() -> impl Strategy<Value = FixOp> {
+    prop_oneof![
+        4 => (0..64usize, any::<u64>(), any::<usize>())
+            .prop_map(|(uid, val, page)| FixOp::Write(uid, val, page)),
+        4 => (0..64usize, any::<usize>()).prop_map(|(uid, page)| FixOp::Read(uid, page)),
+        1 => (0..64usize, any::<usize>()).prop_map(|(uid, page)| FixOp::Remove(uid, page)),
+        1 => (0..4096usize).prop_map(FixOp::Fill),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(8))]
+
+    #[test]
+    fn prop_flexomap_matches_model(
+        seed in any::<u64>(),
+        ops in prop::collection::vec(map_op(2048), 1..200),
+    ) {
+        let map = std::cell::RefCell::new(FlexOmap::<MemStore>::with_options(
+            KeyStorage::HashOnly,
+            LINEAR_SCAN_THRESHOLD,
+            EvictionMode::Greedy,
+            OramScheme::Path,
+            None,
+            None,
+            RandGen::from_seed(seed),
+        ));
+        check_map_ops(
+            &ops,
+            |value| value.to_vec(),
+            |key, value| Some(map.borrow_mut().insert(key, value)),
+            |key| map.borrow_mut().get(key),
+            |key| map.borrow_mut().remove(key),
+        )?;
+    }
+
+    #[test]
+    fn prop_db_matches_model(
+        seed in any::<u64>(),
+        ops in prop::collection::vec(map_op(2048), 1..200),
+    ) {
+        let db = ObliviousDB::with_config(DBConfig {
+            seed: Some(seed),
+            ..Default::default()
+        });
+        check_map_ops(
+            &ops,
+            |value| value.to_vec(),
+            |key, value| {
+                db.insert(key, value);
+                None
+            },
+            |key| db.get(key),
+            |key| db.remove(key),
+        )?;
+    }
+
+    #[test]
+    fn prop_cuckoo_matches_model(
+        seed in any::<u64>(),
+        linear_scan_threshold in prop_oneof![Just(0), Just(usize::MAX)],
+        ops in prop::collection::vec(map_op(1024), 1..300),
+    ) {
+        // the map stores a digest of the values of the ops
+        let mut map = CuckooHashMap::<u64, 4, 16>::with_options(
+            linear_scan_threshold,
+            EvictionMode::Greedy,
+            OramScheme::Path,
+            None,
+            RandGen::from_seed(seed),
+        );
+        let map = std::cell::RefCell::new(&mut map);
+        check_map_ops(
+            &ops,
+            |value| {
+                value
+                    .iter()
+                    .fold(value.len() as u64, |h, b| h.wrapping_mul(31).wrapping_add(*b as u64))
+            },
+            |key, value| Some(map.borrow_mut().insert(key, value)),
+            |key| map.borrow_mut().get(key),
+            |key| {
+                let mut map = map.borrow_mut();
+                let entry = map.compute_hash_entry(key, 0);
+                map.remove_hash_entry(&entry)
+            },
+        )?;
+    }
+
+    #[test]
+    fn prop_recoram_matches_model(
+        seed in any::<u64>(),
+        init_log_size in 0..6u32,
+        linear_scan_threshold in prop_oneof![Just(0), Just(usize::MAX)],
+        budget_entries in 4..64usize,
+        ops in prop::collection::vec(oram_op(), 1..300),
+    ) {
+        let mut rec_oram: RecOram<u32, 4> = RecOram::with_options(
+            1 << init_log_size,
+            linear_scan_threshold,
+            budget_entries * BASE_ENTRY_SIZE,
+            EvictionMode::Greedy,
+            OramScheme::Path,
+            None,
+            RandGen::from_seed(seed),
+        );
+        let mut model: Vec<Option<u32>> = vec![None; rec_oram.size()];
+        for (step, op) in ops.iter().enumerate() {
+            match *op {
+                OramOp::Write(uid, val) => {
+                    let uid = uid.index(model.len());
+                    model[uid] = Some(val);
+                    rec_oram.write(uid, val);
+                }
+                OramOp::Read(uid) => {
+                    let uid = uid.index(model.len());
+                    prop_assert_eq!(rec_oram.read(uid), model[uid], "read at step {}", step);
+                }
+                OramOp::Remove(uid) => {
+                    let uid = uid.index(model.len());
+                    model[uid] = None;
+                    rec_oram.update(uid, |_| None);
+                }
+                OramOp::Double => {
+                    // keep the cases small
+                    if model.len() < 4096 {
+                        rec_oram.double_size_and_fork_self();
+                        model.extend_from_within(..);
+                    }
+                }
+            }
+            prop_assert_eq!(rec_oram.size(), model.len());
+        }
+        for (uid, val) in model.iter().enumerate() {
+            prop_assert_eq!(rec_oram.read(uid), *val, "uid {}", uid);
+        }
+    }
+
+    #[test]
+    fn prop_fixoram_matches_model(
+        seed in any::<u64>(),
+        ops in prop::collection::vec(fix_op(), 1..100),
+    ) {
+        let mut fix_oram = FixOram::<FixVal, FIX_PER_PAGE>::with_options(
+            EvictionMode::Greedy,
+            OramScheme::Path,
+            None,
+            RandGen::from_seed(seed),
+        );
+        // the page of every block and its value
+        let mut model: HashMap<usize, (usize, FixVal)> = HashMap::new();
+        let block_id = |model: &HashMap<usize, (usize, FixVal)>, uid: usize, page: usize| BlockId {
+            page_idx: model.get(&uid).map_or(page, |(page_idx, _)| *page_idx),
+            uid,
+        };
+        for (step, op) in ops.iter().enumerate() {
+            match *op {
+                FixOp::Write(uid, val, page) => {
+                    let id = block_id(&model, uid, page);
+                    fix_oram.write(&id, &[val; 96], page);
+                    model.insert(uid, (page, [val; 96]));
+                }
+                FixOp::Read(uid, page) => {
+                    let id = block_id(&model, uid, page);
+                    let expected = model.get_mut(&uid).map(|(page_idx, val)| {
+                        *page_idx = page;
+                        *val
+                    });
+                    prop_assert_eq!(fix_oram.read(&id, page), expected, "read at step {}", step);
+                }
+                FixOp::Remove(uid, page) => {
+                    let id = block_id(&model, uid, page);
+                    fix_oram.update(&id, |_, uid| (None, uid), page);
+                    model.remove(&uid);
+                }
+                FixOp::Fill(count) => {
+                    for i in 0..count {
+                        let uid = 64 + i;
+                        let page = (i * 7919) % (1 << 20);
+                        let id = block_id(&model, uid, page);
+                        fix_oram.write(&id, &[i as u64; 96], page);
+                        model.insert(uid, (page, [i as u64; 96]));
+                    }
+                }
+            }
+        }
+        prop_assert_eq!(fix_oram.get_all().len(), model.len());
+        for (uid, (page_idx, val)) in model.iter() {
+            let id = BlockId { page_idx: *page_idx, uid: *uid };
+            prop_assert_eq!(fix_oram.read(&id, *page_idx), Some(*val), "uid {}", uid);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use super::memory::MemoryUsage;
+use crate::tree::cipher::StorageKey;
+use crate::utils::utils::{RandGen, SimpleVal};
+
+/**
+ * An oblivious array of `size()` blocks addressed by uid. A uid that was never written, or whose block was
+ * removed by returning `None` from an update, reads as `None`. Doubling the size forks the array, so that uid
+ * `i + size` starts with the block of uid `i`.
+ *
+ * `FixOram` and `FlexOram` do not implement it: their blocks are addressed by the page they were last placed on,
+ * which the caller keeps in its own position map, and `FlexOram` stores values of variable size. `RecOram` wraps a
+ * `FixOram` with a recursive position map into an `Oram`, and `FlexOmap` wraps a `FlexOram` with a cuckoo map.
+ */
+pub trait Oram<T: SimpleVal>: Send {
+    fn new(size: usize) -> Self
+    where
+        Self: Sized;
+
+    /**
+     * Same as `new`, with the randomness of the oram drawn from `rand_gen`. Orams that draw none ignore it.
+     */
+    fn with_rand_gen(size: usize, _rand_gen: RandGen) -> Self
+    where
+        Self: Sized,
+    {
+        Self::new(size)
+    }
+
+    /**
+     * Read and write back a block with a single access.
+     */
+    fn update<F>(&mut self, uid: usize, update_func: F)
+    where
+        F: FnOnce(Option<T>) -> Option<T>;
+
+    fn read(&mut self, uid: usize) -> Option<T> {
+        let mut ret = None;
+        let update_func = |val: Option<T>| {
+            ret = val;
+            val
+        };
+        self.update(uid, update_func);
+        ret
+    }
+
+    fn write(&mut self, uid: usize, val: T) {
+        let update_func = |_: Option<T>| Some(val);
+        self.update(uid, update_func);
+    }
+
+    /**
+     * Build the oram from a value for every uid in one pass, instead of writing the uids one by one, `None` for the
+     * uids that read as absent. Any previous content is replaced.
+     */
+    fn bulk_build(&mut self, vals: Vec<Option<T>>);
+
+    fn double_size_and_fork_self(&mut self);
+
+    fn size(&self) -> usize;
+
+    /**
+     * Trusted memory used by the oram and the orams it contains.
+     */
+    fn memory_usage(&self) -> MemoryUsage;
+
+    /**
+     * Move part of the trusted memory out of the enclave, e.g., by recursing the position map deeper, at the cost
+     * of slower accesses. Returns whether anything changed, so that it can be called until nothing is left to move.
+     */
+    fn shrink_memory(&mut self) -> bool {
+        false
+    }
+
+    /**
+     * Write the pages of the trees under `key` from now on, and rewrite the pages under the previous key a few at a
+     * time on every access. Orams kept in trusted memory have nothing to rotate.
+     */
+    fn rotate_key(&mut self, _key: &StorageKey) {}
+
+    /**
+     * Rewrite up to `max_pages` pages still under the previous key, see `ORAMTree::sweep_keys`. Returns the number
+     * of pages visited, 0 once the rotation is complete.
+     */
+    fn sweep_keys(&mut self, _max_pages: usize) -> usize {
+        0
+    }
+
+    fn print_meta_state(&self);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oblivious::autooram::AutoOram;
+    use crate::oblivious::linearoram::LinearOram;
+    use crate::oblivious::recoram::RecOram;
+    use rand::random;
+    use std::collections::HashMap;
+
+    fn conformance_simple<O: Oram<u32>>() {
+        let mut oram = O::new(4);
+        assert_eq!(oram.size(), 4);
+        assert_eq!(oram.read(2), None);
+        for i in 0..4 {
+            oram.write(i, i as u32 + 1);
+        }
+        for i in 0..4 {
+            assert_eq!(oram.read(i), Some(i as u32 + 1));
+        }
+        oram.update(1, |val| val.map(|val| val * 10));
+        assert_eq!(oram.read(1), Some(20));
+        oram.update(3, |_| None);
+        assert_eq!(oram.read(3), None);
+    }
+
+    fn conformance_scale<O: Oram<u32>>() {
+        let mut oram = O::new(4);
+        for i in 0..4 {
+            oram.write(i, i as u32 + 1);
+        }
+        oram.double_size_and_fork_self();
+        assert_eq!(oram.size(), 8);
+        oram.write(3, 5);
+        oram.write(6, 8);
+        oram.double_size_and_fork_self();
+        oram.write(14, 4);
+        let expected = [1, 2, 3, 5, 1, 2, 8, 4, 1, 2, 3, 5, 1, 2, 4, 4];
+        for (i, val) in expected.iter().enumerate() {
+            assert_eq!(oram.read(i), Some(*val));
+        }
+    }
+
+    // random writes, removes and reads, including uids never written, compared with a map of the present uids
+    fn conformance_rand<O: Oram<u32>>() {
+        let mut size = 128;
+        let mut ref_map: HashMap<usize, u32> = HashMap::new();
+        let mut oram = O::new(size);
+        for i in 0..5000 {
+            if i % 2000 == 1999 {
+                oram.double_size_and_fork_self();
+                let forks: Vec<_> = ref_map
+                    .iter()
+                    .map(|(uid, val)| (uid + size, *val))
+                    .collect();
+                ref_map.extend(forks);
+                size *= 2;
+            }
+            let write_uid = random::<usize>() % size;
+            if random::<u8>() < 64 {
+                oram.update(write_uid, |_| None);
+                ref_map.remove(&write_uid);
+            } else {
+                let val = random::<u32>();
+                oram.write(write_uid, val);
+                ref_map.insert(write_uid, val);
+            }
+            let read_uid = random::<usize>() % size;
+            assert_eq!(oram.read(read_uid), ref_map.get(&read_uid).copied());
+        }
+        for i in 0..size {
+            assert_eq!(oram.read(i), ref_map.get(&i).copied(), "uid {}", i);
+        }
+    }
+
+    fn conformance_bulk_build<O: Oram<u32>>() {
+        let size = 256;
+        let mut oram = O::new(size);
+        // every fifth uid is absent
+        oram.bulk_build(
+            (0..size as u32)
+                .map(|i| (i % 5 != 0).then_some(i * 3))
+                .collect(),
+        );
+        oram.write(7, 1);
+        oram.write(10, 2);
+        oram.double_size_and_fork_self();
+        for i in 0..size * 2 {
+            let expected = match i % size {
+                7 => Some(1),
+                10 => Some(2),
+                uid if uid % 5 == 0 => None,
+                uid => Some(uid as u32 * 3),
+            };
+            assert_eq!(oram.read(i), expected, "uid {}", i);
+        }
+    }
+
+    fn conformance<O: Oram<u32>>() {
+        conformance_simple::<O>();
+        conformance_scale::<O>();
+        conformance_rand::<O>();
+        conformance_bulk_build::<O>();
+    }
+
+    #[test]
+    fn linear_oram_conformance() {
+        conformance::<LinearOram<u32, 4>>();
+    }
+
+    #[test]
+    fn rec_oram_conformance() {
+        conformance::<RecOram<u32, 4>>();
+    }
+
+    #[test]
+    fn auto_oram_conformance() {
+        conformance::<AutoOram<u32, 4>>();
+        // small enough to start linear and switch to the recursive oram on a doubling, with absent uids
+        let mut oram = AutoOram::<u32, 4>::with_threshold(4, 32);
+        oram.write(1, 3);
+        oram.double_size_and_fork_self();
+        oram.double_size_and_fork_self();
+        assert!(!oram.is_linear());
+        for i in 0..16 {
+            let expected = (i % 4 == 1).then_some(3);
+            assert_eq!(oram.read(i), expected, "uid {}", i);
+        }
+    }
+}
@@ -1,4 +1,13 @@
+use super::evict::{BandwidthStats, EvictionMode, OramScheme};
 use super::fixoram::{BlockId, FixOram};
+use super::memory::MemoryUsage;
+use super::oram::Oram;
+use crate::params::{LINEAR_SCAN_THRESHOLD, POS_MAP_MEMORY_BUDGET};
+use crate::storage::memstore::MemStore;
+use crate::storage::storage::BlockStorage;
+use crate::tree::cipher::StorageKey;
+use crate::tree::forkvec::ForkVec;
+use crate::utils::ct::{cmov, ct_eq};
 use crate::utils::utils::{get_low_bits, RandGen, SimpleVal};
 use bytemuck::{Pod, Zeroable};
 use std::fmt::Debug;
@@ -21,63 +30,103 @@ impl<const B: usize> PosBlock<B> {
 unsafe impl<const B: usize> Zeroable for PosBlock<B> {}
 unsafe impl<const B: usize> Pod for PosBlock<B> {}
 
-struct RecOramPosMap<const N: usize, const B: usize> {
-    base_level_pos: Vec<usize>,
-    base_level_versions: Vec<u8>,
-    ext_levels: Vec<FixOram<PosBlock<B>, N>>,
+struct RecOramPosMap<const N: usize, const B: usize, S: BlockStorage = MemStore> {
+    base_level_pos: ForkVec<usize>,
+    base_level_versions: ForkVec<u8>,
+    // once the base level exceeds the memory budget, it is moved into an oram of position blocks, where block i
+    // holds the positions and versions of uids i * B .. (i + 1) * B; that oram has its own position map, so the
+    // levels recurse until the top one fits in the budget
+    ext_level: Option<Box<RecOram<PosBlock<B>, N, S>>>,
     base_level_log_size: u8,
-    ext_level_log_sizes: Vec<u8>,
     rand_gen: RandGen,
+    linear_scan_threshold: usize,
+    memory_budget: usize,
+    eviction: EvictionMode,
+    scheme: OramScheme,
+    background_eviction: Option<usize>,
+    key: StorageKey, // the key of the levels added later
 }
 
-impl<const N: usize, const B: usize> RecOramPosMap<N, B> {
-    pub fn new(size: usize) -> Self {
-        Self {
-            base_level_pos: vec![0; size],
-            base_level_versions: vec![size.trailing_zeros() as u8; size],
-            ext_levels: Vec::new(),
+// positions per block and blocks per page of the extension levels, so that a page fits in PAGE_SIZE
+const POS_BLOCK_SIZE: usize = 16;
+const POS_BLOCKS_PER_PAGE: usize = 8;
+
+// bytes of a position and a version in the base level
+pub(crate) const BASE_ENTRY_SIZE: usize = std::mem::size_of::<usize>() + std::mem::size_of::<u8>();
+
+impl<const N: usize, const B: usize, S: BlockStorage> RecOramPosMap<N, B, S> {
+    pub fn new(
+        size: usize,
+        linear_scan_threshold: usize,
+        memory_budget: usize,
+        eviction: EvictionMode,
+        scheme: OramScheme,
+        background_eviction: Option<usize>,
+        rand_gen: RandGen,
+    ) -> Self {
+        let mut pos_map = Self {
+            base_level_pos: ForkVec::from_elem(0, size),
+            base_level_versions: ForkVec::from_elem(size.trailing_zeros() as u8, size),
+            ext_level: None,
             base_level_log_size: size.trailing_zeros() as u8,
-            ext_level_log_sizes: Vec::new(),
-            rand_gen: RandGen::new(),
+            rand_gen,
+            linear_scan_threshold,
+            memory_budget,
+            eviction,
+            scheme,
+            background_eviction,
+            key: StorageKey::default(),
+        };
+        pos_map.add_level_if_over_budget();
+        pos_map
+    }
+
+    fn add_level_if_over_budget(&mut self) {
+        let len = self.base_level_pos.len();
+        if self.ext_level.is_none() && len * BASE_ENTRY_SIZE > self.memory_budget && len >= 2 * B {
+            self.add_new_level();
         }
     }
 
-    // put data in the base level into a fixoram, and reset a smaller base level
+    // put data in the base level into an oram of position blocks, which keeps a smaller base level of its own
     pub fn add_new_level(&mut self) {
-        let mut new_level = FixOram::new();
         let len = self.base_level_pos.len();
-        let log_len = len.trailing_zeros() as u8;
-        let new_base_len = len / B;
-
-        let mut new_base_level_pos = vec![0 as usize; new_base_len];
-        let new_base_level_versions = vec![log_len as u8; new_base_len];
-        for i in 0..new_base_len {
-            let new_pos: usize = self.rand_gen.gen();
-            new_base_level_pos[i] = new_pos;
-            let mut new_block: PosBlock<B> = PosBlock::new();
+        let mut new_level = RecOram::<PosBlock<B>, N, S>::with_options(
+            len / B,
+            self.linear_scan_threshold,
+            self.memory_budget,
+            self.eviction,
+            self.scheme,
+            self.background_eviction,
+            self.rand_gen.fork(),
+        );
+        new_level.rotate_key(&self.key);
+        let mut blocks = vec![PosBlock::new(); len / B];
+        for (i, block) in blocks.iter_mut().enumerate() {
             for j in 0..B {
-                new_block.pos[j] = self.base_level_pos[i * B + j];
-                new_block.versions[j] = self.base_level_versions[i * B + j];
+                let uid = i * B + j;
+                let version = self.base_level_versions[uid];
+                // after a fork only the entry at the low bits holds the position
+                block.pos[j] = self.base_level_pos[get_low_bits(uid, version)];
+                block.versions[j] = version;
             }
-            new_level.write(
-                &BlockId {
-                    page_idx: i, // deterministic eviction
-                    uid: i,
-                },
-                &new_block,
-                new_pos,
-            );
         }
-        self.ext_levels.push(new_level);
-        self.ext_level_log_sizes.push(log_len);
-        self.base_level_pos = new_base_level_pos;
-        self.base_level_versions = new_base_level_versions;
+        new_level.bulk_build(blocks.into_iter().map(Some).collect());
+        self.ext_level = Some(Box::new(new_level));
+        self.base_level_pos = ForkVec::new();
+        self.base_level_versions = ForkVec::new();
     }
 
     /**
      * Get the position for the given uid and set the new positions for all the relevant uninitialized uids in the map.
      */
     pub fn get_and_set_new_positions(&mut self, uid: usize) -> (usize, u8, Vec<usize>) {
+        if self.ext_level.is_some() {
+            return self.ext_get_and_set_new_positions(uid);
+        }
+        if self.base_level_pos.len() * BASE_ENTRY_SIZE <= self.linear_scan_threshold {
+            return self.scan_and_set_new_positions(uid);
+        }
         let base_idx = uid;
         let version = self.base_level_versions[base_idx];
         let scaling_factor = 1 << (self.base_level_log_size - version);
@@ -93,82 +142,189 @@ impl<const N: usize, const B: usize> RecOramPosMap<N, B> {
         (pos, version, new_positions)
     }
 
+    /**
+     * Same as `get_and_set_new_positions`, but scans the whole base level with conditional moves instead of indexing
+     * it. The version read is revealed anyway by the number of new positions.
+     */
+    fn scan_and_set_new_positions(&mut self, uid: usize) -> (usize, u8, Vec<usize>) {
+        let len = self.base_level_pos.len();
+        let mut version = 0u8;
+        for i in 0..len {
+            cmov(ct_eq(i, uid), &mut version, &self.base_level_versions[i]);
+        }
+        let scaling_factor = 1 << (self.base_level_log_size - version);
+        let actual_base_idx = get_low_bits(uid, version);
+        let mut pos = 0usize;
+        for i in 0..len {
+            cmov(ct_eq(i, actual_base_idx), &mut pos, &self.base_level_pos[i]);
+        }
+        let new_positions: Vec<usize> = (0..scaling_factor).map(|_| self.rand_gen.gen()).collect();
+        for i in 0..len {
+            let hit = ct_eq(get_low_bits(i, version), actual_base_idx);
+            cmov(
+                hit,
+                &mut self.base_level_pos[i],
+                &new_positions[i >> version],
+            );
+            cmov(
+                hit,
+                &mut self.base_level_versions[i],
+                &self.base_level_log_size,
+            );
+        }
+        (pos, version, new_positions)
+    }
+
+    /**
+     * Same as `get_and_set_new_positions` for positions stored in the extension level. The block of the uid is read
+     * and updated in one access, and the forked copies in other blocks, whose number is revealed anyway, are updated
+     * in one access per block.
+     */
+    fn ext_get_and_set_new_positions(&mut self, uid: usize) -> (usize, u8, Vec<usize>) {
+        let log_size = self.base_level_log_size;
+        let rand_gen = &mut self.rand_gen;
+        let ext_level = self.ext_level.as_mut().unwrap();
+        let mut ret = (0, 0, Vec::new());
+        let update_func = |block: Option<PosBlock<B>>| {
+            let mut block = block.unwrap_or_else(PosBlock::new);
+            let version = block.versions[uid % B];
+            let scaling_factor = 1 << (log_size - version);
+            let new_positions: Vec<usize> = (0..scaling_factor).map(|_| rand_gen.gen()).collect();
+            ret = (block.pos[uid % B], version, new_positions);
+            let actual_base_idx = get_low_bits(uid, version);
+            for (i, new_pos) in ret.2.iter().enumerate() {
+                let idx = actual_base_idx + (i << version);
+                if idx / B == uid / B {
+                    block.pos[idx % B] = *new_pos;
+                    block.versions[idx % B] = log_size;
+                }
+            }
+            Some(block)
+        };
+        ext_level.update(uid / B, update_func);
+
+        let (_, version, new_positions) = &ret;
+        let actual_base_idx = get_low_bits(uid, *version);
+        let mut other_blocks: Vec<(usize, Vec<(usize, usize)>)> = Vec::new();
+        for (i, new_pos) in new_positions.iter().enumerate() {
+            let idx = actual_base_idx + (i << version);
+            if idx / B == uid / B {
+                continue;
+            }
+            match other_blocks.last_mut() {
+                Some((block_idx, entries)) if *block_idx == idx / B => {
+                    entries.push((idx % B, *new_pos))
+                }
+                _ => other_blocks.push((idx / B, vec![(idx % B, *new_pos)])),
+            }
+        }
+        for (block_idx, entries) in other_blocks {
+            ext_level.update(block_idx, |block| {
+                let mut block = block.unwrap_or_else(PosBlock::new);
+                for (offset, new_pos) in entries {
+                    block.pos[offset] = new_pos;
+                    block.versions[offset] = log_size;
+                }
+                Some(block)
+            });
+        }
+        ret
+    }
+
+    /**
+     * Assign a fresh random position to every uid and return the positions, used when the oram is built in bulk.
+     */
+    pub fn reset_positions(&mut self) -> Vec<usize> {
+        let size = self.size();
+        let positions: Vec<usize> = (0..size).map(|_| self.rand_gen.gen()).collect();
+        match self.ext_level.as_mut() {
+            Some(ext_level) => {
+                let blocks = positions
+                    .chunks(B)
+                    .map(|chunk| {
+                        Some(PosBlock {
+                            pos: chunk.try_into().unwrap(),
+                            versions: [self.base_level_log_size; B],
+                        })
+                    })
+                    .collect();
+                ext_level.bulk_build(blocks);
+            }
+            None => {
+                self.base_level_pos = ForkVec::from(positions.clone());
+                self.base_level_versions = ForkVec::from_elem(self.base_level_log_size, size);
+            }
+        }
+        positions
+    }
+
     pub fn double_size_and_fork_self(&mut self) {
-        self.base_level_pos.resize(self.base_level_pos.len() * 2, 0);
-        self.base_level_versions
-            .extend_from_within(0..self.base_level_versions.len());
         self.base_level_log_size += 1;
+        match self.ext_level.as_mut() {
+            Some(ext_level) => ext_level.double_size_and_fork_self(),
+            None => {
+                // the versions of the new half point to the low bits, so their positions are only read once written
+                self.base_level_pos.double_with(0);
+                self.base_level_versions.double_and_fork();
+                self.add_level_if_over_budget();
+            }
+        }
     }
 
-    // pub fn get_pos(&mut self, uid: usize) -> (usize, u8, Vec<usize>) {
-    //     let base_idx = uid % self.base_level_pos.len();
-    //     let mut next_version = self.base_level_versions[base_idx];
-    //     let mut next_id = BlockId {
-    //         page_idx: self.base_level_pos[base_idx],
-    //         uid: get_low_bits(uid, next_version),
-    //     };
-
-    //     let scaling_factor = 1 << (self.base_level_log_size - next_version);
-    //     let mut new_positions = vec![0; scaling_factor];
-    //     for i in 0..scaling_factor {
-    //         new_positions[i] = self.rand_gen.gen();
-    //         let idx = next_id.uid + (i << next_version);
-    //         self.base_level_pos[idx] = new_positions[i];
-    //         self.base_level_versions[idx] = self.base_level_log_size;
-    //     }
-    //     let mut remain_offsets = uid / (1 << next_version);
-    //     for (i, level) in self.ext_levels.iter_mut().rev().enumerate() {
-    //         let level_update_func = |pos_block: Option<PosBlock<B>>, id: usize| {
-    //             let old_block_unwrap = pos_block.unwrap_or(PosBlock::new());
-    //             let offset = remain_offsets % B;
-    //             let old_pos = old_block_unwrap.pos[offset];
-    //             let old_next_version = old_block_unwrap.versions[offset];
-    //             let next_level_scaling_factor =
-    //                 1 << (self.ext_level_log_sizes[i] - old_next_version);
-    //             assert!(next_level_scaling_factor >= scaling_factor);
-    //         };
-    //         // let block_offset = remain_offsets % B;
-    //         // remain_offsets /= B;
-    //         // let mut next_pos = 0 as usize;
-
-    //         // let next_new_pos = self.rand_gen.gen();
-    //         // let updated_this_version = self.base_level_versions[i];
-    //         // let updated_next_version = self.ext_level_log_sizes[i + 1];
-    //         // let this_version = next_version;
-    //         // let updated_this_uid = get_low_bits(uid, updated_this_version);
-
-    //         // let update_func = |pos_block: Option<PosBlock<B>>, _| {
-    //         //     let mut ret_block = if let Some(pos_block_unwrap) = pos_block {
-    //         //         next_pos = pos_block_unwrap.pos[block_offset];
-    //         //         next_version = pos_block_unwrap.versions[block_offset];
-    //         //         pos_block_unwrap
-    //         //     } else {
-    //         //         next_pos = self.rand_gen.gen();
-    //         //         next_version = updated_next_version;
-    //         //         PosBlock::new()
-    //         //     };
-    //         //     ret_block.pos[block_offset] = next_new_pos;
-    //         //     ret_block.versions[block_offset] = updated_next_version;
-    //         //     (Some(ret_block), updated_this_uid)
-    //         // };
-    //         // level.update(&next_id, update_func, new_pos);
-    //         // if this_version != updated_this_version {
-    //         //     // the version has changed, need to clone the entry
-    //         //     let scale_factor = 1 << (updated_this_version - this_version);
-    //         //     for j in 0..scale_factor {
-    //         //         let uid_to_write = get_low_bits(uid, this_version) * j;
-    //         //         if uid_to_write != updated_this_uid {}
-    //         //     }
-    //         // }
-    //         // next_id.page_idx = next_pos;
-    //         // next_id.uid = get_low_bits(uid, next_version);
-    //         // new_pos = next_new_pos;
-    //     }
-    //     (next_id.page_idx, next_version, vec![0; 0])
-    // }
-
     pub fn size(&self) -> usize {
-        self.base_level_pos.len()
+        match &self.ext_level {
+            Some(ext_level) => ext_level.size() * B,
+            None => self.base_level_pos.len(),
+        }
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        match &self.ext_level {
+            Some(ext_level) => ext_level.memory_usage(),
+            None => MemoryUsage {
+                position_map: self.base_level_pos.len() * BASE_ENTRY_SIZE,
+                ..Default::default()
+            },
+        }
+    }
+
+    /**
+     * Halve the memory budget of the base level, and move the base level into a new level if it no longer fits.
+     * Returns whether the budget of any level was still positive.
+     */
+    pub fn shrink_memory(&mut self) -> bool {
+        let ret = self.memory_budget > 0;
+        self.memory_budget /= 2;
+        match self.ext_level.as_mut() {
+            Some(ext_level) => ext_level.shrink_memory() || ret,
+            None => {
+                self.add_level_if_over_budget();
+                ret
+            }
+        }
+    }
+
+    pub fn rotate_key(&mut self, key: &StorageKey) {
+        self.key = key.clone();
+        if let Some(ext_level) = self.ext_level.as_mut() {
+            ext_level.rotate_key(key);
+        }
+    }
+
+    pub fn sweep_keys(&mut self, max_pages: usize) -> usize {
+        match self.ext_level.as_mut() {
+            Some(ext_level) => ext_level.sweep_keys(max_pages),
+            None => 0,
+        }
+    }
+
+    // number of levels stored in orams
+    #[cfg(test)]
+    pub fn num_ext_levels(&self) -> usize {
+        match &self.ext_level {
+            Some(ext_level) => 1 + ext_level.pos_map.num_ext_levels(),
+            None => 0,
+        }
     }
 
     pub fn print_state(&self) {
@@ -179,49 +335,69 @@ impl<const N: usize, const B: usize> RecOramPosMap<N, B> {
 
     pub fn print_meta_state(&self) {
         println!("RecOramPosMap meta state:");
-        println!(
-            "base_level_memory: {:?} MB",
-            self.base_level_pos.len() as f64 * 9.0 / 1024.0 / 1024.0
-        );
+        match &self.ext_level {
+            Some(ext_level) => {
+                println!("positions of {} uids in an extension level", self.size());
+                ext_level.print_meta_state();
+            }
+            None => println!(
+                "base_level_memory: {:?} MB",
+                (self.base_level_pos.len() * BASE_ENTRY_SIZE) as f64 / 1024.0 / 1024.0
+            ),
+        }
     }
 }
 
-pub struct RecOram<T: SimpleVal, const N: usize> {
-    pos_map: RecOramPosMap<16, 16>,
-    val_ram: FixOram<T, N>,
+/**
+ * Oram of fixed-size blocks with a recursive position map, whose tree pages, those of the position map levels
+ * included, are kept in storages of type `S`.
+ */
+pub struct RecOram<T: SimpleVal, const N: usize, S: BlockStorage = MemStore> {
+    pos_map: RecOramPosMap<POS_BLOCKS_PER_PAGE, POS_BLOCK_SIZE, S>,
+    val_ram: FixOram<T, N, S>,
 }
 
-impl<T: SimpleVal, const N: usize> RecOram<T, N> {
-    pub fn new(size: usize) -> Self {
-        println!("new recoram sizeof T: {}", std::mem::size_of::<T>());
-        Self {
-            pos_map: RecOramPosMap::new(size),
-            val_ram: FixOram::new(),
-        }
+impl<T: SimpleVal, const N: usize, S: BlockStorage> Oram<T> for RecOram<T, N, S> {
+    fn new(size: usize) -> Self {
+        Self::with_linear_scan_threshold(size, LINEAR_SCAN_THRESHOLD)
+    }
+
+    fn with_rand_gen(size: usize, rand_gen: RandGen) -> Self {
+        Self::with_options(
+            size,
+            LINEAR_SCAN_THRESHOLD,
+            POS_MAP_MEMORY_BUDGET,
+            EvictionMode::Greedy,
+            OramScheme::Path,
+            None,
+            rand_gen,
+        )
     }
 
-    pub fn update<F>(&mut self, uid: usize, update_func: F)
+    fn update<F>(&mut self, uid: usize, update_func: F)
     where
         F: FnOnce(Option<T>) -> Option<T>,
     {
         let (page_idx, version, new_positions) = self.pos_map.get_and_set_new_positions(uid);
 
+        // one write back per fork, absent blocks are dummy writes, so that the forks of a removed or never written
+        // block stay absent and the number of writes only depends on the number of forks
         let val_ram_update_func = |val: Option<T>, id: usize| {
-            let old_val_unwrap = val.unwrap_or(T::zeroed());
+            let old_val = val;
             let new_val = update_func(val);
-            let mut ret = Vec::new();
-            ret.reserve(new_positions.len());
-            for (i, new_pos) in new_positions.iter().enumerate() {
-                let uid_to_write = id + (i << version);
-                if uid_to_write == uid {
-                    if new_val.is_some() {
-                        ret.push((new_val.unwrap(), uid_to_write, *new_pos));
-                    }
-                } else {
-                    ret.push((old_val_unwrap, uid_to_write, *new_pos));
-                }
-            }
-            ret
+            new_positions
+                .iter()
+                .enumerate()
+                .map(|(i, new_pos)| {
+                    let uid_to_write = id + (i << version);
+                    let val = if uid_to_write == uid {
+                        new_val
+                    } else {
+                        old_val
+                    };
+                    (val, uid_to_write, *new_pos)
+                })
+                .collect()
         };
         let base_uid = get_low_bits(uid, version);
         self.val_ram.update_and_write_multiple(
@@ -233,42 +409,150 @@ impl<T: SimpleVal, const N: usize> RecOram<T, N> {
         );
     }
 
-    pub fn read(&mut self, uid: usize) -> Option<T> {
-        let mut ret = None;
-        let update_func = |val: Option<T>| {
-            ret = val;
-            val
-        };
-        self.update(uid, update_func);
-        ret
-    }
-
-    pub fn write(&mut self, uid: usize, val: T) {
-        let update_func = |_: Option<T>| Some(val);
-        self.update(uid, update_func);
+    fn bulk_build(&mut self, vals: Vec<Option<T>>) {
+        assert_eq!(vals.len(), self.size());
+        // replace any previous content
+        let max_top_layer_size = self.val_ram.max_top_layer_size();
+        self.val_ram = FixOram::with_options(
+            self.val_ram.eviction(),
+            self.val_ram.scheme(),
+            self.val_ram.background_eviction(),
+            self.pos_map.rand_gen.fork(),
+        );
+        self.val_ram.set_max_top_layer_size(max_top_layer_size);
+        self.val_ram.rotate_key(&self.pos_map.key);
+        let positions = self.pos_map.reset_positions();
+        let num_blocks = vals.len();
+        // the absent uids get no block, the tree is still sized for all the uids
+        let blocks = vals
+            .into_iter()
+            .zip(positions)
+            .enumerate()
+            .filter_map(|(uid, (val, page_idx))| val.map(|val| (BlockId { page_idx, uid }, val)))
+            .collect();
+        self.val_ram.bulk_build(blocks, num_blocks);
     }
 
-    pub fn double_size_and_fork_self(&mut self) {
+    fn double_size_and_fork_self(&mut self) {
         self.pos_map.double_size_and_fork_self();
     }
 
-    pub fn size(&self) -> usize {
+    fn size(&self) -> usize {
         self.pos_map.size()
     }
 
-    pub fn print_state(&self) {
-        println!("RecOram state:");
-        self.pos_map.print_state();
-        // self.val_ram.print_state();
+    fn memory_usage(&self) -> MemoryUsage {
+        self.pos_map.memory_usage() + self.val_ram.memory_usage()
     }
 
-    pub fn print_meta_state(&self) {
+    fn shrink_memory(&mut self) -> bool {
+        let pos_map_shrunk = self.pos_map.shrink_memory();
+        self.val_ram.limit_top_layer() || pos_map_shrunk
+    }
+
+    fn rotate_key(&mut self, key: &StorageKey) {
+        self.pos_map.rotate_key(key);
+        self.val_ram.rotate_key(key);
+    }
+
+    fn sweep_keys(&mut self, max_pages: usize) -> usize {
+        let swept = self.pos_map.sweep_keys(max_pages);
+        swept + self.val_ram.sweep_keys(max_pages - swept)
+    }
+
+    fn print_meta_state(&self) {
         println!("RecOram meta state:");
         self.pos_map.print_meta_state();
         self.val_ram.print_meta_state();
     }
 }
 
+impl<T: SimpleVal, const N: usize, S: BlockStorage> RecOram<T, N, S> {
+    /**
+     * The position map is accessed by a linear scan while it takes at most `linear_scan_threshold` bytes.
+     */
+    pub fn with_linear_scan_threshold(size: usize, linear_scan_threshold: usize) -> Self {
+        Self::with_pos_map_options(size, linear_scan_threshold, POS_MAP_MEMORY_BUDGET)
+    }
+
+    /**
+     * Positions are kept in enclave memory up to `memory_budget` bytes, and beyond that moved into a recursive oram.
+     */
+    pub fn with_pos_map_options(
+        size: usize,
+        linear_scan_threshold: usize,
+        memory_budget: usize,
+    ) -> Self {
+        Self::with_options(
+            size,
+            linear_scan_threshold,
+            memory_budget,
+            EvictionMode::Greedy,
+            OramScheme::Path,
+            None,
+            RandGen::new(),
+        )
+    }
+
+    /**
+     * The blocks and the levels of the position map are stored with the given tree oram scheme.
+     */
+    pub fn with_scheme(size: usize, scheme: OramScheme) -> Self {
+        Self::with_options(
+            size,
+            LINEAR_SCAN_THRESHOLD,
+            POS_MAP_MEMORY_BUDGET,
+            EvictionMode::Greedy,
+            scheme,
+            None,
+            RandGen::new(),
+        )
+    }
+
+    /**
+     * The blocks and the levels of the position map are stored with the given scheme and, for the path scheme,
+     * evicted with the given mode, and in the background every `background_eviction` accesses if set. The
+     * positions, the slots and the nonces are drawn from sources forked from `rand_gen`.
+     */
+    pub fn with_options(
+        size: usize,
+        linear_scan_threshold: usize,
+        memory_budget: usize,
+        eviction: EvictionMode,
+        scheme: OramScheme,
+        background_eviction: Option<usize>,
+        mut rand_gen: RandGen,
+    ) -> Self {
+        println!("new recoram sizeof T: {}", std::mem::size_of::<T>());
+        Self {
+            pos_map: RecOramPosMap::new(
+                size,
+                linear_scan_threshold,
+                memory_budget,
+                eviction,
+                scheme,
+                background_eviction,
+                rand_gen.fork(),
+            ),
+            val_ram: FixOram::with_options(eviction, scheme, background_eviction, rand_gen),
+        }
+    }
+
+    /**
+     * Bytes moved by the oram of the values, the position map levels are not included.
+     */
+    pub fn bandwidth(&self) -> BandwidthStats {
+        self.val_ram.bandwidth()
+    }
+
+    pub fn print_state(&self) {
+        println!("RecOram state:");
+        self.pos_map.print_state();
+        // self.val_ram.print_state();
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
     use rand::random;
@@ -286,6 +570,103 @@ mod tests {
         assert_eq!(rec_oram.read(3), Some(4));
     }
 
+    #[test]
+    fn test_rec_oram_pos_map_scan() {
+        let size = 64;
+        let mut scan_oram: RecOram<u32, 4> = RecOram::with_linear_scan_threshold(size, usize::MAX);
+        let mut index_oram: RecOram<u32, 4> = RecOram::with_linear_scan_threshold(size, 0);
+        for round in 0..3 {
+            for _ in 0..500 {
+                let uid = random::<usize>() % scan_oram.size();
+                let val = random::<u32>();
+                scan_oram.write(uid, val);
+                index_oram.write(uid, val);
+            }
+            for uid in 0..scan_oram.size() {
+                assert_eq!(scan_oram.read(uid), index_oram.read(uid), "round {}", round);
+            }
+            scan_oram.double_size_and_fork_self();
+            index_oram.double_size_and_fork_self();
+        }
+    }
+
+    // compare against a plain vector while doubling the size every `double_every` rounds
+    fn check_against_ref(rec_oram: &mut RecOram<u32, 4>, rounds: usize, double_every: usize) {
+        let mut ref_ram: Vec<Option<u32>> = vec![None; rec_oram.size()];
+        for i in 0..rounds {
+            if i % double_every == double_every - 1 {
+                rec_oram.double_size_and_fork_self();
+                ref_ram.extend_from_within(0..ref_ram.len());
+            }
+            let write_uid = random::<usize>() % ref_ram.len();
+            let val = random::<u32>();
+            ref_ram[write_uid] = Some(val);
+            rec_oram.write(write_uid, val);
+            let read_uid = random::<usize>() % ref_ram.len();
+            assert_eq!(rec_oram.read(read_uid), ref_ram[read_uid]);
+        }
+        for (uid, val) in ref_ram.iter().enumerate() {
+            assert_eq!(rec_oram.read(uid), *val);
+        }
+    }
+
+    #[test]
+    fn test_rec_oram_recursion() {
+        // 64 positions fit in the budget, so the positions of 1024 uids go to one level of 64 position blocks
+        let budget = 64 * BASE_ENTRY_SIZE;
+        let mut rec_oram: RecOram<u32, 4> = RecOram::with_pos_map_options(1024, 0, budget);
+        assert_eq!(rec_oram.pos_map.num_ext_levels(), 1);
+        assert_eq!(rec_oram.size(), 1024);
+        check_against_ref(&mut rec_oram, 3000, 1000);
+        assert_eq!(rec_oram.size(), 8192);
+        assert_eq!(rec_oram.pos_map.num_ext_levels(), 2);
+    }
+
+    #[test]
+    fn test_rec_oram_recursion_grow() {
+        // levels are added while the oram grows from a size that fits in memory, and old forks are resolved
+        // through the levels
+        let budget = 32 * BASE_ENTRY_SIZE;
+        let mut rec_oram: RecOram<u32, 4> = RecOram::with_pos_map_options(4, usize::MAX, budget);
+        assert_eq!(rec_oram.pos_map.num_ext_levels(), 0);
+        check_against_ref(&mut rec_oram, 4000, 400);
+        assert_eq!(rec_oram.size(), 4096);
+        assert!(rec_oram.pos_map.num_ext_levels() >= 2);
+    }
+
+    #[test]
+    fn test_rec_oram_recursion_stale_fork() {
+        let budget = 32 * BASE_ENTRY_SIZE;
+        let mut rec_oram: RecOram<u32, 4> = RecOram::with_pos_map_options(4, usize::MAX, budget);
+        for uid in 0..4 {
+            rec_oram.write(uid, uid as u32 + 1);
+        }
+        for _ in 0..8 {
+            rec_oram.double_size_and_fork_self();
+        }
+        assert!(rec_oram.pos_map.num_ext_levels() >= 1);
+        // every uid still resolves to one of the 4 blocks written before the forks
+        rec_oram.write(6, 100);
+        for uid in 0..rec_oram.size() {
+            let expected = if uid == 6 { 100 } else { (uid % 4) as u32 + 1 };
+            assert_eq!(rec_oram.read(uid), Some(expected), "uid {}", uid);
+        }
+    }
+
+    #[test]
+    fn test_rec_oram_recursion_bulk_build() {
+        let budget = 16 * BASE_ENTRY_SIZE;
+        let mut rec_oram: RecOram<u32, 4> = RecOram::with_pos_map_options(512, 0, budget);
+        assert!(rec_oram.pos_map.num_ext_levels() >= 2);
+        rec_oram.bulk_build((0..512).map(Some).collect());
+        rec_oram.double_size_and_fork_self();
+        rec_oram.write(3, 7);
+        for uid in 0..1024 {
+            let expected = if uid == 3 { 7 } else { (uid % 512) as u32 };
+            assert_eq!(rec_oram.read(uid), Some(expected));
+        }
+    }
+
     #[test]
     fn test_rec_oram_rand() {
         let size = 128;
@@ -378,6 +759,25 @@ mod tests {
         assert_eq!(rec_oram.read(15), Some(4));
     }
 
+    #[test]
+    fn test_rec_oram_bulk_build() {
+        let size = 4096;
+        let mut rec_oram: RecOram<u32, 4> = RecOram::new(size);
+        let mut ref_ram: Vec<u32> = (0..size).map(|_| random::<u32>()).collect();
+        rec_oram.bulk_build(ref_ram.iter().copied().map(Some).collect());
+        for _ in 0..1000 {
+            let write_uid = random::<usize>() % size;
+            let val = random::<u32>();
+            ref_ram[write_uid] = val;
+            rec_oram.write(write_uid, val);
+        }
+        rec_oram.double_size_and_fork_self();
+        ref_ram.extend_from_within(0..size);
+        for (i, val) in ref_ram.iter().enumerate() {
+            assert_eq!(rec_oram.read(i), Some(*val));
+        }
+    }
+
     #[test]
     fn test_rec_oram_rand_scale() {
         let mut size = 128;
@@ -405,4 +805,63 @@ mod tests {
             assert_eq!(real_val, ref_ram[i]);
         }
     }
+
+    #[test]
+    fn test_rec_oram_oblivious_eviction() {
+        let budget = 64 * BASE_ENTRY_SIZE;
+        let mut rec_oram: RecOram<u32, 4> = RecOram::with_options(
+            256,
+            0,
+            budget,
+            EvictionMode::Oblivious,
+            OramScheme::Path,
+            None,
+            RandGen::from_seed(7),
+        );
+        assert_eq!(rec_oram.pos_map.num_ext_levels(), 1);
+        check_against_ref(&mut rec_oram, 2000, 1000);
+        rec_oram.bulk_build((0..rec_oram.size() as u32).map(Some).collect());
+        assert_eq!(rec_oram.val_ram.eviction(), EvictionMode::Oblivious);
+        assert_eq!(rec_oram.read(5), Some(5));
+    }
+
+    #[test]
+    fn test_rec_oram_circuit() {
+        let budget = 64 * BASE_ENTRY_SIZE;
+        let mut rec_oram: RecOram<u32, 4> = RecOram::with_options(
+            256,
+            0,
+            budget,
+            EvictionMode::Greedy,
+            OramScheme::Circuit,
+            None,
+            RandGen::from_seed(7),
+        );
+        check_against_ref(&mut rec_oram, 2000, 1000);
+        assert!(rec_oram.bandwidth().online_bytes > 0);
+        rec_oram.bulk_build((0..rec_oram.size() as u32).map(Some).collect());
+        assert_eq!(rec_oram.val_ram.scheme(), OramScheme::Circuit);
+        assert_eq!(rec_oram.read(5), Some(5));
+    }
+
+    #[test]
+    fn test_rec_oram_shrink_memory() {
+        let budget = 1024 * BASE_ENTRY_SIZE;
+        let mut rec_oram: RecOram<u32, 4> = RecOram::with_pos_map_options(1024, 0, budget);
+        for uid in 0..1024 {
+            rec_oram.write(uid, uid as u32);
+        }
+        assert_eq!(rec_oram.memory_usage().position_map, budget);
+        assert!(rec_oram.shrink_memory());
+        assert_eq!(rec_oram.pos_map.num_ext_levels(), 1);
+        assert!(rec_oram.memory_usage().position_map < budget);
+        while rec_oram.shrink_memory() {}
+        assert!(rec_oram.pos_map.num_ext_levels() >= 2);
+        rec_oram.double_size_and_fork_self();
+        rec_oram.write(3, 7);
+        for uid in 0..2048 {
+            let expected = if uid == 3 { 7 } else { (uid % 1024) as u32 };
+            assert_eq!(rec_oram.read(uid), Some(expected));
+        }
+    }
 }
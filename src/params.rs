@@ -3,3 +3,8 @@ pub const KEY_SIZE: usize = 32;
 pub const MIN_SEGMENT_SIZE: usize = 4096; // Example segment size
 pub const MAX_CACHE_SIZE: usize = 65536; // Equals the number of top-level pages
 pub const ENCRYPT_FLAG: bool = false;
+pub const LINEAR_SCAN_THRESHOLD: usize = 16384; // Tables up to this many bytes are accessed by a linear scan
+pub const POS_MAP_MEMORY_BUDGET: usize = 1 << 20; // Bytes of positions kept in enclave memory per recursive oram
+pub const OBLIVIOUS_STASH_SIZE: usize = 16; // Stash entries of a path processed by each oblivious eviction
+pub const OBLIVIOUS_STASH_BYTES: usize = 4 * PAGE_SIZE; // Bytes of the stash of a path in a flexible oblivious eviction
+pub const KEY_SWEEP_RATE: usize = 2; // Pages of a tree re-encrypted per access while a key rotation is in progress
@@ -8,6 +8,8 @@ pub struct MemStore {
 }
 
 impl BlockStorage for MemStore {
+    const IN_MEMORY: bool = true;
+
     fn open<P: AsRef<Path>>(_path: P, total_pages: usize) -> io::Result<Self> {
         Ok(MemStore {
             data: RwLock::new(vec![0; total_pages * PAGE_SIZE]),
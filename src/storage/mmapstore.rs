@@ -0,0 +1,121 @@
+use crate::params::PAGE_SIZE;
+use crate::storage::storage::BlockStorage;
+use memmap2::MmapMut;
+use std::fs::OpenOptions;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::RwLock;
+
+/**
+ * Pages stored in a file mapped into memory, so that reads and writes are memory copies and the OS page cache keeps
+ * the hot pages, e.g., the upper layers of the trees, in memory. The file grows in place and is remapped.
+ */
+pub struct MmapStore {
+    file: std::fs::File,
+    path: std::path::PathBuf,
+    map: RwLock<MmapMut>,
+}
+
+impl BlockStorage for MmapStore {
+    fn open<P: AsRef<Path>>(path: P, total_pages: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        file.set_len(file_len(total_pages)?)?;
+        // the file is only accessed through the map while the store is alive
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        Ok(MmapStore {
+            file,
+            path: path.as_ref().to_path_buf(),
+            map: RwLock::new(map),
+        })
+    }
+
+    fn read(&self, block_idx: usize, buf: &mut [u8]) -> io::Result<()> {
+        let map = self.map.read().unwrap();
+        buf.copy_from_slice(&map[page_range(map.len(), block_idx)?]);
+        Ok(())
+    }
+
+    fn write(&self, block_idx: usize, buf: &[u8]) -> io::Result<()> {
+        let mut map = self.map.write().unwrap();
+        let range = page_range(map.len(), block_idx)?;
+        map[range].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn grow(&mut self, total_pages: usize) -> io::Result<bool> {
+        let map = self.map.get_mut().unwrap();
+        map.flush_async()?;
+        self.file.set_len(file_len(total_pages)?)?;
+        *map = unsafe { MmapMut::map_mut(&self.file)? };
+        Ok(true)
+    }
+}
+
+// the indices may come from a client of the block server, so they are checked instead of trusted
+fn page_range(map_len: usize, block_idx: usize) -> io::Result<Range<usize>> {
+    block_idx
+        .checked_mul(PAGE_SIZE)
+        .filter(|start| *start < map_len && map_len - start >= PAGE_SIZE)
+        .map(|start| start..start + PAGE_SIZE)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("page {} out of range", block_idx),
+            )
+        })
+}
+
+fn file_len(total_pages: usize) -> io::Result<u64> {
+    total_pages
+        .checked_mul(PAGE_SIZE)
+        .map(|len| len as u64)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("store of {} pages too large", total_pages),
+            )
+        })
+}
+
+impl Drop for MmapStore {
+    fn drop(&mut self) {
+        // remove the file when the MmapStore instance goes out of scope
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mmap_store() {
+        let path = format!("mmapstore_{}.dat", rand::random::<u64>());
+        let mut store = MmapStore::open(&path, 4).unwrap();
+        let page = [7u8; PAGE_SIZE];
+        store.write(3, &page).unwrap();
+        assert!(store.grow(8).unwrap());
+        let mut buf = [1u8; PAGE_SIZE];
+        store.read(3, &mut buf).unwrap();
+        assert_eq!(buf, page);
+        // the new pages read as zeroes
+        store.read(7, &mut buf).unwrap();
+        assert_eq!(buf, [0u8; PAGE_SIZE]);
+        store.write(7, &page).unwrap();
+        store.read(7, &mut buf).unwrap();
+        assert_eq!(buf, page);
+        // pages past the end are rejected instead of panicking
+        let err = store.read(8, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = store.write(usize::MAX, &page).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        drop(store);
+        assert!(!Path::new(&path).exists());
+    }
+}
@@ -1,3 +1,7 @@
 pub mod memstore;
+pub mod mmapstore;
 pub mod pagefile;
+pub mod remotestore;
 pub mod storage;
+#[cfg(target_os = "linux")]
+pub mod uringstore;
@@ -0,0 +1,675 @@
+/*
+ * Pages kept by an untrusted host outside the enclave and accessed over a TCP or a Unix socket. The host runs a
+ * `BlockServer`, e.g., the `block_server` binary, which keeps the pages of every store in its own storage, and the
+ * stores of a process share one connection, so the pages of a batch, e.g., the pages of a path spread over the
+ * stores of the layers, take one round trip. The pages are encrypted by `EncVec` before they leave the enclave, so
+ * the server only sees which pages are accessed, which is what the orams already hide.
+ *
+ * Every message is a frame of a one-byte tag, the length of the body as a little-endian u32 and the body. A request
+ * is tagged with its operation and a response with its status, the body of an error being the message:
+ *
+ * OPEN  total_pages: u64, name: [u8]          -> id: u64
+ * READ  (id: u64, block_idx: u64)*            -> page*
+ * WRITE (id: u64, block_idx: u64, page)*      -> ()
+ * GROW  id: u64, total_pages: u64             -> grown: u8
+ * CLOSE id: u64                               -> ()
+ *
+ * The requests are idempotent, opening a name again replaces the store with a new one under the same id, so the
+ * client reconnects and resends a request when the connection breaks.
+ */
+use crate::params::PAGE_SIZE;
+use crate::storage::storage::BlockStorage;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+const OP_OPEN: u8 = 1;
+const OP_READ: u8 = 2;
+const OP_WRITE: u8 = 3;
+const OP_GROW: u8 = 4;
+const OP_CLOSE: u8 = 5;
+
+const STATUS_OK: u8 = 0;
+const STATUS_ERR: u8 = 1;
+
+// larger frames are rejected instead of allocated
+const MAX_FRAME_SIZE: usize = 1 << 28;
+
+const RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(50);
+
+/**
+ * Environment variable with the address of the block server, used if `RemoteStore::set_server` is not called.
+ */
+pub const SERVER_ENV: &str = "ORDB_BLOCK_SERVER";
+
+/**
+ * Address of a block server, written `unix:<path>` for a Unix socket and `<host>:<port>` or `tcp:<host>:<port>`
+ * otherwise.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServerAddr {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for ServerAddr {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Infallible> {
+        Ok(match s.strip_prefix("unix:") {
+            Some(path) => ServerAddr::Unix(PathBuf::from(path)),
+            None => ServerAddr::Tcp(s.strip_prefix("tcp:").unwrap_or(s).to_string()),
+        })
+    }
+}
+
+impl fmt::Display for ServerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerAddr::Tcp(addr) => write!(f, "tcp:{}", addr),
+            ServerAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn connect(addr: &ServerAddr) -> io::Result<Self> {
+        match addr {
+            ServerAddr::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)?;
+                // a request is sent as a whole and waits for its response
+                stream.set_nodelay(true)?;
+                Ok(Stream::Tcp(stream))
+            }
+            ServerAddr::Unix(path) => Ok(Stream::Unix(UnixStream::connect(path)?)),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Stream::Tcp(stream) => Ok(Stream::Tcp(stream.try_clone()?)),
+            Stream::Unix(stream) => Ok(Stream::Unix(stream.try_clone()?)),
+        }
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            Stream::Unix(stream) => stream.shutdown(Shutdown::Both),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+fn write_frame<W: Write>(writer: &mut W, tag: u8, body: &[u8]) -> io::Result<()> {
+    writer.write_all(&[tag])?;
+    writer.write_all(&(body.len() as u32).to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.flush()
+}
+
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+    let len = u32::from_le_bytes(header[1..5].try_into().unwrap()) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame too large",
+        ));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body)?;
+    Ok((header[0], body))
+}
+
+fn take_u64(body: &mut &[u8]) -> io::Result<u64> {
+    if body.len() < 8 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "truncated request",
+        ));
+    }
+    let (head, rest) = body.split_at(8);
+    *body = rest;
+    Ok(u64::from_le_bytes(head.try_into().unwrap()))
+}
+
+fn take_page<'a>(body: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    if body.len() < PAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "truncated request",
+        ));
+    }
+    let (page, rest) = body.split_at(PAGE_SIZE);
+    *body = rest;
+    Ok(page)
+}
+
+struct Connection {
+    stream: Stream,
+    reader: BufReader<Stream>,
+    writer: BufWriter<Stream>,
+}
+
+impl Connection {
+    fn connect(addr: &ServerAddr) -> io::Result<Self> {
+        let stream = Stream::connect(addr)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream.try_clone()?),
+            stream,
+        })
+    }
+
+    // the outer error is a broken connection, the inner one an error reported by the server
+    fn exchange(&mut self, op: u8, body: &[u8]) -> io::Result<Result<Vec<u8>, String>> {
+        write_frame(&mut self.writer, op, body)?;
+        let (status, body) = read_frame(&mut self.reader)?;
+        match status {
+            STATUS_OK => Ok(Ok(body)),
+            _ => Ok(Err(String::from_utf8_lossy(&body).into_owned())),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // a dropped connection is broken or replaced, let the server end its thread now
+        let _ = self.stream.shutdown();
+    }
+}
+
+struct Client {
+    addr: ServerAddr,
+    conn: Option<Connection>,
+}
+
+// one connection shared by all the stores, so that a batch over several stores is a single request
+static CLIENT: Mutex<Option<Client>> = Mutex::new(None);
+
+// send a request, reconnecting with a growing backoff while the connection fails
+fn call(op: u8, body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut client = CLIENT.lock().unwrap();
+    if client.is_none() {
+        let addr = std::env::var(SERVER_ENV).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "no block server, see RemoteStore::set_server",
+            )
+        })?;
+        *client = Some(Client {
+            addr: addr.parse().unwrap(),
+            conn: None,
+        });
+    }
+    let client = client.as_mut().unwrap();
+    let mut last_err = None;
+    for attempt in 0..RECONNECT_ATTEMPTS {
+        if attempt > 0 {
+            thread::sleep(RECONNECT_BACKOFF * (1 << (attempt - 1)));
+        }
+        if client.conn.is_none() {
+            match Connection::connect(&client.addr) {
+                Ok(conn) => client.conn = Some(conn),
+                Err(e) => {
+                    last_err = Some(e);
+                    continue;
+                }
+            }
+        }
+        match client.conn.as_mut().unwrap().exchange(op, body) {
+            Ok(Ok(response)) => return Ok(response),
+            Ok(Err(msg)) => return Err(io::Error::other(msg)),
+            Err(e) => {
+                client.conn = None;
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err.unwrap())
+}
+
+/**
+ * A store whose pages are kept by a `BlockServer`. The stores connect to the server set by `set_server`, or else
+ * to the one in the `ORDB_BLOCK_SERVER` environment variable.
+ */
+pub struct RemoteStore {
+    id: u64,
+}
+
+impl RemoteStore {
+    /**
+     * Use the block server at `addr` for the stores opened from now on. The connection is made on the first
+     * request and remade whenever it breaks.
+     */
+    pub fn set_server(addr: ServerAddr) {
+        let mut client = CLIENT.lock().unwrap();
+        if client.as_ref().is_none_or(|client| client.addr != addr) {
+            *client = Some(Client { addr, conn: None });
+        }
+    }
+}
+
+impl BlockStorage for RemoteStore {
+    fn open<P: AsRef<Path>>(path: P, total_pages: usize) -> io::Result<Self> {
+        let mut body = (total_pages as u64).to_le_bytes().to_vec();
+        body.extend_from_slice(path.as_ref().to_string_lossy().as_bytes());
+        let mut response = &call(OP_OPEN, &body)?[..];
+        Ok(Self {
+            id: take_u64(&mut response)?,
+        })
+    }
+
+    fn read(&self, block_idx: usize, buf: &mut [u8]) -> io::Result<()> {
+        Self::read_batch(&mut [(self, block_idx, buf)])
+    }
+
+    fn write(&self, block_idx: usize, buf: &[u8]) -> io::Result<()> {
+        Self::write_batch(&[(self, block_idx, buf)])
+    }
+
+    fn grow(&mut self, total_pages: usize) -> io::Result<bool> {
+        let mut body = self.id.to_le_bytes().to_vec();
+        body.extend_from_slice(&(total_pages as u64).to_le_bytes());
+        Ok(call(OP_GROW, &body)? == [1])
+    }
+
+    fn read_batch(reads: &mut [(&Self, usize, &mut [u8])]) -> io::Result<()> {
+        let mut body = Vec::with_capacity(reads.len() * 16);
+        for (store, block_idx, _) in reads.iter() {
+            body.extend_from_slice(&store.id.to_le_bytes());
+            body.extend_from_slice(&(*block_idx as u64).to_le_bytes());
+        }
+        let response = call(OP_READ, &body)?;
+        if response.len() != reads.len() * PAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "wrong number of pages",
+            ));
+        }
+        for ((_, _, buf), page) in reads.iter_mut().zip(response.chunks(PAGE_SIZE)) {
+            buf.copy_from_slice(page);
+        }
+        Ok(())
+    }
+
+    fn write_batch(writes: &[(&Self, usize, &[u8])]) -> io::Result<()> {
+        let mut body = Vec::with_capacity(writes.len() * (16 + PAGE_SIZE));
+        for (store, block_idx, buf) in writes.iter() {
+            body.extend_from_slice(&store.id.to_le_bytes());
+            body.extend_from_slice(&(*block_idx as u64).to_le_bytes());
+            body.extend_from_slice(buf);
+        }
+        call(OP_WRITE, &body)?;
+        Ok(())
+    }
+}
+
+impl Drop for RemoteStore {
+    fn drop(&mut self) {
+        // release the pages on the server when the RemoteStore instance goes out of scope
+        let _ = call(OP_CLOSE, &self.id.to_le_bytes());
+    }
+}
+
+/**
+ * A socket the block server accepts connections on.
+ */
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub fn bind(addr: &ServerAddr) -> io::Result<Self> {
+        match addr {
+            ServerAddr::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr)?)),
+            ServerAddr::Unix(path) => Ok(Listener::Unix(UnixListener::bind(path)?)),
+        }
+    }
+
+    /**
+     * The address to connect to, e.g., with the port chosen by the system when binding to port 0.
+     */
+    pub fn local_addr(&self) -> io::Result<ServerAddr> {
+        match self {
+            Listener::Tcp(listener) => Ok(ServerAddr::Tcp(listener.local_addr()?.to_string())),
+            Listener::Unix(listener) => {
+                let addr = listener.local_addr()?;
+                let path = addr.as_pathname().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "unnamed unix socket")
+                })?;
+                Ok(ServerAddr::Unix(path.to_path_buf()))
+            }
+        }
+    }
+
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                Ok(Stream::Tcp(stream))
+            }
+            Listener::Unix(listener) => Ok(Stream::Unix(listener.accept()?.0)),
+        }
+    }
+}
+
+struct Stores<S> {
+    ids: HashMap<String, u64>,
+    // each store with its number of pages, the indices sent by the clients are checked against it
+    stores: HashMap<u64, (S, usize)>,
+}
+
+/**
+ * The untrusted side of `RemoteStore`: serves the pages of the stores opened by the clients, each kept in a storage
+ * `S` named after the store in the directory of the server. The stores outlive the connections, so a client that
+ * reconnects finds its pages, and are dropped when the client closes them.
+ */
+pub struct BlockServer<S: BlockStorage> {
+    dir: PathBuf,
+    stores: RwLock<Stores<S>>,
+}
+
+impl<S: BlockStorage + Send + Sync + 'static> BlockServer<S> {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            stores: RwLock::new(Stores {
+                ids: HashMap::new(),
+                stores: HashMap::new(),
+            }),
+        }
+    }
+
+    /**
+     * Accept connections and serve each on its own thread. Only returns if accepting fails.
+     */
+    pub fn serve(self, listener: Listener) -> io::Result<()> {
+        let server = Arc::new(self);
+        loop {
+            let stream = listener.accept()?;
+            let server = server.clone();
+            thread::spawn(move || {
+                // a broken connection only ends its thread, the client reconnects
+                let _ = server.handle(stream);
+            });
+        }
+    }
+
+    fn handle(&self, stream: Stream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+        loop {
+            let (op, body) = match read_frame(&mut reader) {
+                Ok(frame) => frame,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            match self.dispatch(op, &body) {
+                Ok(response) => write_frame(&mut writer, STATUS_OK, &response)?,
+                Err(e) => write_frame(&mut writer, STATUS_ERR, e.to_string().as_bytes())?,
+            }
+        }
+    }
+
+    fn dispatch(&self, op: u8, mut body: &[u8]) -> io::Result<Vec<u8>> {
+        match op {
+            OP_OPEN => {
+                let total_pages = take_total_pages(&mut body)?;
+                let name = String::from_utf8_lossy(body).into_owned();
+                // the name is the file name of the store, it must not escape the directory
+                if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid store name {:?}", name),
+                    ));
+                }
+                let mut stores = self.stores.write().unwrap();
+                let id = match stores.ids.get(&name) {
+                    Some(id) => *id,
+                    None => stores.ids.len() as u64,
+                };
+                // drop the previous store first, which may remove its file
+                stores.stores.remove(&id);
+                let store = S::open(self.dir.join(&name), total_pages)?;
+                stores.ids.insert(name, id);
+                stores.stores.insert(id, (store, total_pages));
+                Ok(id.to_le_bytes().to_vec())
+            }
+            OP_READ => {
+                let stores = self.stores.read().unwrap();
+                let mut response = Vec::with_capacity(body.len() / 16 * PAGE_SIZE);
+                while !body.is_empty() {
+                    let id = take_u64(&mut body)?;
+                    let block_idx = take_u64(&mut body)? as usize;
+                    let store = Self::store(&stores, id, block_idx)?;
+                    let start = response.len();
+                    response.resize(start + PAGE_SIZE, 0);
+                    store.read(block_idx, &mut response[start..])?;
+                }
+                Ok(response)
+            }
+            OP_WRITE => {
+                let stores = self.stores.read().unwrap();
+                while !body.is_empty() {
+                    let id = take_u64(&mut body)?;
+                    let block_idx = take_u64(&mut body)? as usize;
+                    let store = Self::store(&stores, id, block_idx)?;
+                    store.write(block_idx, take_page(&mut body)?)?;
+                }
+                Ok(Vec::new())
+            }
+            OP_GROW => {
+                let id = take_u64(&mut body)?;
+                let total_pages = take_total_pages(&mut body)?;
+                let mut stores = self.stores.write().unwrap();
+                let (store, num_pages) = stores
+                    .stores
+                    .get_mut(&id)
+                    .ok_or_else(|| unknown_store(id))?;
+                let grown = store.grow(total_pages)?;
+                if grown {
+                    *num_pages = total_pages;
+                }
+                Ok(vec![grown as u8])
+            }
+            OP_CLOSE => {
+                let id = take_u64(&mut body)?;
+                // the id stays assigned to the name, closing twice is harmless
+                self.stores.write().unwrap().stores.remove(&id);
+                Ok(Vec::new())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown operation {}", op),
+            )),
+        }
+    }
+
+    // the store holding the page, failing if the page is past its end
+    fn store(stores: &Stores<S>, id: u64, block_idx: usize) -> io::Result<&S> {
+        let (store, num_pages) = stores.stores.get(&id).ok_or_else(|| unknown_store(id))?;
+        if block_idx >= *num_pages {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("page {} out of range of store {}", block_idx, id),
+            ));
+        }
+        Ok(store)
+    }
+}
+
+// a number of pages sent by a client, failing if the size of the store in bytes would overflow
+fn take_total_pages(body: &mut &[u8]) -> io::Result<usize> {
+    let total_pages = take_u64(body)? as usize;
+    if total_pages.checked_mul(PAGE_SIZE).is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("store of {} pages too large", total_pages),
+        ));
+    }
+    Ok(total_pages)
+}
+
+fn unknown_store(id: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("unknown store {}", id))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::params::MIN_SEGMENT_SIZE;
+    use crate::storage::memstore::MemStore;
+    use crate::tree::segvec::SegmentedVec;
+    use std::sync::OnceLock;
+
+    // a loopback server standing in for the host, shared by all the tests as the stores connect to a single server
+    pub(crate) fn start_test_server() {
+        static SERVER: OnceLock<ServerAddr> = OnceLock::new();
+        let addr = SERVER.get_or_init(|| {
+            let listener = Listener::bind(&"tcp:127.0.0.1:0".parse().unwrap()).unwrap();
+            let addr = listener.local_addr().unwrap();
+            thread::spawn(move || BlockServer::<MemStore>::new(".").serve(listener));
+            addr
+        });
+        RemoteStore::set_server(addr.clone());
+    }
+
+    #[test]
+    fn test_remote_store() {
+        start_test_server();
+        let stores: Vec<RemoteStore> = (0..3)
+            .map(|i| {
+                RemoteStore::open(format!("remote_{}_{}", i, rand::random::<u64>()), 16).unwrap()
+            })
+            .collect();
+        let pages: Vec<[u8; PAGE_SIZE]> = (0..3).map(|i| [i as u8 + 1; PAGE_SIZE]).collect();
+        let writes: Vec<(&RemoteStore, usize, &[u8])> = stores
+            .iter()
+            .zip(pages.iter())
+            .enumerate()
+            .map(|(i, (store, page))| (store, i * 5, &page[..]))
+            .collect();
+        RemoteStore::write_batch(&writes).unwrap();
+
+        // break the connection, the next request reconnects and finds the pages
+        if let Some(conn) = &CLIENT.lock().unwrap().as_ref().unwrap().conn {
+            conn.stream.shutdown().unwrap();
+        }
+        let mut bufs = vec![[0u8; PAGE_SIZE]; 3];
+        let mut reads: Vec<(&RemoteStore, usize, &mut [u8])> = stores
+            .iter()
+            .zip(bufs.iter_mut())
+            .enumerate()
+            .map(|(i, (store, buf))| (store, i * 5, &mut buf[..]))
+            .collect();
+        RemoteStore::read_batch(&mut reads).unwrap();
+        assert_eq!(bufs, pages);
+
+        // MemStore does not grow in place, and a closed store is reported by the server
+        let mut store = RemoteStore::open(format!("remote_{}", rand::random::<u64>()), 4).unwrap();
+        assert!(!store.grow(8).unwrap());
+        let id = store.id;
+        drop(store);
+        let closed = RemoteStore { id };
+        let mut buf = [0u8; PAGE_SIZE];
+        assert_eq!(
+            closed.read(1, &mut buf).unwrap_err().kind(),
+            io::ErrorKind::Other
+        );
+        std::mem::forget(closed);
+
+        // a segmented vector whose segments are all on the server
+        let mut vec = SegmentedVec::<u64, RemoteStore>::new();
+        vec.set(3, &1);
+        vec.double_size_and_fork_self();
+        vec.set(MIN_SEGMENT_SIZE + 3, &2);
+        assert_eq!(vec.get(3), Some(1));
+        assert_eq!(vec.get(MIN_SEGMENT_SIZE + 3), Some(2));
+    }
+
+    #[test]
+    fn test_unix_socket() {
+        let path = std::env::temp_dir().join(format!("ordb_{}.sock", rand::random::<u64>()));
+        let addr = ServerAddr::Unix(path.clone());
+        let listener = Listener::bind(&addr).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), addr);
+        let server = Arc::new(BlockServer::<MemStore>::new("."));
+        let stream = thread::spawn(move || {
+            let stream = listener.accept().unwrap();
+            server.handle(stream).unwrap();
+        });
+        let mut conn = Connection::connect(&addr).unwrap();
+        let mut open = 2u64.to_le_bytes().to_vec();
+        open.extend_from_slice(b"unix_store");
+        let id = conn.exchange(OP_OPEN, &open).unwrap().unwrap();
+        let mut write = id.clone();
+        write.extend_from_slice(&1u64.to_le_bytes());
+        write.extend_from_slice(&[9u8; PAGE_SIZE]);
+        assert_eq!(conn.exchange(OP_WRITE, &write).unwrap(), Ok(Vec::new()));
+        let mut read = id.clone();
+        read.extend_from_slice(&1u64.to_le_bytes());
+        assert_eq!(
+            conn.exchange(OP_READ, &read).unwrap(),
+            Ok(vec![9u8; PAGE_SIZE])
+        );
+        // errors are reported without breaking the connection
+        assert!(conn.exchange(OP_READ, &read[..4]).unwrap().is_err());
+        assert!(conn.exchange(OP_OPEN, &[0u8; 8]).unwrap().is_err());
+        let mut out_of_range = id.clone();
+        out_of_range.extend_from_slice(&2u64.to_le_bytes());
+        assert!(conn.exchange(OP_READ, &out_of_range).unwrap().is_err());
+        out_of_range.extend_from_slice(&[9u8; PAGE_SIZE]);
+        assert!(conn.exchange(OP_WRITE, &out_of_range).unwrap().is_err());
+        let mut too_large = u64::MAX.to_le_bytes().to_vec();
+        too_large.extend_from_slice(b"large_store");
+        assert!(conn.exchange(OP_OPEN, &too_large).unwrap().is_err());
+        assert_eq!(conn.exchange(OP_CLOSE, &id).unwrap(), Ok(Vec::new()));
+        assert!(conn.exchange(OP_READ, &read).unwrap().is_err());
+        drop(conn);
+        stream.join().unwrap();
+        let _ = std::fs::remove_file(path);
+    }
+}
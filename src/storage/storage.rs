@@ -1,9 +1,48 @@
 use std::io;
 use std::path::Path;
-pub trait BlockStorage {
+// the stores are shared by the orams of a database, which may be accessed from several threads
+pub trait BlockStorage: Send + Sync {
+    // whether the pages are kept in the memory of the process, which is trusted memory inside an enclave
+    const IN_MEMORY: bool = false;
+
     fn open<P: AsRef<Path>>(path: P, total_pages: usize) -> io::Result<Self>
     where
         Self: Sized;
     fn read(&self, block_idx: usize, buf: &mut [u8]) -> io::Result<()>;
     fn write(&self, block_idx: usize, buf: &[u8]) -> io::Result<()>;
+
+    /**
+     * Extend the storage to `total_pages` pages in place, the new pages reading as zeroes. Returns false if the
+     * storage cannot grow, in which case the caller opens another storage for the new pages.
+     */
+    fn grow(&mut self, _total_pages: usize) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /**
+     * Read one page from each of several storages, e.g., the pages of a path, which spread over the storages of
+     * the layers. Storages that can submit the reads together override it.
+     */
+    fn read_batch(reads: &mut [(&Self, usize, &mut [u8])]) -> io::Result<()>
+    where
+        Self: Sized,
+    {
+        for (store, block_idx, buf) in reads.iter_mut() {
+            store.read(*block_idx, buf)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Write one page to each of several storages, see `read_batch`.
+     */
+    fn write_batch(writes: &[(&Self, usize, &[u8])]) -> io::Result<()>
+    where
+        Self: Sized,
+    {
+        for (store, block_idx, buf) in writes.iter() {
+            store.write(*block_idx, buf)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,278 @@
+/*
+ * Pages stored in a file opened with O_DIRECT and accessed through io_uring, so reads and writes bypass the page
+ * cache. The pages of a batch, e.g., the pages of a path spread over the files of the layers, are submitted together
+ * to a ring shared by all the stores, which takes one system call per path instead of one per page. If io_uring or
+ * O_DIRECT is not available, e.g., in a container that blocks io_uring or on a file system without direct io, the
+ * store falls back to a `PageFile`.
+ */
+use crate::params::PAGE_SIZE;
+use crate::storage::pagefile::PageFile;
+use crate::storage::storage::BlockStorage;
+use io_uring::{opcode, squeue, types, IoUring};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+const RING_ENTRIES: u32 = 256;
+
+// O_DIRECT requires the buffers to be aligned to the logical block size of the device
+#[repr(C, align(4096))]
+#[derive(Clone, Copy)]
+struct AlignedPage([u8; PAGE_SIZE]);
+
+static RING: OnceLock<Option<Mutex<IoUring>>> = OnceLock::new();
+
+fn shared_ring() -> Option<&'static Mutex<IoUring>> {
+    RING.get_or_init(|| IoUring::new(RING_ENTRIES).ok().map(Mutex::new))
+        .as_ref()
+}
+
+// tags the operations of each chunk, so that a completion is only counted for the chunk it belongs to
+static NEXT_TAG: AtomicU64 = AtomicU64::new(0);
+
+// errors of io_uring_enter after which the operations already submitted are still waited for
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EINTR) | Some(libc::EAGAIN) | Some(libc::EBUSY)
+    )
+}
+
+// submit the operations on the pages and wait for all of them, in chunks that fit in the ring. The pages are handed
+// back once the kernel is done with them; if it may still use some of them, they are leaked instead of freed
+fn run_batch(entries: &[squeue::Entry], pages: Vec<AlignedPage>) -> io::Result<Vec<AlignedPage>> {
+    if entries.is_empty() {
+        return Ok(pages);
+    }
+    let mut ring = shared_ring().unwrap().lock().unwrap();
+    for chunk in entries.chunks(RING_ENTRIES as usize) {
+        let (ret, drained) = run_chunk(&mut ring, chunk);
+        if !drained {
+            std::mem::forget(pages);
+            return Err(ret.unwrap_err());
+        }
+        ret?;
+    }
+    Ok(pages)
+}
+
+// the chunk only returns once the kernel is done with every operation pushed, even if one of them fails, unless
+// waiting fails; the flag tells whether every operation pushed has completed
+fn run_chunk(ring: &mut IoUring, chunk: &[squeue::Entry]) -> (io::Result<()>, bool) {
+    let tag = NEXT_TAG.fetch_add(1, Ordering::Relaxed);
+    let mut ret = Ok(());
+    let mut num_pushed = 0;
+    {
+        let mut submission = ring.submission();
+        for entry in chunk {
+            // the buffers outlive the chunk, or are leaked if an operation pushed may not have completed
+            if unsafe { submission.push(&entry.clone().user_data(tag)) }.is_err() {
+                ret = Err(io::Error::other("submission queue full"));
+                break;
+            }
+            num_pushed += 1;
+        }
+    }
+    let mut num_completed = 0;
+    while num_completed < num_pushed {
+        if let Err(err) = ring.submit_and_wait(num_pushed - num_completed) {
+            if !is_transient(&err) {
+                // the completions of the operations in flight are left to be skipped by the next chunks
+                return (Err(err), false);
+            }
+        }
+        for cqe in ring.completion() {
+            if cqe.user_data() != tag {
+                continue;
+            }
+            num_completed += 1;
+            if cqe.result() < 0 {
+                ret = Err(io::Error::from_raw_os_error(-cqe.result()));
+            } else if cqe.result() as usize != PAGE_SIZE {
+                ret = Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "short page transfer",
+                ));
+            }
+        }
+    }
+    (ret, true)
+}
+
+enum Backend {
+    Direct { file: File, path: PathBuf },
+    Fallback(PageFile),
+}
+
+pub struct UringStore {
+    backend: Backend,
+}
+
+impl UringStore {
+    fn open_direct<P: AsRef<Path>>(path: P, total_pages: usize) -> io::Result<Self> {
+        shared_ring().ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "no io_uring"))?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(&path)?;
+        file.set_len((total_pages * PAGE_SIZE) as u64)?;
+        let store = Self {
+            backend: Backend::Direct {
+                file,
+                path: path.as_ref().to_path_buf(),
+            },
+        };
+        // some file systems accept the flag but reject direct io, or require offsets aligned to more than a page
+        let mut buf = [0u8; PAGE_SIZE];
+        for block_idx in 0..total_pages.min(2) {
+            store.read(block_idx, &mut buf)?;
+        }
+        Ok(store)
+    }
+
+    /**
+     * Whether the pages go through io_uring with O_DIRECT, rather than the `PageFile` fallback.
+     */
+    pub fn is_direct(&self) -> bool {
+        matches!(self.backend, Backend::Direct { .. })
+    }
+}
+
+impl BlockStorage for UringStore {
+    fn open<P: AsRef<Path>>(path: P, total_pages: usize) -> io::Result<Self> {
+        match Self::open_direct(&path, total_pages) {
+            Ok(store) => Ok(store),
+            Err(_) => Ok(Self {
+                backend: Backend::Fallback(PageFile::open(path, total_pages)?),
+            }),
+        }
+    }
+
+    fn read(&self, block_idx: usize, buf: &mut [u8]) -> io::Result<()> {
+        Self::read_batch(&mut [(self, block_idx, buf)])
+    }
+
+    fn write(&self, block_idx: usize, buf: &[u8]) -> io::Result<()> {
+        Self::write_batch(&[(self, block_idx, buf)])
+    }
+
+    fn read_batch(reads: &mut [(&Self, usize, &mut [u8])]) -> io::Result<()> {
+        let mut pages = vec![AlignedPage([0; PAGE_SIZE]); reads.len()];
+        let mut entries = Vec::new();
+        for ((store, block_idx, buf), page) in reads.iter_mut().zip(pages.iter_mut()) {
+            match &store.backend {
+                Backend::Direct { file, .. } => entries.push(
+                    opcode::Read::new(
+                        types::Fd(file.as_raw_fd()),
+                        page.0.as_mut_ptr(),
+                        PAGE_SIZE as u32,
+                    )
+                    .offset((*block_idx * PAGE_SIZE) as u64)
+                    .build(),
+                ),
+                Backend::Fallback(page_file) => page_file.read(*block_idx, buf)?,
+            }
+        }
+        let pages = run_batch(&entries, pages)?;
+        for ((store, _, buf), page) in reads.iter_mut().zip(pages.iter()) {
+            if store.is_direct() {
+                buf.copy_from_slice(&page.0);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_batch(writes: &[(&Self, usize, &[u8])]) -> io::Result<()> {
+        let mut pages = vec![AlignedPage([0; PAGE_SIZE]); writes.len()];
+        let mut entries = Vec::new();
+        for ((store, block_idx, buf), page) in writes.iter().zip(pages.iter_mut()) {
+            match &store.backend {
+                Backend::Direct { file, .. } => {
+                    page.0.copy_from_slice(buf);
+                    entries.push(
+                        opcode::Write::new(
+                            types::Fd(file.as_raw_fd()),
+                            page.0.as_ptr(),
+                            PAGE_SIZE as u32,
+                        )
+                        .offset((*block_idx * PAGE_SIZE) as u64)
+                        .build(),
+                    );
+                }
+                Backend::Fallback(page_file) => page_file.write(*block_idx, buf)?,
+            }
+        }
+        run_batch(&entries, pages)?;
+        Ok(())
+    }
+}
+
+impl Drop for UringStore {
+    fn drop(&mut self) {
+        // remove the file when the UringStore instance goes out of scope, the fallback removes its own
+        if let Backend::Direct { path, .. } = &self.backend {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uring_store() {
+        let paths: Vec<String> = (0..3)
+            .map(|_| format!("uringstore_{}.dat", rand::random::<u64>()))
+            .collect();
+        let stores: Vec<UringStore> = paths
+            .iter()
+            .map(|path| UringStore::open(path, 16).unwrap())
+            .collect();
+        let pages: Vec<[u8; PAGE_SIZE]> = (0..3).map(|i| [i as u8 + 1; PAGE_SIZE]).collect();
+        let writes: Vec<(&UringStore, usize, &[u8])> = stores
+            .iter()
+            .zip(pages.iter())
+            .enumerate()
+            .map(|(i, (store, page))| (store, i * 5, &page[..]))
+            .collect();
+        UringStore::write_batch(&writes).unwrap();
+        let mut bufs = vec![[0u8; PAGE_SIZE]; 3];
+        let mut reads: Vec<(&UringStore, usize, &mut [u8])> = stores
+            .iter()
+            .zip(bufs.iter_mut())
+            .enumerate()
+            .map(|(i, (store, buf))| (store, i * 5, &mut buf[..]))
+            .collect();
+        UringStore::read_batch(&mut reads).unwrap();
+        assert_eq!(bufs, pages);
+        let mut buf = [1u8; PAGE_SIZE];
+        stores[0].read(15, &mut buf).unwrap();
+        assert_eq!(buf, [0u8; PAGE_SIZE]);
+        if stores[0].is_direct() {
+            // a batch with a failed read still waits for all its reads, so the next batch only sees its own
+            let mut bufs = vec![[0u8; PAGE_SIZE]; 3];
+            let mut reads: Vec<(&UringStore, usize, &mut [u8])> = bufs
+                .iter_mut()
+                .zip([5, 100, 10])
+                .zip(stores.iter())
+                .map(|((buf, block_idx), store)| (store, block_idx, &mut buf[..]))
+                .collect();
+            assert!(UringStore::read_batch(&mut reads).is_err());
+            let mut buf = [0u8; PAGE_SIZE];
+            stores[2].read(10, &mut buf).unwrap();
+            assert_eq!(buf, pages[2]);
+        }
+        drop(stores);
+        for path in paths {
+            assert!(!Path::new(&path).exists());
+        }
+    }
+}
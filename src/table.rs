@@ -0,0 +1,617 @@
+use crate::error::DBError;
+use crate::oblivious::flexomap::FlexOmap;
+use crate::oblivious::memory::MemoryUsage;
+use crate::storage::memstore::MemStore;
+use crate::storage::storage::BlockStorage;
+use crate::tree::cipher::StorageKey;
+use crate::ObliviousDB;
+use std::collections::HashMap;
+
+pub(crate) const DEFAULT_TABLE: &[u8] = b"";
+
+/**
+ * Byte order of the integer values used by `fetch_add`.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+// [name len (u8)][name][key], so that the keys of different tables never collide
+fn table_key(table: &[u8], key: &[u8]) -> Vec<u8> {
+    assert!(table.len() <= u8::MAX as usize, "table name too long");
+    let mut raw_key = Vec::with_capacity(1 + table.len() + key.len());
+    raw_key.push(table.len() as u8);
+    raw_key.extend_from_slice(table);
+    raw_key.extend_from_slice(key);
+    raw_key
+}
+
+fn split_table_key(raw_key: &[u8]) -> (&[u8], &[u8]) {
+    let name_len = raw_key[0] as usize;
+    (&raw_key[1..1 + name_len], &raw_key[1 + name_len..])
+}
+
+/**
+ * All the tables of a database, stored in one map so that the table accessed is hidden.
+ */
+pub(crate) struct Tables<S: BlockStorage> {
+    flexomap: FlexOmap<S>,
+    sizes: HashMap<Vec<u8>, usize>,
+}
+
+impl<S: BlockStorage> Tables<S> {
+    pub fn new(flexomap: FlexOmap<S>) -> Self {
+        Self {
+            flexomap,
+            sizes: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, table: &[u8], key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        self.flexomap.try_get(table_key(table, key))
+    }
+
+    pub fn get_with<R, F: FnOnce(&[u8]) -> R>(
+        &mut self,
+        table: &[u8],
+        key: &[u8],
+        f: F,
+    ) -> Result<Option<R>, DBError> {
+        self.flexomap.try_get_with(table_key(table, key), f)
+    }
+
+    pub fn insert(
+        &mut self,
+        table: &[u8],
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<Option<Vec<u8>>, DBError> {
+        let ret = self.flexomap.try_insert(table_key(table, key), value)?;
+        if ret.is_none() {
+            *self.sizes.entry(table.to_vec()).or_insert(0) += 1;
+        }
+        Ok(ret)
+    }
+
+    pub fn remove(&mut self, table: &[u8], key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        let ret = self.flexomap.try_remove(table_key(table, key))?;
+        if ret.is_some() {
+            *self.sizes.get_mut(table).unwrap() -= 1;
+        }
+        Ok(ret)
+    }
+
+    pub fn update<F>(
+        &mut self,
+        table: &[u8],
+        key: &[u8],
+        update_func: F,
+    ) -> Result<Option<Vec<u8>>, DBError>
+    where
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        let (ret, exist_flag) = self
+            .flexomap
+            .try_update(table_key(table, key), |old_value| {
+                update_func(old_value.as_deref())
+            })?;
+        match (ret.is_some(), exist_flag) {
+            (false, true) => *self.sizes.entry(table.to_vec()).or_insert(0) += 1,
+            (true, false) => *self.sizes.get_mut(table).unwrap() -= 1,
+            _ => {}
+        }
+        Ok(ret)
+    }
+
+    pub fn compare_and_swap(
+        &mut self,
+        table: &[u8],
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool, DBError> {
+        let mut swapped = false;
+        self.update(table, key, |old_value| {
+            if old_value == expected {
+                swapped = true;
+                new.map(|value| value.to_vec())
+            } else {
+                old_value.map(|value| value.to_vec())
+            }
+        })?;
+        Ok(swapped)
+    }
+
+    pub fn fetch_add(
+        &mut self,
+        table: &[u8],
+        key: &[u8],
+        delta: u64,
+        endian: Endian,
+    ) -> Result<u64, DBError> {
+        let mut ret = Ok(0);
+        self.update(table, key, |old_value| {
+            let old_bytes: [u8; 8] = match old_value.map(|value| value.try_into()) {
+                None => [0; 8],
+                Some(Ok(bytes)) => bytes,
+                Some(Err(_)) => {
+                    ret = Err(DBError::InvalidValue(format!(
+                        "expected an 8-byte integer, found {} bytes",
+                        old_value.unwrap().len()
+                    )));
+                    return old_value.map(|value| value.to_vec());
+                }
+            };
+            let old = match endian {
+                Endian::Little => u64::from_le_bytes(old_bytes),
+                Endian::Big => u64::from_be_bytes(old_bytes),
+            };
+            ret = Ok(old);
+            let new = old.wrapping_add(delta);
+            Some(match endian {
+                Endian::Little => new.to_le_bytes().to_vec(),
+                Endian::Big => new.to_be_bytes().to_vec(),
+            })
+        })?;
+        ret
+    }
+
+    pub fn append(&mut self, table: &[u8], key: &[u8], suffix: &[u8]) -> Result<(), DBError> {
+        self.update(table, key, |old_value| {
+            Some([old_value.unwrap_or_default(), suffix].concat())
+        })?;
+        Ok(())
+    }
+
+    pub fn bulk_load<K: AsRef<[u8]>, V: AsRef<[u8]>, I: IntoIterator<Item = (K, V)>>(
+        &mut self,
+        table: &[u8],
+        items: I,
+        max_item_len: Option<usize>,
+    ) -> Result<(), DBError> {
+        let raw_items = items
+            .into_iter()
+            .map(|(key, value)| (table_key(table, key.as_ref()), value));
+        // the keys are stored with the table name in front
+        let max_raw_len = max_item_len.map(|len| len + 1 + table.len());
+        self.flexomap.bulk_load_padded(raw_items, max_raw_len)?;
+        self.sizes.insert(table.to_vec(), self.flexomap.size());
+        Ok(())
+    }
+
+    /**
+     * Visit the entries of a table. The whole map is swept, so the access pattern does not depend on the table.
+     */
+    pub fn for_each<F: FnMut(&[u8], &[u8])>(&self, table: &[u8], mut f: F) {
+        self.flexomap.for_each(|raw_key, value| {
+            let (entry_table, key) = split_table_key(raw_key);
+            if entry_table == table {
+                f(key, value);
+            }
+        });
+    }
+
+    /**
+     * Remove all the entries of a table. The entries are found with a sweep and removed one by one, so the number
+     * of accesses reveals the size of the table.
+     */
+    pub fn clear(&mut self, table: &[u8]) -> Result<(), DBError> {
+        let mut keys = Vec::new();
+        self.for_each(table, |key, _| keys.push(key.to_vec()));
+        for key in keys {
+            self.remove(table, &key)?;
+        }
+        Ok(())
+    }
+
+    pub fn size(&self, table: &[u8]) -> usize {
+        self.sizes.get(table).cloned().unwrap_or(0)
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let tables = self
+            .sizes
+            .keys()
+            .map(|name| name.capacity() + std::mem::size_of::<(Vec<u8>, usize)>())
+            .sum();
+        self.flexomap.memory_usage()
+            + MemoryUsage {
+                tables,
+                ..Default::default()
+            }
+    }
+
+    pub fn rotate_key(&mut self, key: &StorageKey) {
+        self.flexomap.rotate_key(key);
+    }
+
+    pub fn finish_key_rotation(&mut self) -> usize {
+        self.flexomap.finish_key_rotation()
+    }
+
+    pub fn print_meta_state(&self) {
+        self.flexomap.print_meta_state();
+    }
+}
+
+/**
+ * A named table of an `ObliviousDB`, created with `ObliviousDB::table`. All the tables share the same oblivious
+ * map, so an access does not reveal which table is accessed.
+ */
+pub struct Table<'a, S: BlockStorage = MemStore> {
+    db: &'a ObliviousDB<S>,
+    name: Vec<u8>,
+}
+
+impl<'a, S: BlockStorage> Table<'a, S> {
+    pub(crate) fn new(db: &'a ObliviousDB<S>, name: &str) -> Self {
+        assert!(name.len() <= u8::MAX as usize, "table name too long");
+        // the empty name is the default table, accessed through the methods of `ObliviousDB` itself
+        assert!(!name.is_empty(), "empty table name");
+        Self {
+            db,
+            name: name.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        std::str::from_utf8(&self.name).unwrap()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.try_get(key).expect("key mismatch")
+    }
+
+    pub fn try_get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        self.db.tables.lock().unwrap().get(&self.name, key)
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        let mut tables = self.db.tables.lock().unwrap();
+        let ret = tables.get_with(&self.name, key, |_| ());
+        ret.expect("key mismatch").is_some()
+    }
+
+    pub fn get_len(&self, key: &[u8]) -> Option<usize> {
+        let mut tables = self.db.tables.lock().unwrap();
+        let ret = tables.get_with(&self.name, key, |value| value.len());
+        ret.expect("key mismatch")
+    }
+
+    pub fn insert<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) {
+        self.try_insert(key, value).expect("key mismatch");
+    }
+
+    pub fn try_insert<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        key: K,
+        value: V,
+    ) -> Result<Option<Vec<u8>>, DBError> {
+        let mut tables = self.db.tables.lock().unwrap();
+        tables.insert(&self.name, key.as_ref(), value.as_ref())
+    }
+
+    pub fn remove(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.try_remove(key).expect("key mismatch")
+    }
+
+    pub fn try_remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        self.db.tables.lock().unwrap().remove(&self.name, key)
+    }
+
+    /**
+     * Read-modify-write a value with a single oblivious access, see `ObliviousDB::update`.
+     */
+    pub fn update<F>(&self, key: &[u8], update_func: F) -> Result<Option<Vec<u8>>, DBError>
+    where
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        let mut tables = self.db.tables.lock().unwrap();
+        tables.update(&self.name, key, update_func)
+    }
+
+    pub fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool, DBError> {
+        let mut tables = self.db.tables.lock().unwrap();
+        tables.compare_and_swap(&self.name, key, expected, new)
+    }
+
+    pub fn fetch_add(&self, key: &[u8], delta: u64, endian: Endian) -> Result<u64, DBError> {
+        let mut tables = self.db.tables.lock().unwrap();
+        tables.fetch_add(&self.name, key, delta, endian)
+    }
+
+    pub fn append(&self, key: &[u8], suffix: &[u8]) -> Result<(), DBError> {
+        self.db
+            .tables
+            .lock()
+            .unwrap()
+            .append(&self.name, key, suffix)
+    }
+
+    /**
+     * Remove all the entries of the table. Requires `KeyStorage::Full`.
+     */
+    pub fn clear(&self) -> Result<(), DBError> {
+        self.db.tables.lock().unwrap().clear(&self.name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.db.tables.lock().unwrap().size(&self.name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /**
+     * Visit every key-value pair of the table. Requires `KeyStorage::Full`.
+     */
+    pub fn for_each<F: FnMut(&[u8], &[u8])>(&self, f: F) {
+        self.db.tables.lock().unwrap().for_each(&self.name, f);
+    }
+
+    pub fn iter(&self) -> std::vec::IntoIter<(Vec<u8>, Vec<u8>)> {
+        let mut entries = Vec::new();
+        self.for_each(|key, value| entries.push((key.to_vec(), value.to_vec())));
+        entries.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::remotestore::tests::start_test_server;
+    use crate::{
+        BlockStorage, DBConfig, DBError, Endian, KeyStorage, MmapStore, ObliviousDB, OramScheme,
+        RemoteStore, StorageKey,
+    };
+
+    #[test]
+    fn read_modify_write_test() {
+        let db = ObliviousDB::new();
+        let counters = db.table("counters");
+        for i in 0..100u64 {
+            for _ in 0..3 {
+                counters
+                    .fetch_add(&i.to_le_bytes(), i, Endian::Little)
+                    .unwrap();
+            }
+        }
+        assert_eq!(
+            counters.fetch_add(&7u64.to_le_bytes(), 0, Endian::Little),
+            Ok(21)
+        );
+        assert_eq!(
+            counters.get(&7u64.to_le_bytes()),
+            Some(21u64.to_le_bytes().to_vec())
+        );
+        assert_eq!(counters.len(), 100);
+
+        assert_eq!(db.fetch_add(b"be", 258, Endian::Big), Ok(0));
+        assert_eq!(db.fetch_add(b"be", u64::MAX, Endian::Big), Ok(258));
+        assert_eq!(db.get(b"be"), Some(257u64.to_be_bytes().to_vec()));
+        db.insert(b"short", [1, 2]);
+        assert!(matches!(
+            db.fetch_add(b"short", 1, Endian::Little),
+            Err(DBError::InvalidValue(_))
+        ));
+        assert_eq!(db.get(b"short"), Some(vec![1, 2]));
+
+        assert_eq!(
+            db.compare_and_swap(b"cas", Some(&[1]), Some(&[2])),
+            Ok(false)
+        );
+        assert_eq!(db.get(b"cas"), None);
+        assert_eq!(db.compare_and_swap(b"cas", None, Some(&[1])), Ok(true));
+        assert_eq!(db.compare_and_swap(b"cas", None, Some(&[3])), Ok(false));
+        assert_eq!(
+            db.compare_and_swap(b"cas", Some(&[1]), Some(&[2])),
+            Ok(true)
+        );
+        assert_eq!(db.get(b"cas"), Some(vec![2]));
+        assert_eq!(db.compare_and_swap(b"cas", Some(&[2]), None), Ok(true));
+        assert_eq!(db.get(b"cas"), None);
+
+        db.append(b"log", b"ab").unwrap();
+        db.append(b"log", b"cd").unwrap();
+        assert_eq!(db.get(b"log"), Some(b"abcd".to_vec()));
+
+        let old = db.update(b"log", |value| value.map(|value| value[1..].to_vec()));
+        assert_eq!(old, Ok(Some(b"abcd".to_vec())));
+        assert_eq!(db.update(b"log", |_| None), Ok(Some(b"bcd".to_vec())));
+        assert_eq!(db.get(b"log"), None);
+        assert_eq!(db.update(b"missing", |_| None), Ok(None));
+        assert_eq!(db.get(b"missing"), None);
+        assert_eq!(counters.len(), 100);
+    }
+
+    #[test]
+    fn len_and_contains_test() {
+        let db = ObliviousDB::new();
+        assert!(db.is_empty());
+        for i in 0..300u32 {
+            db.insert(i.to_le_bytes(), vec![0; i as usize]);
+        }
+        db.table("other").insert(b"key", b"value");
+        assert_eq!(db.len(), 300);
+        assert!(!db.is_empty());
+        for i in 0..600u32 {
+            assert_eq!(db.contains_key(&i.to_le_bytes()), i < 300);
+            let expected = if i < 300 { Some(i as usize) } else { None };
+            assert_eq!(db.get_len(&i.to_le_bytes()), expected);
+        }
+        assert!(!db.contains_key(b"key"));
+        assert!(db.table("other").contains_key(b"key"));
+        assert_eq!(db.table("other").get_len(b"key"), Some(5));
+        db.remove(&0u32.to_le_bytes());
+        assert_eq!(db.len(), 299);
+        assert!(!db.contains_key(&0u32.to_le_bytes()));
+    }
+
+    #[test]
+    #[should_panic(expected = "empty table name")]
+    fn empty_table_name_test() {
+        let db = ObliviousDB::new();
+        db.table("");
+    }
+
+    #[test]
+    fn table_test() {
+        let db = ObliviousDB::with_config(DBConfig {
+            key_storage: KeyStorage::Full,
+            ..Default::default()
+        });
+        let accounts = db.table("accounts");
+        let storage = db.table("storage");
+        for i in 0..1000u32 {
+            accounts.insert(i.to_le_bytes(), [1; 8]);
+            if i % 2 == 0 {
+                storage.insert(i.to_le_bytes(), [2; 32]);
+            }
+        }
+        db.insert(7u32.to_le_bytes(), [3]);
+        assert_eq!(accounts.len(), 1000);
+        assert_eq!(storage.len(), 500);
+        assert_eq!(accounts.get(&4u32.to_le_bytes()), Some(vec![1; 8]));
+        assert_eq!(storage.get(&4u32.to_le_bytes()), Some(vec![2; 32]));
+        assert_eq!(storage.get(&5u32.to_le_bytes()), None);
+        assert_eq!(db.get(&7u32.to_le_bytes()), Some(vec![3]));
+        assert_eq!(db.get(&4u32.to_le_bytes()), None);
+
+        assert_eq!(storage.remove(&4u32.to_le_bytes()), Some(vec![2; 32]));
+        assert_eq!(storage.len(), 499);
+        assert_eq!(accounts.get(&4u32.to_le_bytes()), Some(vec![1; 8]));
+
+        let mut exported: Vec<_> = storage.iter().map(|(key, _)| key).collect();
+        exported.sort();
+        let mut expected: Vec<_> = (0..1000u32)
+            .filter(|i| i % 2 == 0 && *i != 4)
+            .map(|i| i.to_le_bytes().to_vec())
+            .collect();
+        expected.sort();
+        assert_eq!(exported, expected);
+
+        storage.clear().unwrap();
+        assert!(storage.is_empty());
+        assert_eq!(storage.iter().count(), 0);
+        assert_eq!(accounts.len(), 1000);
+        assert_eq!(accounts.iter().count(), 1000);
+        assert_eq!(
+            db.iter().collect::<Vec<_>>(),
+            vec![(7u32.to_le_bytes().to_vec(), vec![3])]
+        );
+    }
+
+    #[test]
+    fn circuit_scheme_test() {
+        // without a linear scan, the position maps of the tables are circuit orams from the start
+        let db = ObliviousDB::with_config(DBConfig {
+            linear_scan_threshold: 0,
+            scheme: OramScheme::Circuit,
+            seed: Some(1),
+            ..Default::default()
+        });
+        let accounts = db.table("accounts");
+        for i in 0..2000u32 {
+            accounts.insert(i.to_le_bytes(), [i as u8; 24]);
+        }
+        for i in (0..2000u32).step_by(3) {
+            assert_eq!(accounts.remove(&i.to_le_bytes()), Some(vec![i as u8; 24]));
+        }
+        for i in 0..2000u32 {
+            let expected = (i % 3 != 0).then(|| vec![i as u8; 24]);
+            assert_eq!(accounts.get(&i.to_le_bytes()), expected);
+        }
+        assert_eq!(accounts.len(), 2000 - 667);
+    }
+
+    // without a linear scan, both the values and the position maps are kept in trees of `S` from the start
+    fn storage_test<S: BlockStorage>() {
+        let db = ObliviousDB::<S>::with_storage(DBConfig {
+            key_storage: KeyStorage::Full,
+            linear_scan_threshold: 0,
+            seed: Some(3),
+            ..Default::default()
+        });
+        let accounts = db.table("accounts");
+        for i in 0..1000u32 {
+            accounts.insert(i.to_le_bytes(), vec![i as u8; i as usize % 200]);
+            db.insert(i.to_le_bytes(), [1]);
+        }
+        for i in (0..1000u32).step_by(3) {
+            assert_eq!(
+                accounts.remove(&i.to_le_bytes()),
+                Some(vec![i as u8; i as usize % 200])
+            );
+        }
+        for i in 0..1000u32 {
+            let expected = (i % 3 != 0).then(|| vec![i as u8; i as usize % 200]);
+            assert_eq!(accounts.get(&i.to_le_bytes()), expected);
+            assert_eq!(db.get(&i.to_le_bytes()), Some(vec![1]));
+        }
+        assert_eq!(accounts.len(), 1000 - 334);
+        assert_eq!(db.iter().count(), 1000);
+    }
+
+    #[test]
+    fn mmap_storage_test() {
+        storage_test::<MmapStore>();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn uring_storage_test() {
+        storage_test::<crate::UringStore>();
+    }
+
+    #[test]
+    fn remote_storage_test() {
+        start_test_server();
+        storage_test::<RemoteStore>();
+    }
+
+    #[test]
+    fn memory_budget_test() {
+        let usage = ObliviousDB::new().memory_usage();
+        assert!(usage.total() > 0);
+        assert_eq!(usage.tables, 0);
+        let db = ObliviousDB::new();
+        db.table("accounts").insert([1], [2]);
+        assert!(db.memory_usage().tables > 0);
+        let db = ObliviousDB::with_config(DBConfig {
+            memory_budget: Some(usage.total() / 2),
+            ..Default::default()
+        });
+        let err = db.table("accounts").try_insert([1], [2]).unwrap_err();
+        assert!(matches!(err, DBError::MemoryBudgetExceeded { .. }));
+        assert_eq!(db.table("accounts").len(), 0);
+        assert_eq!(db.table("accounts").get(&[1]), None);
+    }
+
+    #[test]
+    fn key_rotation_test() {
+        let db = ObliviousDB::new();
+        for i in 0..200u32 {
+            db.insert(i.to_le_bytes(), [i as u8; 40]);
+        }
+        db.rotate_key(&StorageKey::from([1; 32]));
+        for i in 0..100u32 {
+            db.insert(i.to_le_bytes(), [i as u8 + 1; 40]);
+        }
+        // a second rotation completes the first one
+        db.rotate_key(&StorageKey::from([2; 32]));
+        for i in 0..200u32 {
+            let expected = if i < 100 { i as u8 + 1 } else { i as u8 };
+            assert_eq!(db.get(&i.to_le_bytes()), Some(vec![expected; 40]));
+        }
+        assert!(db.finish_key_rotation() > 0);
+        assert_eq!(db.finish_key_rotation(), 0);
+        assert_eq!(db.get(&7u32.to_le_bytes()), Some(vec![8; 40]));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::params::{ENCRYPT_FLAG, KEY_SIZE};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, Nonce, Payload};
+use aes_gcm::Aes256Gcm;
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20poly1305::ChaCha20Poly1305;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+pub const NONCE_SIZE: usize = 12;
+
+/**
+ * A key of the page ciphers, wiped when dropped. It is neither `Copy` nor `Debug`, so the key bytes are only copied
+ * by an explicit `clone`, whose copy is wiped as well, and never end up in a log.
+ */
+#[derive(Clone, Default)]
+pub struct StorageKey([u8; KEY_SIZE]);
+
+impl StorageKey {
+    pub fn as_bytes(&self) -> &[u8; KEY_SIZE] {
+        &self.0
+    }
+}
+
+// the caller's array is a copy, which the caller is left to wipe
+impl From<[u8; KEY_SIZE]> for StorageKey {
+    fn from(bytes: [u8; KEY_SIZE]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Zeroize for StorageKey {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Drop for StorageKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for StorageKey {}
+
+/**
+ * The ciphers a page can be encrypted with. The value of each is the id kept in the page header, so it must not
+ * change once pages are written.
+ * - `Aes256Gcm` is the fastest with AES-NI.
+ * - `Aes256GcmSiv` does not leak the plaintext if a nonce is ever reused, at the cost of a second pass.
+ * - `ChaCha20Poly1305` is the fastest on hosts without AES instructions.
+ * - `Plain` does not encrypt, for layers kept inside the enclave.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CipherKind {
+    Plain = 0,
+    Aes256Gcm = 1,
+    Aes256GcmSiv = 2,
+    ChaCha20Poly1305 = 3,
+}
+
+impl Default for CipherKind {
+    fn default() -> Self {
+        if ENCRYPT_FLAG {
+            CipherKind::Aes256Gcm
+        } else {
+            CipherKind::Plain
+        }
+    }
+}
+
+impl CipherKind {
+    pub fn new_cipher(self, key: &StorageKey) -> Box<dyn PageCipher> {
+        match self {
+            CipherKind::Plain => Box::new(PlainCipher),
+            CipherKind::Aes256Gcm => Box::new(AeadCipher::<Aes256Gcm>::new(key, self)),
+            CipherKind::Aes256GcmSiv => Box::new(AeadCipher::<Aes256GcmSiv>::new(key, self)),
+            CipherKind::ChaCha20Poly1305 => {
+                Box::new(AeadCipher::<ChaCha20Poly1305>::new(key, self))
+            }
+        }
+    }
+}
+
+/**
+ * Authenticated encryption of the body of a page under a nonce kept in the page header, which also authenticates the
+ * associated data, e.g., the header and the position of the page. The plaintexts are wiped when dropped, and so is
+ * the key schedule of each cipher.
+ */
+pub trait PageCipher: Send + Sync {
+    fn kind(&self) -> CipherKind;
+
+    // bytes the ciphertext adds to the plaintext
+    fn overhead(&self) -> usize;
+
+    fn encrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        associated_data: &[u8],
+        plaintext: &[u8],
+    ) -> Zeroizing<Vec<u8>>;
+
+    // None if the ciphertext or the associated data is not authentic
+    fn decrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        associated_data: &[u8],
+        ciphertext: &[u8],
+    ) -> Option<Zeroizing<Vec<u8>>>;
+}
+
+struct AeadCipher<A> {
+    aead: A,
+    kind: CipherKind,
+}
+
+impl<A: Aead + KeyInit> AeadCipher<A> {
+    fn new(key: &StorageKey, kind: CipherKind) -> Self {
+        assert_eq!(std::mem::size_of::<Nonce<A>>(), NONCE_SIZE);
+        Self {
+            aead: A::new_from_slice(key.as_bytes()).unwrap(),
+            kind,
+        }
+    }
+}
+
+impl<A: Aead + AeadCore + Send + Sync> PageCipher for AeadCipher<A> {
+    fn kind(&self) -> CipherKind {
+        self.kind
+    }
+
+    fn overhead(&self) -> usize {
+        std::mem::size_of::<aes_gcm::aead::Tag<A>>()
+    }
+
+    fn encrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        associated_data: &[u8],
+        plaintext: &[u8],
+    ) -> Zeroizing<Vec<u8>> {
+        let payload = Payload {
+            msg: plaintext,
+            aad: associated_data,
+        };
+        let ciphertext = self
+            .aead
+            .encrypt(Nonce::<A>::from_slice(nonce), payload)
+            .expect("encryption failure!");
+        Zeroizing::new(ciphertext)
+    }
+
+    fn decrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        associated_data: &[u8],
+        ciphertext: &[u8],
+    ) -> Option<Zeroizing<Vec<u8>>> {
+        let payload = Payload {
+            msg: ciphertext,
+            aad: associated_data,
+        };
+        self.aead
+            .decrypt(Nonce::<A>::from_slice(nonce), payload)
+            .ok()
+            .map(Zeroizing::new)
+    }
+}
+
+struct PlainCipher;
+
+impl PageCipher for PlainCipher {
+    fn kind(&self) -> CipherKind {
+        CipherKind::Plain
+    }
+
+    fn overhead(&self) -> usize {
+        0
+    }
+
+    fn encrypt(
+        &self,
+        _nonce: &[u8; NONCE_SIZE],
+        _associated_data: &[u8],
+        plaintext: &[u8],
+    ) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(plaintext.to_vec())
+    }
+
+    fn decrypt(
+        &self,
+        _nonce: &[u8; NONCE_SIZE],
+        _associated_data: &[u8],
+        ciphertext: &[u8],
+    ) -> Option<Zeroizing<Vec<u8>>> {
+        Some(Zeroizing::new(ciphertext.to_vec()))
+    }
+}
@@ -1,45 +1,89 @@
+use super::cipher::{CipherKind, StorageKey};
 use super::segvec::SegmentedVec;
+use crate::storage::memstore::MemStore;
+use crate::storage::storage::BlockStorage;
+use crate::utils::ct::ct_lt;
+use crate::utils::utils::RandGen;
 use bytemuck::{Pod, Zeroable};
-pub struct ORAMTree<T: Clone + Copy + Pod + Zeroable> {
-    tree: Vec<SegmentedVec<T>>,
+
+/**
+ * Tree of pages whose layers are stored in storages of type `S`. The pages of a path are read and written in one
+ * batch of the storage.
+ */
+pub struct ORAMTree<T: Clone + Copy + Pod + Zeroable, S: BlockStorage = MemStore> {
+    tree: Vec<SegmentedVec<T, S>>,
     top_vec_max_size: usize,
     total_size: usize,
+    cipher: CipherKind,
+    key: StorageKey,
+    // authenticated with the pages of the tree, along with their layer
+    tree_id: u64,
+    rand_gen: RandGen,
 }
 
-impl<T: Clone + Copy + Pod + Zeroable> ORAMTree<T> {
+impl<T: Clone + Copy + Pod + Zeroable, S: BlockStorage> ORAMTree<T, S> {
     pub fn new(top_vec_max_size: usize) -> Self {
+        Self::with_cipher(top_vec_max_size, CipherKind::default())
+    }
+
+    /**
+     * A tree whose layers encrypt their pages with `cipher`.
+     */
+    pub fn with_cipher(top_vec_max_size: usize, cipher: CipherKind) -> Self {
+        Self::with_options(top_vec_max_size, cipher, RandGen::new())
+    }
+
+    /**
+     * The layers draw their nonces from sources forked from `rand_gen`.
+     */
+    pub fn with_options(
+        top_vec_max_size: usize,
+        cipher: CipherKind,
+        mut rand_gen: RandGen,
+    ) -> Self {
+        let key = StorageKey::default();
+        let tree_id = rand_gen.gen();
         let mut tree = Vec::new();
-        tree.push(SegmentedVec::new());
+        tree.push(SegmentedVec::with_options(
+            cipher,
+            &key,
+            tree_id,
+            0,
+            rand_gen.fork(),
+        ));
         let total_size = tree[0].capacity();
         Self {
             tree,
             top_vec_max_size,
             total_size,
+            cipher,
+            key,
+            tree_id,
+            rand_gen,
         }
     }
 
-    fn read_node(&self, index: usize, level: usize) -> T {
-        self.tree[level]
-            .get(index % self.tree[level].capacity())
-            .unwrap()
-    }
-
     pub fn read_path(&self, index: usize) -> (Vec<T>, Vec<usize>) {
-        let mut path = Vec::new();
-        let mut capacities = Vec::new();
-        path.reserve(self.tree.len());
-        capacities.reserve(self.tree.len());
-        for vec in self.tree.iter() {
-            path.push(vec.get(index % vec.capacity()).unwrap());
-            capacities.push(vec.capacity());
-        }
-        (path, capacities)
+        let reads: Vec<(&SegmentedVec<T, S>, usize)> = self
+            .tree
+            .iter()
+            .map(|vec| (vec, index % vec.capacity()))
+            .collect();
+        let capacities = self.tree.iter().map(|vec| vec.capacity()).collect();
+        (SegmentedVec::get_batch(&reads), capacities)
     }
 
     pub fn write_path(&mut self, index: usize, path: &Vec<T>) {
-        for (i, vec) in self.tree.iter_mut().enumerate() {
-            vec.set(index % vec.capacity(), &path[i]);
-        }
+        let mut writes: Vec<(&mut SegmentedVec<T, S>, usize, &T)> = self
+            .tree
+            .iter_mut()
+            .zip(path.iter())
+            .map(|(vec, page)| {
+                let capacity = vec.capacity();
+                (vec, index % capacity, page)
+            })
+            .collect();
+        SegmentedVec::set_batch(&mut writes);
     }
 
     pub fn scale(&mut self, mut target_branching_factor: usize) {
@@ -66,7 +110,13 @@ impl<T: Clone + Copy + Pod + Zeroable> ORAMTree<T> {
             self.tree.last_mut().unwrap().double_size_and_fork_self();
             if self.tree.last().unwrap().capacity() > self.top_vec_max_size {
                 // add a new layer
-                let mut new_top_vec = SegmentedVec::new();
+                let mut new_top_vec = SegmentedVec::with_options(
+                    self.cipher,
+                    &self.key,
+                    self.tree_id,
+                    self.tree.len() as u32,
+                    self.rand_gen.fork(),
+                );
                 while new_top_vec.capacity() < init_min_layer_size {
                     new_top_vec.double_size_and_fork_self();
                 }
@@ -76,6 +126,68 @@ impl<T: Clone + Copy + Pod + Zeroable> ORAMTree<T> {
         }
     }
 
+    /**
+     * Overwrite all the pages of a layer, e.g., when the tree is built in bulk.
+     */
+    pub fn write_layer(&mut self, level: usize, pages: &[T]) {
+        self.tree[level].overwrite_all(pages);
+    }
+
+    /**
+     * Rotate the key of every layer, see `SegmentedVec::rotate_key`. Layers added later start with the new key.
+     */
+    pub fn rotate_key(&mut self, key: &StorageKey) {
+        self.key = key.clone();
+        for layer in self.tree.iter_mut() {
+            layer.rotate_key(key);
+        }
+    }
+
+    /**
+     * Rewrite up to `max_pages` pages still under the previous key, the layers being swept from the bottom up.
+     * Returns the number of pages visited, 0 once the rotation is complete.
+     */
+    pub fn sweep_keys(&mut self, max_pages: usize) -> usize {
+        let mut swept = 0;
+        for layer in self.tree.iter_mut() {
+            swept += layer.sweep_keys(max_pages - swept);
+        }
+        swept
+    }
+
+    pub fn layer_sizes(&self) -> Vec<usize> {
+        self.tree.iter().map(|vec| vec.capacity()).collect()
+    }
+
+    /**
+     * The path of the given eviction count in reverse-lexicographic order. The page of a path in every layer is
+     * indexed by the lowest bits of the path, so reading the digits of the path from the top layer down and
+     * counting with the first digit as the least significant one is simply counting the paths in order, which
+     * visits the pages of every layer evenly.
+     */
+    pub fn reverse_lex_path(&self, count: usize) -> usize {
+        count & (self.tree[0].capacity() - 1)
+    }
+
+    /**
+     * Stop growing the top layer: once the top layer is doubled past its current size, a new top layer of the current
+     * size is added, so the tree grows in the layers below. Returns whether the top layer could still grow before.
+     */
+    pub fn limit_top_layer(&mut self) -> bool {
+        let top_size = self.min_layer_size();
+        let ret = self.top_vec_max_size > top_size;
+        self.top_vec_max_size = self.top_vec_max_size.min(top_size);
+        ret
+    }
+
+    pub fn max_top_layer_size(&self) -> usize {
+        self.top_vec_max_size
+    }
+
+    pub fn set_max_top_layer_size(&mut self, max_size: usize) {
+        self.top_vec_max_size = max_size;
+    }
+
     pub fn min_layer_size(&self) -> usize {
         self.tree.last().unwrap().capacity()
     }
@@ -84,6 +196,26 @@ impl<T: Clone + Copy + Pod + Zeroable> ORAMTree<T> {
         self.total_size
     }
 
+    /**
+     * The pages of the hash trees of the layers, which are kept in storages of type `S` as well, and the bytes of
+     * hashes kept in memory.
+     */
+    pub fn hash_usage(&self) -> (usize, usize) {
+        self.tree
+            .iter()
+            .map(|vec| vec.hash_usage())
+            .fold((0, 0), |(pages, bytes), (layer_pages, layer_bytes)| {
+                (pages + layer_pages, bytes + layer_bytes)
+            })
+    }
+
+    /**
+     * Whether the pages are kept in the memory of the process rather than in untrusted storage.
+     */
+    pub fn in_memory(&self) -> bool {
+        S::IN_MEMORY
+    }
+
     // pub fn print_state(&self) {
     //     for (i, vec) in self.tree.iter().enumerate() {
     //         println!("Layer {}:", i);
@@ -93,6 +225,18 @@ impl<T: Clone + Copy + Pod + Zeroable> ORAMTree<T> {
     //     }
     // }
 
+    /**
+     * Visit every page layer by layer in index order, so the access pattern is a fixed linear sweep.
+     * The callback receives the index of the page, the size of its layer and the page.
+     */
+    pub fn for_each_page<F: FnMut(usize, usize, &T)>(&self, mut f: F) {
+        for vec in self.tree.iter() {
+            for i in 0..vec.capacity() {
+                f(i, vec.capacity(), &vec.get(i).unwrap());
+            }
+        }
+    }
+
     pub fn get_all(&self) -> Vec<(usize, usize, T)> {
         let mut all = Vec::new();
         all.reserve(self.total_size);
@@ -105,12 +249,13 @@ impl<T: Clone + Copy + Pod + Zeroable> ORAMTree<T> {
     }
 }
 
+// the layer sizes never increase towards the top, so the deepest layer shared by the two indices is the number of
+// layers that tell them apart; every layer is visited, so the running time does not depend on the indices
 pub fn calc_deepest(self_idx: usize, other_idx: usize, layer_log_sizes: &Vec<u8>) -> u8 {
-    let tzcnt = (self_idx ^ other_idx).trailing_zeros() as u8;
-    for (i, log_layer_size) in layer_log_sizes.iter().enumerate() {
-        if tzcnt >= *log_layer_size {
-            return i as u8;
-        }
+    let tzcnt = (self_idx ^ other_idx).trailing_zeros() as usize;
+    let mut deepest = 0;
+    for log_layer_size in layer_log_sizes.iter() {
+        deepest += ct_lt(tzcnt, *log_layer_size as usize) as u8;
     }
-    layer_log_sizes.len() as u8
+    deepest
 }
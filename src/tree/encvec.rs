@@ -1,18 +1,70 @@
-use crate::params::{ENCRYPT_FLAG, KEY_SIZE, PAGE_SIZE};
+use super::cipher::{CipherKind, PageCipher, StorageKey, NONCE_SIZE};
+use super::hashtree::HashTree;
+use crate::params::PAGE_SIZE;
 use crate::storage::storage::BlockStorage;
-use aes_gcm::aead::{Aead, KeyInit};
-use aes_gcm::{Aes256Gcm, Nonce};
+use crate::utils::utils::RandGen;
 use bytemuck::{Pod, Zeroable};
 use rand::Rng;
 use std::convert::TryFrom;
-#[derive(Clone, Copy)]
+use std::sync::Mutex;
+use zeroize::Zeroize;
+
+/*
+ * Header of a page, version 3:
+ * [0..2]  length of the body in bytes, little endian, 0 for a page never written, whose bytes are all zeroes
+ * [2]     version of the header
+ * [3]     cipher of the body in the low 7 bits, see `CipherKind`, and the parity of the generation of its key in
+ *         the high bit; only the current key and, while a rotation is in progress, the previous one are in use
+ * [4..16] nonce of the encryption, zeroes for `CipherKind::Plain`
+ * [16..]  body: the ciphertext followed by its tag, or the plaintext
+ * A page whose version or cipher differs from the vector's is rejected rather than misread. The header and the
+ * position of the page, see `PageContext`, are authenticated along with the body, so a page copied to another index,
+ * layer or tree fails to decrypt. An old copy of a page, or a page zeroed to read as never written, fails the check
+ * of the hash tree of the vector instead, which is only kept by the ciphers that authenticate the pages.
+ */
+const PAGE_VERSION: u8 = 3;
+const VERSION_OFFSET: usize = 2;
+const CIPHER_OFFSET: usize = 3;
+const GENERATION_BIT: u8 = 0x80;
+const NONCE_OFFSET: usize = 4;
+const HEADER_SIZE: usize = NONCE_OFFSET + NONCE_SIZE;
+
+// not `Copy`, so that every copy of a page, which holds the plaintext under `CipherKind::Plain`, is wiped on drop
+#[derive(Clone)]
 struct EncPage {
     data: [u8; PAGE_SIZE],
 }
+
+impl Drop for EncPage {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
+}
+
+/**
+ * Where the pages of a vector sit, authenticated with every page: the tree and the layer of the vector, and the
+ * index of its first page in the layer.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PageContext {
+    pub tree_id: u64,
+    pub layer: u32,
+    pub first_index: usize,
+}
+
+const ASSOCIATED_DATA_SIZE: usize = 8 + 4 + 8 + HEADER_SIZE;
+
 pub struct EncVec<T: Clone + Pod + Zeroable, StoreT: BlockStorage> {
     file_pages: StoreT,
     size: usize,
-    cipher: Aes256Gcm,
+    context: PageContext,
+    // the hashes that keep the pages fresh, None under `CipherKind::Plain`, whose pages are kept in trusted memory
+    hashes: Option<HashTree<StoreT>>,
+    cipher: Box<dyn PageCipher>,
+    // the cipher under the previous key while a key rotation is in progress
+    prev_cipher: Option<Box<dyn PageCipher>>,
+    generation: u64,
+    rand_gen: Mutex<RandGen>, // draws the nonces
     phantom: std::marker::PhantomData<T>,
 }
 
@@ -22,82 +74,334 @@ impl EncPage {
             data: [0; PAGE_SIZE],
         }
     }
+
+    fn len(&self) -> u16 {
+        u16::from_le_bytes([self.data[0], self.data[1]])
+    }
+
+    fn nonce(&self) -> &[u8; NONCE_SIZE] {
+        self.data[NONCE_OFFSET..HEADER_SIZE].try_into().unwrap()
+    }
 }
 
 impl<T: Clone + Pod + Zeroable, StoreT: BlockStorage> EncVec<T, StoreT> {
-    pub fn new(size: usize, raw_key: &[u8; KEY_SIZE]) -> Self {
-        let key = (*raw_key).into();
+    pub fn new(size: usize, raw_key: &StorageKey) -> Self {
+        Self::with_cipher(size, raw_key, CipherKind::default())
+    }
+
+    pub fn with_cipher(size: usize, raw_key: &StorageKey, cipher: CipherKind) -> Self {
+        Self::with_keys(
+            size,
+            cipher,
+            0,
+            raw_key,
+            None,
+            PageContext::default(),
+            RandGen::new(),
+        )
+    }
+
+    /**
+     * A vector whose pages are written under the key of the given generation, and may still be read under the
+     * previous key, e.g., a segment added to a vector whose key rotation is in progress.
+     */
+    pub fn with_keys(
+        size: usize,
+        cipher: CipherKind,
+        generation: u64,
+        raw_key: &StorageKey,
+        prev_key: Option<&StorageKey>,
+        context: PageContext,
+        rand_gen: RandGen,
+    ) -> Self {
+        // the name only has to be unique, and must stay so when several vectors are created from the same seed
         let filename_uid = rand::thread_rng().gen::<u64>();
         let filename = format!("encvec_{}.dat", filename_uid);
-        Self {
+        let mut vec = Self {
             file_pages: StoreT::open(filename, size).unwrap(),
             size,
-            cipher: Aes256Gcm::new(&key),
+            context,
+            hashes: (cipher != CipherKind::Plain).then(|| HashTree::new(size)),
+            cipher: cipher.new_cipher(raw_key),
+            prev_cipher: None,
+            generation: 0,
+            rand_gen: Mutex::new(rand_gen),
             phantom: std::marker::PhantomData,
+        };
+        assert!(
+            HEADER_SIZE + std::mem::size_of::<T>() + vec.cipher.overhead() <= PAGE_SIZE,
+            "the encrypted value does not fit in a page"
+        );
+        vec.set_keys(generation, raw_key, prev_key);
+        vec
+    }
+
+    /**
+     * Write the pages under the key of the given generation from now on, and read the pages of the previous
+     * generation under `prev_key`, or reject them if it is None.
+     */
+    pub fn set_keys(
+        &mut self,
+        generation: u64,
+        raw_key: &StorageKey,
+        prev_key: Option<&StorageKey>,
+    ) {
+        let kind = self.cipher.kind();
+        self.cipher = kind.new_cipher(raw_key);
+        self.prev_cipher = prev_key.map(|prev_key| kind.new_cipher(prev_key));
+        self.generation = generation;
+    }
+
+    pub fn cipher(&self) -> CipherKind {
+        self.cipher.kind()
+    }
+
+    fn generation_bit(generation: u64) -> u8 {
+        if generation % 2 == 1 {
+            GENERATION_BIT
+        } else {
+            0
         }
     }
 
-    pub fn get(&self, index: usize, nonce: u32) -> Option<T> {
-        if index < self.size {
-            // perform an AES-NI decryption
-            let mut page = EncPage::new();
-            let err = self.file_pages.read(index, &mut page.data);
-            if err.is_err() {
-                panic!("read error: {:?}", err);
-            }
-            let mut nonce_bytes = [0u8; 12];
-            nonce_bytes[0..4].copy_from_slice(&nonce.to_ne_bytes());
-
-            let nonce = Nonce::from_slice(&nonce_bytes);
-            let len_bytes = [page.data[0], page.data[1]];
-            let len = u16::from_ne_bytes(len_bytes);
-            if len == 0 {
-                return Some(unsafe { std::mem::zeroed() });
-            }
-            if ENCRYPT_FLAG {
-                let decrypted_plaintext = self
-                    .cipher
-                    .decrypt(&nonce, page.data[2..2 + len as usize].as_ref())
-                    .expect("decryption failure!");
-
-                let decrypted_data: &T = bytemuck::from_bytes(&decrypted_plaintext);
-                Some(*decrypted_data)
-            } else {
-                let decrypted_data: &T = bytemuck::from_bytes(&page.data[8..8 + len as usize]);
-                Some(*decrypted_data)
+    // the pages of the hash tree above the page at the index, empty under `CipherKind::Plain`
+    fn hash_path_pages(&self, index: usize) -> Vec<(&StoreT, usize)> {
+        match &self.hashes {
+            Some(hashes) => hashes.path_pages(index),
+            None => Vec::new(),
+        }
+    }
+
+    // the pages at the given indices of several vectors, along with the pages of the hash trees above them, all read
+    // in one batch of the storage
+    fn read_pages(reads: &[(&Self, usize)]) -> Vec<(EncPage, Vec<[u8; PAGE_SIZE]>)> {
+        let hash_reads: Vec<Vec<(&StoreT, usize)>> = reads
+            .iter()
+            .map(|(vec, index)| {
+                assert!(*index < vec.size);
+                vec.hash_path_pages(*index)
+            })
+            .collect();
+        let mut pages: Vec<(EncPage, Vec<[u8; PAGE_SIZE]>)> = hash_reads
+            .iter()
+            .map(|hash_read| (EncPage::new(), vec![[0; PAGE_SIZE]; hash_read.len()]))
+            .collect();
+        let mut batch: Vec<(&StoreT, usize, &mut [u8])> = Vec::new();
+        for (((vec, index), hash_read), (page, hash_path)) in
+            reads.iter().zip(hash_reads.iter()).zip(pages.iter_mut())
+        {
+            batch.push((&vec.file_pages, *index, &mut page.data[..]));
+            for ((level, page_index), hash_page) in hash_read.iter().zip(hash_path.iter_mut()) {
+                batch.push((*level, *page_index, &mut hash_page[..]));
             }
+        }
+        let err = StoreT::read_batch(&mut batch);
+        if err.is_err() {
+            panic!("read error: {:?}", err);
+        }
+        pages
+    }
+
+    fn read_page(&self, index: usize) -> (EncPage, Vec<[u8; PAGE_SIZE]>) {
+        Self::read_pages(&[(self, index)]).pop().unwrap()
+    }
+
+    // the header and the position of the page, authenticated along with its body
+    fn associated_data(&self, index: usize, page: &EncPage) -> [u8; ASSOCIATED_DATA_SIZE] {
+        let mut data = [0; ASSOCIATED_DATA_SIZE];
+        data[0..8].copy_from_slice(&self.context.tree_id.to_le_bytes());
+        data[8..12].copy_from_slice(&self.context.layer.to_le_bytes());
+        let index = (self.context.first_index + index) as u64;
+        data[12..20].copy_from_slice(&index.to_le_bytes());
+        data[20..].copy_from_slice(&page.data[..HEADER_SIZE]);
+        data
+    }
+
+    // panics unless the page is the one last written at the index, or zeroes if it was never written, given the
+    // pages of the hash tree above it
+    fn check_fresh(&self, index: usize, page: &EncPage, hash_path: &[[u8; PAGE_SIZE]]) {
+        if let Some(hashes) = &self.hashes {
+            hashes.verify_path(index, &HashTree::<StoreT>::leaf_hash(&page.data), hash_path);
+        }
+    }
+
+    // the value of the page at the index, or None if the page was never written
+    fn decode(&self, index: usize, page: &EncPage, hash_path: &[[u8; PAGE_SIZE]]) -> Option<T> {
+        let len = page.len() as usize;
+        if len == 0 {
+            self.check_fresh(index, page, hash_path);
+            return None;
+        }
+        assert_eq!(
+            page.data[VERSION_OFFSET], PAGE_VERSION,
+            "unknown page version"
+        );
+        let cipher_id = page.data[CIPHER_OFFSET];
+        assert_eq!(
+            cipher_id & !GENERATION_BIT,
+            self.cipher.kind() as u8,
+            "page encrypted with another cipher"
+        );
+        let cipher = if cipher_id & GENERATION_BIT == Self::generation_bit(self.generation) {
+            &self.cipher
+        } else {
+            self.prev_cipher
+                .as_ref()
+                .expect("page encrypted with a retired key")
+        };
+        let body = page.data.get(HEADER_SIZE..HEADER_SIZE + len);
+        let associated_data = self.associated_data(index, page);
+        let plaintext = body
+            .and_then(|body| cipher.decrypt(page.nonce(), &associated_data, body))
+            .expect("decryption failure!");
+        self.check_fresh(index, page, hash_path);
+        // the buffers are byte arrays, so they are not aligned for T; the plaintext is wiped when dropped
+        Some(bytemuck::pod_read_unaligned(&plaintext))
+    }
+
+    // encrypt the value of the page at the index with a fresh random nonce, which is kept in the header of the page
+    fn encode(&self, index: usize, value: &T) -> EncPage {
+        let mut page = EncPage::new();
+        let kind = self.cipher.kind();
+        if kind != CipherKind::Plain {
+            let nonce: [u8; NONCE_SIZE] = self.rand_gen.lock().unwrap().gen();
+            page.data[NONCE_OFFSET..HEADER_SIZE].copy_from_slice(&nonce);
+        }
+        let len = std::mem::size_of::<T>() + self.cipher.overhead();
+        page.data[0..2].copy_from_slice(&(len as u16).to_le_bytes());
+        page.data[VERSION_OFFSET] = PAGE_VERSION;
+        page.data[CIPHER_OFFSET] = kind as u8 | Self::generation_bit(self.generation);
+        let associated_data = self.associated_data(index, &page);
+        let body = self.cipher.encrypt(
+            page.nonce(),
+            &associated_data,
+            bytemuck::cast_slice(&[*value]),
+        );
+        assert_eq!(body.len(), len);
+        page.data[HEADER_SIZE..HEADER_SIZE + len].copy_from_slice(&body);
+        page
+    }
+
+    /**
+     * Whether the page has been written since the vector was created; a page never written reads as zeroes.
+     */
+    pub fn is_written(&self, index: usize) -> bool {
+        let (page, hash_path) = self.read_page(index);
+        self.check_fresh(index, &page, &hash_path);
+        page.len() != 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<T> {
+        if index < self.size {
+            let (page, hash_path) = self.read_page(index);
+            Some(
+                self.decode(index, &page, &hash_path)
+                    .unwrap_or_else(T::zeroed),
+            )
         } else {
             None
         }
     }
 
-    pub fn put(&self, index: usize, value: &T, nonce: u32) {
+    /**
+     * The value of the page if it was ever written.
+     */
+    pub fn get_written(&self, index: usize) -> Option<T> {
+        assert!(index < self.size);
+        let (page, hash_path) = self.read_page(index);
+        self.decode(index, &page, &hash_path)
+    }
+
+    /**
+     * Same as `get_written` for one page of each of several vectors, e.g., the pages of a path, which the storage
+     * may read in a single batch along with the pages of the hash trees above them.
+     */
+    pub fn get_written_batch(reads: &[(&Self, usize)]) -> Vec<Option<T>> {
+        reads
+            .iter()
+            .zip(Self::read_pages(reads))
+            .map(|((vec, index), (page, hash_path))| vec.decode(*index, &page, &hash_path))
+            .collect()
+    }
+
+    pub fn put(&self, index: usize, value: &T) {
         if index < self.size {
-            // perform an AES-NI encryption
-            let mut page = EncPage::new();
-            let mut nonce_bytes = [0u8; 12];
-            nonce_bytes[0..4].copy_from_slice(&nonce.to_ne_bytes());
-            let nonce = Nonce::from_slice(&nonce_bytes);
-
-            if ENCRYPT_FLAG {
-                let encrypted_data = self
-                    .cipher
-                    .encrypt(&nonce, bytemuck::cast_slice(&[*value]))
-                    .expect("encryption failure!");
-                page.data[0..2].copy_from_slice(&(encrypted_data.len() as u16).to_ne_bytes());
-                page.data[2..encrypted_data.len() + 2].copy_from_slice(encrypted_data.as_ref());
-            } else {
-                let len = std::mem::size_of::<T>() as u16;
-                page.data[0..2].copy_from_slice(&len.to_ne_bytes());
-                page.data[8..len as usize + 8].copy_from_slice(bytemuck::cast_slice(&[*value]));
-            }
-            let err = self.file_pages.write(index, &page.data);
-            if err.is_err() {
-                panic!("write error: {:?}", err);
+            Self::put_batch(&[(self, index, value)]);
+        }
+    }
+
+    /**
+     * Same as `put` for one page of each of several vectors, which the storage may write in a single batch. The
+     * pages of the hash trees above them are read in one batch beforehand, and written back in the same batch as
+     * the pages.
+     */
+    pub fn put_batch(writes: &[(&Self, usize, &T)]) {
+        let mut hash_reads: Vec<Vec<(&StoreT, usize)>> = Vec::with_capacity(writes.len());
+        let mut hash_paths: Vec<Vec<[u8; PAGE_SIZE]>> = Vec::with_capacity(writes.len());
+        for (vec, index, _) in writes.iter() {
+            assert!(*index < vec.size);
+            let hash_read = vec.hash_path_pages(*index);
+            hash_paths.push(vec![[0; PAGE_SIZE]; hash_read.len()]);
+            hash_reads.push(hash_read);
+        }
+        let mut batch: Vec<(&StoreT, usize, &mut [u8])> = hash_reads
+            .iter()
+            .zip(hash_paths.iter_mut())
+            .flat_map(|(hash_read, hash_path)| {
+                hash_read.iter().zip(hash_path.iter_mut()).map(
+                    |((level, page_index), hash_page)| (*level, *page_index, &mut hash_page[..]),
+                )
+            })
+            .collect();
+        let err = StoreT::read_batch(&mut batch);
+        if err.is_err() {
+            panic!("read error: {:?}", err);
+        }
+        let pages: Vec<EncPage> = writes
+            .iter()
+            .zip(hash_paths.iter_mut())
+            .map(|((vec, index, value), hash_path)| {
+                let page = vec.encode(*index, value);
+                // record the page written at the index as the only fresh one
+                if let Some(hashes) = &vec.hashes {
+                    hashes.update_path(
+                        *index,
+                        &HashTree::<StoreT>::leaf_hash(&page.data),
+                        hash_path,
+                    );
+                }
+                page
+            })
+            .collect();
+        let mut batch: Vec<(&StoreT, usize, &[u8])> = Vec::new();
+        for (((vec, index, _), page), (hash_read, hash_path)) in writes
+            .iter()
+            .zip(pages.iter())
+            .zip(hash_reads.iter().zip(hash_paths.iter()))
+        {
+            batch.push((&vec.file_pages, *index, &page.data[..]));
+            for ((level, page_index), hash_page) in hash_read.iter().zip(hash_path.iter()) {
+                batch.push((*level, *page_index, &hash_page[..]));
             }
         }
+        let err = StoreT::write_batch(&batch);
+        if err.is_err() {
+            panic!("write error: {:?}", err);
+        }
+    }
+
+    /**
+     * Rewrite a page under the current key, leaving a page never written untouched.
+     */
+    pub fn reencrypt(&self, index: usize) {
+        if let Some(value) = self.get_written(index) {
+            self.put(index, &value);
+        }
     }
 
+    /**
+     * The bytes of the page as kept in the storage.
+     */
     pub fn raw_get(&self, index: usize) -> Option<[u8; PAGE_SIZE]> {
         if index < self.size {
             let mut page = [0; PAGE_SIZE];
@@ -111,6 +415,9 @@ impl<T: Clone + Pod + Zeroable, StoreT: BlockStorage> EncVec<T, StoreT> {
         }
     }
 
+    /**
+     * Overwrite the bytes of the page in the storage, as the host could: the page is not recorded as fresh.
+     */
     pub fn raw_put(&self, index: usize, value: &[u8; PAGE_SIZE]) {
         if index < self.size {
             let err = self.file_pages.write(index, value);
@@ -119,18 +426,265 @@ impl<T: Clone + Pod + Zeroable, StoreT: BlockStorage> EncVec<T, StoreT> {
             }
         }
     }
+
+    /**
+     * The number of pages of the hash tree kept in storages of the same kind as the pages, and the bytes of hashes
+     * kept in memory.
+     */
+    pub fn hash_usage(&self) -> (usize, usize) {
+        match &self.hashes {
+            Some(hashes) => (hashes.num_level_pages(), hashes.top_bytes()),
+            None => (0, 0),
+        }
+    }
+
+    /**
+     * Extend the vector to `size` pages in place if the storage supports it, see `BlockStorage::grow`.
+     */
+    pub fn grow(&mut self, size: usize) -> bool {
+        let grown = self.file_pages.grow(size).expect("grow error");
+        if grown {
+            if let Some(hashes) = &mut self.hashes {
+                hashes.grow(size);
+            }
+            self.size = size;
+        }
+        grown
+    }
 }
 
+#[cfg(test)]
 mod tests {
     use crate::params::PAGE_SIZE;
+    use crate::storage::memstore::MemStore;
     use crate::storage::pagefile::PageFile;
-    use crate::tree::encvec::EncVec;
+    use crate::storage::storage::BlockStorage;
+    use crate::tree::cipher::{CipherKind, StorageKey};
+    use crate::tree::encvec::{EncVec, PageContext};
+    use crate::utils::utils::RandGen;
+    use std::io;
+    use std::mem::ManuallyDrop;
+    use std::path::Path;
 
     #[test]
     fn it_works() {
-        let vec = EncVec::<u128, PageFile>::new(1024, &[0u8; 32]);
-        vec.put(0, &42, 123);
-        assert_eq!(vec.get(0, 123), Some(42));
+        let vec = EncVec::<u128, PageFile>::new(1024, &StorageKey::default());
+        assert!(!vec.is_written(0));
+        assert_eq!(vec.get(0), Some(0));
+        vec.put(0, &42);
+        assert!(vec.is_written(0));
+        assert_eq!(vec.get(0), Some(42));
+    }
+
+    #[test]
+    fn test_ciphers() {
+        for kind in [
+            CipherKind::Plain,
+            CipherKind::Aes256Gcm,
+            CipherKind::Aes256GcmSiv,
+            CipherKind::ChaCha20Poly1305,
+        ] {
+            let vec = EncVec::<u128, MemStore>::with_cipher(16, &StorageKey::from([7u8; 32]), kind);
+            assert_eq!(vec.cipher(), kind);
+            vec.put(3, &42);
+            assert_eq!(vec.get(3), Some(42));
+            let page = vec.raw_get(3).unwrap();
+            assert_eq!(page[2..4], [3, kind as u8]);
+            // only the plain cipher keeps the value in the clear
+            assert_eq!(
+                page[16..32] == 42u128.to_ne_bytes(),
+                kind == CipherKind::Plain
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "decryption failure!")]
+    fn test_tampered_page() {
+        let vec = EncVec::<u128, MemStore>::with_cipher(
+            16,
+            &StorageKey::from([7u8; 32]),
+            CipherKind::ChaCha20Poly1305,
+        );
+        vec.put(3, &42);
+        let mut page = vec.raw_get(3).unwrap();
+        page[20] ^= 1;
+        vec.raw_put(3, &page);
+        vec.get(3);
+    }
+
+    #[test]
+    #[should_panic(expected = "page encrypted with another cipher")]
+    fn test_other_cipher() {
+        let vec = EncVec::<u128, MemStore>::with_cipher(
+            16,
+            &StorageKey::from([7u8; 32]),
+            CipherKind::Aes256Gcm,
+        );
+        let other = EncVec::<u128, MemStore>::with_cipher(
+            16,
+            &StorageKey::from([7u8; 32]),
+            CipherKind::Aes256GcmSiv,
+        );
+        other.put(3, &42);
+        vec.raw_put(3, &other.raw_get(3).unwrap());
+        vec.get(3);
+    }
+
+    fn context_vec(tree_id: u64, layer: u32) -> EncVec<u128, MemStore> {
+        EncVec::with_keys(
+            16,
+            CipherKind::Aes256Gcm,
+            0,
+            &StorageKey::from([7u8; 32]),
+            None,
+            PageContext {
+                tree_id,
+                layer,
+                first_index: 0,
+            },
+            RandGen::new(),
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "decryption failure!")]
+    fn test_moved_page() {
+        let vec = context_vec(1, 0);
+        vec.put(3, &42);
+        vec.raw_put(5, &vec.raw_get(3).unwrap());
+        vec.get(5);
+    }
+
+    #[test]
+    #[should_panic(expected = "decryption failure!")]
+    fn test_page_of_other_layer() {
+        let vec = context_vec(1, 0);
+        let other = context_vec(1, 1);
+        other.put(3, &42);
+        vec.raw_put(3, &other.raw_get(3).unwrap());
+        vec.get(3);
+    }
+
+    #[test]
+    #[should_panic(expected = "decryption failure!")]
+    fn test_page_of_other_tree() {
+        let vec = context_vec(1, 0);
+        let other = context_vec(2, 0);
+        other.put(3, &42);
+        vec.raw_put(3, &other.raw_get(3).unwrap());
+        vec.get(3);
+    }
+
+    #[test]
+    #[should_panic(expected = "integrity check failure!")]
+    fn test_replayed_page() {
+        let vec = context_vec(1, 0);
+        vec.put(3, &42);
+        let old_page = vec.raw_get(3).unwrap();
+        vec.put(3, &43);
+        vec.raw_put(3, &old_page);
+        vec.get(3);
+    }
+
+    #[test]
+    #[should_panic(expected = "integrity check failure!")]
+    fn test_zeroed_page() {
+        let vec = context_vec(1, 0);
+        vec.put(3, &42);
+        vec.raw_put(3, &[0; PAGE_SIZE]);
+        vec.is_written(3);
+    }
+
+    #[test]
+    fn test_wipe_on_drop() {
+        let mut key = ManuallyDrop::new(StorageKey::from([7u8; 32]));
+        let vec = EncVec::<u128, MemStore>::with_cipher(16, &key, CipherKind::Plain);
+        let page = vec.encode(0, &u128::MAX);
+        assert_eq!(page.data[16..32], [0xff; 16]);
+        let mut page = ManuallyDrop::new(page);
+        // drop in place, so that the bytes left behind can still be read
+        unsafe {
+            ManuallyDrop::drop(&mut key);
+            ManuallyDrop::drop(&mut page);
+        }
+        assert_eq!(key.as_bytes(), &[0; 32]);
+        assert!(page.data.iter().all(|byte| *byte == 0));
+    }
+
+    thread_local! {
+        // calls to (read, write, read_batch, write_batch) of the counting stores of the thread
+        static CALLS: std::cell::Cell<[usize; 4]> = const { std::cell::Cell::new([0; 4]) };
+    }
+
+    fn count_call(op: usize) {
+        CALLS.with(|calls| {
+            let mut counts = calls.get();
+            counts[op] += 1;
+            calls.set(counts);
+        });
+    }
+
+    struct CountingStore(MemStore);
+
+    impl BlockStorage for CountingStore {
+        const IN_MEMORY: bool = true;
+
+        fn open<P: AsRef<Path>>(path: P, total_pages: usize) -> io::Result<Self> {
+            Ok(Self(MemStore::open(path, total_pages)?))
+        }
+
+        fn read(&self, block_idx: usize, buf: &mut [u8]) -> io::Result<()> {
+            count_call(0);
+            self.0.read(block_idx, buf)
+        }
+
+        fn write(&self, block_idx: usize, buf: &[u8]) -> io::Result<()> {
+            count_call(1);
+            self.0.write(block_idx, buf)
+        }
+
+        fn read_batch(reads: &mut [(&Self, usize, &mut [u8])]) -> io::Result<()> {
+            count_call(2);
+            for (store, block_idx, buf) in reads.iter_mut() {
+                store.0.read(*block_idx, buf)?;
+            }
+            Ok(())
+        }
+
+        fn write_batch(writes: &[(&Self, usize, &[u8])]) -> io::Result<()> {
+            count_call(3);
+            for (store, block_idx, buf) in writes.iter() {
+                store.0.write(*block_idx, buf)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_batched_hash_path() {
+        // two levels of hashes are kept in storage above the pages
+        let vec = EncVec::<u128, CountingStore>::with_cipher(
+            100000,
+            &StorageKey::from([7u8; 32]),
+            CipherKind::Aes256Gcm,
+        );
+        let other = EncVec::<u128, CountingStore>::with_cipher(
+            100000,
+            &StorageKey::from([7u8; 32]),
+            CipherKind::Aes256Gcm,
+        );
+        assert_eq!(vec.hash_usage(), (1563 + 25, 25 * 32));
+        CALLS.with(|calls| calls.set([0; 4]));
+        vec.put(70000, &42);
+        assert_eq!(CALLS.with(|calls| calls.get()), [0, 0, 1, 1]);
+        assert_eq!(vec.get(70000), Some(42));
+        assert_eq!(CALLS.with(|calls| calls.get()), [0, 0, 2, 1]);
+        EncVec::put_batch(&[(&vec, 3, &1), (&other, 99999, &2)]);
+        assert_eq!(CALLS.with(|calls| calls.get()), [0, 0, 3, 2]);
+        let values = EncVec::get_written_batch(&[(&vec, 3), (&other, 99999), (&other, 5)]);
+        assert_eq!(values, vec![Some(1), Some(2), None]);
+        assert_eq!(CALLS.with(|calls| calls.get()), [0, 0, 4, 2]);
     }
 
     #[derive(Clone, Copy)]
@@ -153,13 +707,13 @@ mod tests {
     fn enc_perf_test() {
         let num_pages = 1e6 as usize;
         const BUFFER_SIZE: usize = PAGE_SIZE - 64;
-        let mut vec = EncVec::<TestBuffer, PageFile>::new(PAGE_SIZE, &[0u8; 32]);
+        let mut vec = EncVec::<TestBuffer, PageFile>::new(PAGE_SIZE, &StorageKey::default());
         for round in 0..num_pages {
             let mut buffer = TestBuffer::default();
             for i in 0..8 {
                 buffer.data[i] = (round >> (i * 8)) as u8;
             }
-            vec.put(round % BUFFER_SIZE, &buffer, 1);
+            vec.put(round % BUFFER_SIZE, &buffer);
         }
     }
 }
@@ -0,0 +1,150 @@
+/*
+ * Vector of metadata that doubles in O(len / CHUNK_SIZE) instead of O(len). The entries are stored in chunks shared
+ * with reference counts: doubling only clones one pointer per chunk, and a chunk shared by a fork is copied on its
+ * first write, so the copy is spread over the operations that follow the doubling.
+ */
+use std::ops::{Index, IndexMut};
+use std::sync::Arc;
+
+const CHUNK_SIZE: usize = 1024;
+
+#[derive(Clone)]
+pub struct ForkVec<T: Clone> {
+    chunks: Vec<Arc<Vec<T>>>,
+    len: usize,
+}
+
+impl<T: Clone> ForkVec<T> {
+    pub fn new() -> Self {
+        Self {
+            chunks: Vec::new(),
+            len: 0,
+        }
+    }
+
+    // all the full chunks share a single allocation until they are written
+    pub fn from_elem(value: T, len: usize) -> Self {
+        let mut ret = Self::new();
+        ret.extend_with(value, len);
+        ret
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.chunks.iter().flat_map(|chunk| chunk.iter())
+    }
+
+    /**
+     * Double the length, the entry at index + len being a copy of the entry at index.
+     */
+    pub fn double_and_fork(&mut self) {
+        let len = self.len;
+        if len.is_multiple_of(CHUNK_SIZE) {
+            self.chunks.extend_from_within(..);
+            self.len *= 2;
+        } else {
+            // only vectors shorter than a chunk, as the lengths are powers of 2
+            for i in 0..len {
+                self.push(self[i].clone());
+            }
+        }
+    }
+
+    /**
+     * Double the length, the new entries being the given value.
+     */
+    pub fn double_with(&mut self, value: T) {
+        self.extend_with(value, self.len);
+    }
+
+    fn extend_with(&mut self, value: T, count: usize) {
+        let mut count = count;
+        while count > 0 && !self.len.is_multiple_of(CHUNK_SIZE) {
+            self.push(value.clone());
+            count -= 1;
+        }
+        if count >= CHUNK_SIZE {
+            let chunk = Arc::new(vec![value.clone(); CHUNK_SIZE]);
+            while count >= CHUNK_SIZE {
+                self.chunks.push(chunk.clone());
+                self.len += CHUNK_SIZE;
+                count -= CHUNK_SIZE;
+            }
+        }
+        for _ in 0..count {
+            self.push(value.clone());
+        }
+    }
+
+    fn push(&mut self, value: T) {
+        if self.len.is_multiple_of(CHUNK_SIZE) {
+            self.chunks.push(Arc::new(Vec::with_capacity(CHUNK_SIZE)));
+        }
+        Arc::make_mut(self.chunks.last_mut().unwrap()).push(value);
+        self.len += 1;
+    }
+}
+
+impl<T: Clone> From<Vec<T>> for ForkVec<T> {
+    fn from(values: Vec<T>) -> Self {
+        let mut ret = Self::new();
+        for value in values {
+            ret.push(value);
+        }
+        ret
+    }
+}
+
+impl<T: Clone> Index<usize> for ForkVec<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        assert!(index < self.len);
+        &self.chunks[index / CHUNK_SIZE][index % CHUNK_SIZE]
+    }
+}
+
+impl<T: Clone> IndexMut<usize> for ForkVec<T> {
+    // copies the chunk first if it is still shared with a fork
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        assert!(index < self.len);
+        &mut Arc::make_mut(&mut self.chunks[index / CHUNK_SIZE])[index % CHUNK_SIZE]
+    }
+}
+
+impl<T: Clone + std::fmt::Debug> std::fmt::Debug for ForkVec<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fork_vec() {
+        for len in [1, 3, 1000, CHUNK_SIZE, 4 * CHUNK_SIZE] {
+            let mut vec = ForkVec::from((0..len).collect::<Vec<usize>>());
+            let mut ref_vec: Vec<usize> = (0..len).collect();
+            vec.double_and_fork();
+            ref_vec.extend_from_within(..);
+            // writes after the fork do not show in the copies
+            vec[0] = usize::MAX;
+            ref_vec[0] = usize::MAX;
+            vec.double_with(7);
+            ref_vec.resize(ref_vec.len() * 2, 7);
+            let last = ref_vec.len() - 1;
+            vec[last] = 8;
+            ref_vec[last] = 8;
+            assert_eq!(vec.len(), ref_vec.len());
+            assert_eq!(vec.iter().copied().collect::<Vec<usize>>(), ref_vec);
+        }
+        let vec = ForkVec::from_elem(3u8, 2 * CHUNK_SIZE + 5);
+        assert_eq!(vec.len(), 2 * CHUNK_SIZE + 5);
+        assert!(vec.iter().all(|x| *x == 3));
+    }
+}
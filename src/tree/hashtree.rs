@@ -0,0 +1,303 @@
+use crate::params::PAGE_SIZE;
+use crate::storage::storage::BlockStorage;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+
+pub const HASH_SIZE: usize = 32;
+// hashes per page of a level
+const ARITY: usize = PAGE_SIZE / HASH_SIZE;
+const LEAF_DOMAIN: u8 = 0;
+const NODE_DOMAIN: u8 = 1;
+
+type Hash = [u8; HASH_SIZE];
+
+/*
+ * Merkle tree over the pages of a storage, which keeps the pages fresh: a page replayed from an older write, or
+ * zeroed to read as never written, no longer hashes to the value its parent holds.
+ * Level 0 holds the hash of every page, ARITY hashes per page, and each level above holds the hashes of the pages of
+ * the level below, until a level fits in one page. That level is kept in memory, so at most a page of hashes is kept
+ * per storage whatever its size, and the other levels are kept in storages of the same kind as the pages.
+ * A page of zeroes, i.e., a page never written, hashes to zeroes, and so does a page of hashes that are all zeroes.
+ * A new storage, whose pages all read as zeroes, is thus consistent with a tree of zeroes without writing anything.
+ */
+pub struct HashTree<S: BlockStorage> {
+    levels: Vec<S>,
+    // the hashes of the pages of the last level, or of the pages of the storage if there is no level
+    top: Mutex<Vec<Hash>>,
+    size: usize,
+}
+
+fn hash_of(domain: u8, page: &[u8]) -> Hash {
+    if page.iter().all(|byte| *byte == 0) {
+        return [0; HASH_SIZE];
+    }
+    let mut hasher = Sha256::new();
+    hasher.update([domain]);
+    hasher.update(page);
+    hasher.finalize().into()
+}
+
+// the number of entries of each level above `size` pages, up to the level kept in memory
+fn level_sizes(size: usize) -> Vec<usize> {
+    let mut sizes = vec![size];
+    while *sizes.last().unwrap() > ARITY {
+        sizes.push(sizes.last().unwrap().div_ceil(ARITY));
+    }
+    sizes
+}
+
+fn open_level<S: BlockStorage>(num_entries: usize) -> S {
+    let filename = format!("hashtree_{}.dat", rand::thread_rng().gen::<u64>());
+    S::open(filename, num_entries.div_ceil(ARITY)).unwrap()
+}
+
+impl<S: BlockStorage> HashTree<S> {
+    /**
+     * The tree of a storage of `size` pages that are all zeroes.
+     */
+    pub fn new(size: usize) -> Self {
+        let sizes = level_sizes(size);
+        Self {
+            levels: sizes[..sizes.len() - 1]
+                .iter()
+                .map(|num_entries| open_level(*num_entries))
+                .collect(),
+            top: Mutex::new(vec![[0; HASH_SIZE]; *sizes.last().unwrap()]),
+            size,
+        }
+    }
+
+    pub fn leaf_hash(page: &[u8]) -> Hash {
+        hash_of(LEAF_DOMAIN, page)
+    }
+
+    fn read_level(&self, level: usize, page_index: usize) -> [u8; PAGE_SIZE] {
+        let mut page = [0; PAGE_SIZE];
+        let err = self.levels[level].read(page_index, &mut page);
+        if err.is_err() {
+            panic!("read error: {:?}", err);
+        }
+        page
+    }
+
+    /**
+     * The pages of the levels above the page at `index`, from the lowest level up, to be read along with the page
+     * and passed to `verify_path` or `update_path`, so that a path is read and written in the same batches as the
+     * page it covers.
+     */
+    pub fn path_pages(&self, index: usize) -> Vec<(&S, usize)> {
+        assert!(index < self.size);
+        let mut index = index;
+        self.levels
+            .iter()
+            .map(|level| {
+                index /= ARITY;
+                (level, index)
+            })
+            .collect()
+    }
+
+    // checks the pages of the levels above a page against each other and the top
+    fn check_path(&self, index: usize, path: &[[u8; PAGE_SIZE]], top: &[Hash]) {
+        assert_eq!(path.len(), self.levels.len());
+        let mut index = index / ARITY;
+        for (below, page) in path.iter().zip(path.iter().skip(1)) {
+            // the entry of the page below is the entry at the index in this level
+            let offset = (index % ARITY) * HASH_SIZE;
+            assert!(
+                page[offset..offset + HASH_SIZE] == hash_of(NODE_DOMAIN, &below[..]),
+                "integrity check failure!"
+            );
+            index /= ARITY;
+        }
+        if let Some(page) = path.last() {
+            assert!(
+                top[index] == hash_of(NODE_DOMAIN, page),
+                "integrity check failure!"
+            );
+        }
+    }
+
+    /**
+     * Panic unless `leaf` is the hash of the page last written at `index`, see `leaf_hash`, given the pages of
+     * `path_pages` as read from the levels.
+     */
+    pub fn verify_path(&self, index: usize, leaf: &Hash, path: &[[u8; PAGE_SIZE]]) {
+        assert!(index < self.size);
+        let top = self.top.lock().unwrap();
+        self.check_path(index, path, &top);
+        let expected = match path.first() {
+            Some(page) => {
+                let offset = (index % ARITY) * HASH_SIZE;
+                page[offset..offset + HASH_SIZE].try_into().unwrap()
+            }
+            None => top[index],
+        };
+        assert!(expected == *leaf, "integrity check failure!");
+    }
+
+    /**
+     * Record `leaf` as the hash of the page written at `index`, given the pages of `path_pages` as read from the
+     * levels. The pages are updated in place, and are to be written back to the levels along with the page.
+     */
+    pub fn update_path(&self, index: usize, leaf: &Hash, path: &mut [[u8; PAGE_SIZE]]) {
+        assert!(index < self.size);
+        let mut top = self.top.lock().unwrap();
+        self.check_path(index, path, &top);
+        let mut hash = *leaf;
+        let mut index = index;
+        for page in path.iter_mut() {
+            let offset = (index % ARITY) * HASH_SIZE;
+            page[offset..offset + HASH_SIZE].copy_from_slice(&hash);
+            hash = hash_of(NODE_DOMAIN, page);
+            index /= ARITY;
+        }
+        top[index] = hash;
+    }
+
+    /**
+     * The number of pages of the levels, which are kept in storages of the same kind as the pages.
+     */
+    pub fn num_level_pages(&self) -> usize {
+        level_sizes(self.size)[..self.levels.len()]
+            .iter()
+            .map(|num_entries| num_entries.div_ceil(ARITY))
+            .sum()
+    }
+
+    /**
+     * The bytes of the hashes kept in memory, at most a page.
+     */
+    pub fn top_bytes(&self) -> usize {
+        self.top.lock().unwrap().len() * HASH_SIZE
+    }
+
+    /**
+     * Extend the tree to `size` pages, the new pages being zeroes. The levels grow in place if their storage
+     * supports it, and are copied otherwise.
+     */
+    pub fn grow(&mut self, size: usize) {
+        assert!(size >= self.size);
+        let sizes = level_sizes(size);
+        for (level, num_entries) in sizes.iter().enumerate().take(self.levels.len()) {
+            let num_pages = num_entries.div_ceil(ARITY);
+            if !self.levels[level].grow(num_pages).expect("grow error") {
+                let old_pages = level_sizes(self.size)[level].div_ceil(ARITY);
+                let new_level: S = open_level(*num_entries);
+                for page_index in 0..old_pages {
+                    let page = self.read_level(level, page_index);
+                    new_level.write(page_index, &page).expect("write error");
+                }
+                self.levels[level] = new_level;
+            }
+        }
+        let top = self.top.get_mut().unwrap();
+        // the entries kept in memory move to the first page of a new level once they no longer fit in one page
+        while self.levels.len() + 1 < sizes.len() {
+            let new_level: S = open_level(sizes[self.levels.len()]);
+            let mut page = [0; PAGE_SIZE];
+            for (i, hash) in top.iter().enumerate() {
+                page[i * HASH_SIZE..(i + 1) * HASH_SIZE].copy_from_slice(hash);
+            }
+            new_level.write(0, &page).expect("write error");
+            *top = vec![hash_of(NODE_DOMAIN, &page)];
+            self.levels.push(new_level);
+        }
+        top.resize(*sizes.last().unwrap(), [0; HASH_SIZE]);
+        self.size = size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memstore::MemStore;
+
+    impl<S: BlockStorage> HashTree<S> {
+        // the pages of `path_pages`, read in one batch
+        fn read_path(&self, index: usize) -> Vec<[u8; PAGE_SIZE]> {
+            let mut path = vec![[0; PAGE_SIZE]; self.levels.len()];
+            let mut reads: Vec<(&S, usize, &mut [u8])> = self
+                .path_pages(index)
+                .into_iter()
+                .zip(path.iter_mut())
+                .map(|((level, page_index), page)| (level, page_index, &mut page[..]))
+                .collect();
+            let err = S::read_batch(&mut reads);
+            if err.is_err() {
+                panic!("read error: {:?}", err);
+            }
+            path
+        }
+
+        // same as `verify_path`, with the pages of the levels read in one batch
+        fn verify(&self, index: usize, leaf: &Hash) {
+            self.verify_path(index, leaf, &self.read_path(index));
+        }
+
+        // same as `update_path`, with the pages of the levels read in one batch and written back in another
+        fn update(&self, index: usize, leaf: &Hash) {
+            let mut path = self.read_path(index);
+            self.update_path(index, leaf, &mut path);
+            let writes: Vec<(&S, usize, &[u8])> = self
+                .path_pages(index)
+                .into_iter()
+                .zip(path.iter())
+                .map(|((level, page_index), page)| (level, page_index, &page[..]))
+                .collect();
+            let err = S::write_batch(&writes);
+            if err.is_err() {
+                panic!("write error: {:?}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn hash_tree_test() {
+        let mut tree = HashTree::<MemStore>::new(100);
+        assert_eq!(tree.levels.len(), 1);
+        let zeroes = [0; PAGE_SIZE];
+        let mut page = [0; PAGE_SIZE];
+        tree.verify(70, &HashTree::<MemStore>::leaf_hash(&zeroes));
+        for i in 0..100 {
+            page[0] = i as u8 + 1;
+            tree.update(i, &HashTree::<MemStore>::leaf_hash(&page));
+        }
+        for size in [200, 5000, 300000] {
+            tree.grow(size);
+            assert_eq!(tree.levels.len(), level_sizes(size).len() - 1);
+            for i in 0..100 {
+                page[0] = i as u8 + 1;
+                tree.verify(i, &HashTree::<MemStore>::leaf_hash(&page));
+            }
+            tree.verify(size - 1, &HashTree::<MemStore>::leaf_hash(&zeroes));
+        }
+        page[0] = 42;
+        tree.update(299999, &HashTree::<MemStore>::leaf_hash(&page));
+        tree.verify(299999, &HashTree::<MemStore>::leaf_hash(&page));
+    }
+
+    #[test]
+    #[should_panic(expected = "integrity check failure!")]
+    fn stale_hash_test() {
+        let tree = HashTree::<MemStore>::new(5000);
+        let mut page = [1; PAGE_SIZE];
+        let old_leaf = HashTree::<MemStore>::leaf_hash(&page);
+        tree.update(7, &old_leaf);
+        page[0] = 2;
+        tree.update(7, &HashTree::<MemStore>::leaf_hash(&page));
+        tree.verify(7, &old_leaf);
+    }
+
+    #[test]
+    #[should_panic(expected = "integrity check failure!")]
+    fn tampered_level_test() {
+        let tree = HashTree::<MemStore>::new(5000);
+        let leaf = HashTree::<MemStore>::leaf_hash(&[1; PAGE_SIZE]);
+        tree.update(7, &leaf);
+        // a level page rolled back to zeroes, along with the page it covers
+        tree.levels[0].write(0, &[0; PAGE_SIZE]).unwrap();
+        tree.verify(7, &HashTree::<MemStore>::leaf_hash(&[0; PAGE_SIZE]));
+    }
+}
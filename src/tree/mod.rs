@@ -1,4 +1,6 @@
+pub mod cipher;
 pub mod dynamictree;
 pub mod encvec;
-
+pub mod forkvec;
+pub mod hashtree;
 pub mod segvec;
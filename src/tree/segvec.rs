@@ -1,40 +1,173 @@
 use std::usize::MIN;
 
-use super::encvec::EncVec;
+use super::cipher::{CipherKind, StorageKey};
+use super::encvec::{EncVec, PageContext};
 use crate::params::MIN_SEGMENT_SIZE;
 use crate::storage::memstore::MemStore;
 use crate::storage::pagefile::PageFile;
+use crate::storage::storage::BlockStorage;
+use crate::utils::utils::RandGen;
 use bytemuck::{Pod, Zeroable};
 
-pub struct SegmentedVec<T: Clone + Pod + Zeroable> {
-    segments: Vec<EncVec<T, MemStore>>,
-    pub versions: Vec<u8>,
-    nonce: Vec<u32>,
+/*
+ * No metadata is kept per page in memory: the nonce of a page is kept in its header, and a page that was never
+ * written since its segment was added is still shared with the page it was forked from, i.e. the page whose index
+ * is the same without the highest bit. Before a page is written, its copies that are still shared with it get their
+ * own copy of the page, re-encrypted at their own index. The header and the position of a page are authenticated
+ * with it, and the hash tree of each segment, whose top is the only part kept in memory, rejects an old copy of a
+ * page as well as a page zeroed to read as never written, so a page never falls back to its fork source unless it
+ * was really never written.
+ * If the storage grows in place, e.g., `MmapStore`, the vector keeps a single segment that grows on every doubling.
+ * A key rotation re-encrypts the pages lazily as well: the pages are written under the new key from then on, and
+ * `sweep_keys` rewrites the remaining ones in index order, after which the previous key is dropped. A key rotated to
+ * while a sweep is in progress waits for the sweep to end, so a rotation never blocks. All the segments share the
+ * same keys, so that forks may copy the encrypted pages between segments.
+ */
+pub struct SegmentedVec<T: Clone + Pod + Zeroable, S: BlockStorage = MemStore> {
+    segments: Vec<EncVec<T, S>>,
     size: usize,
     log_size: u8,
+    cipher: CipherKind,
+    context: PageContext,
+    generation: u64,
+    key: StorageKey,
+    // the key of the previous generation until every page is swept, up to `sweep_cursor`
+    prev_key: Option<StorageKey>,
+    sweep_cursor: usize,
+    // the key of the next rotation, started once the sweep in progress ends
+    next_key: Option<StorageKey>,
+    written: bool, // whether any page was written, a vector never written can change its key at once
+    rand_gen: RandGen,
 }
 
-impl<T: Clone + Pod + Zeroable> SegmentedVec<T> {
+impl<T: Clone + Pod + Zeroable, S: BlockStorage> SegmentedVec<T, S> {
     pub fn new() -> Self {
+        Self::with_cipher(CipherKind::default())
+    }
+
+    /**
+     * A vector whose segments encrypt their pages with `cipher`.
+     */
+    pub fn with_cipher(cipher: CipherKind) -> Self {
+        Self::with_key(cipher, &StorageKey::default())
+    }
+
+    pub fn with_key(cipher: CipherKind, key: &StorageKey) -> Self {
+        let mut rand_gen = RandGen::new();
+        let tree_id = rand_gen.gen();
+        Self::with_options(cipher, key, tree_id, 0, rand_gen)
+    }
+
+    /**
+     * A vector holding the given layer of the given tree, which are authenticated with its pages. The segments draw
+     * their nonces from sources forked from `rand_gen`.
+     */
+    pub fn with_options(
+        cipher: CipherKind,
+        key: &StorageKey,
+        tree_id: u64,
+        layer: u32,
+        mut rand_gen: RandGen,
+    ) -> Self {
         println!("Creating new SegmentedVec");
-        let initial_segment = EncVec::new(MIN_SEGMENT_SIZE, &[0; 32]);
-        let init_version = MIN_SEGMENT_SIZE.trailing_zeros() as u8;
-        let mut nonce = vec![0; MIN_SEGMENT_SIZE];
-        for i in 0..MIN_SEGMENT_SIZE {
-            nonce[i] = rand::random();
-        }
+        let context = PageContext {
+            tree_id,
+            layer,
+            first_index: 0,
+        };
+        let initial_segment = EncVec::with_keys(
+            MIN_SEGMENT_SIZE,
+            cipher,
+            0,
+            key,
+            None,
+            context,
+            rand_gen.fork(),
+        );
         Self {
             segments: vec![initial_segment],
             size: MIN_SEGMENT_SIZE,
-            log_size: init_version,
-            versions: vec![init_version; MIN_SEGMENT_SIZE],
-            nonce,
+            log_size: MIN_SEGMENT_SIZE.trailing_zeros() as u8,
+            cipher,
+            context,
+            generation: 0,
+            key: key.clone(),
+            prev_key: None,
+            sweep_cursor: 0,
+            next_key: None,
+            written: false,
+            rand_gen,
+        }
+    }
+
+    /**
+     * Write the pages under `key` from now on. The pages written under the current key are rewritten by
+     * `sweep_keys`, and until then they are still read under the current key, which is kept as the previous one.
+     * If a rotation is still in progress, `key` is queued instead and its rotation starts once the sweep ends, so
+     * that at most two keys are ever in use; a key queued earlier is replaced, as no page was written under it.
+     */
+    pub fn rotate_key(&mut self, key: &StorageKey) {
+        if self.prev_key.is_some() {
+            self.next_key = Some(key.clone());
+            return;
+        }
+        let old_key = std::mem::replace(&mut self.key, key.clone());
+        if self.written {
+            self.prev_key = Some(old_key);
+            self.generation += 1;
+            self.sweep_cursor = 0;
+        }
+        self.set_segment_keys();
+    }
+
+    /**
+     * Rewrite up to `max_pages` pages under the current key, in index order whatever pages are accessed, and drop
+     * the previous key once the last page is rewritten, starting the queued rotation if any. Returns the number of
+     * pages visited, 0 once no rotation is in progress.
+     */
+    pub fn sweep_keys(&mut self, max_pages: usize) -> usize {
+        if self.prev_key.is_none() {
+            return 0;
+        }
+        let end = self.size.min(self.sweep_cursor + max_pages);
+        for index in self.sweep_cursor..end {
+            let (segment_index, within_segment_index) = self.inner_indices(index);
+            self.segments[segment_index].reencrypt(within_segment_index);
+        }
+        let swept = end - self.sweep_cursor;
+        self.sweep_cursor = end;
+        if end == self.size {
+            self.prev_key = None;
+            self.set_segment_keys();
+            if let Some(next_key) = self.next_key.take() {
+                self.rotate_key(&next_key);
+            }
+        }
+        swept
+    }
+
+    fn set_segment_keys(&mut self) {
+        for segment in self.segments.iter_mut() {
+            segment.set_keys(self.generation, &self.key, self.prev_key.as_ref());
         }
     }
 
     fn double_size(&mut self) {
-        let new_segment = EncVec::new(self.size, &[0; 32]);
-        self.segments.push(new_segment);
+        if self.segments.len() > 1 || !self.segments[0].grow(self.size * 2) {
+            let new_segment = EncVec::with_keys(
+                self.size,
+                self.cipher,
+                self.generation,
+                &self.key,
+                self.prev_key.as_ref(),
+                PageContext {
+                    first_index: self.size,
+                    ..self.context
+                },
+                self.rand_gen.fork(),
+            );
+            self.segments.push(new_segment);
+        }
         self.size *= 2;
         self.log_size += 1;
     }
@@ -55,71 +188,156 @@ impl<T: Clone + Pod + Zeroable> SegmentedVec<T> {
     //     self.segments.push(new_segment);
     //     self.size *= 2;
     // }
+    // the pages of the new segment are forked lazily, so doubling does not copy them
     pub fn double_size_and_fork_self(&mut self) {
-        let original_size = self.size;
         self.double_size();
-        self.versions.extend_from_within(0..original_size);
-        // self.versions.resize(self.size, 0);
-        self.nonce.resize(self.size, 0);
     }
 
     fn inner_indices(&self, index: usize) -> (usize, usize) {
+        if self.segments.len() == 1 {
+            return (0, index);
+        }
         let segment_index_power_two = (index / MIN_SEGMENT_SIZE) as u64;
         let segment_index = (u64::BITS - segment_index_power_two.leading_zeros()) as usize;
         let within_segment_index = index - ((1 << segment_index) / 2) * MIN_SEGMENT_SIZE;
         (segment_index, within_segment_index)
     }
 
+    fn is_written(&self, index: usize) -> bool {
+        let (segment_index, within_segment_index) = self.inner_indices(index);
+        self.segments[segment_index].is_written(within_segment_index)
+    }
+
+    // the index the page was forked from, i.e. the index without its highest bit
+    fn fork_source(index: usize) -> usize {
+        index & !(1 << (usize::BITS - 1 - index.leading_zeros()))
+    }
+
+    // the value of a page that was read back as never written
+    fn get_unwritten(&self, index: usize) -> T {
+        if index < MIN_SEGMENT_SIZE {
+            T::zeroed()
+        } else {
+            self.get(Self::fork_source(index)).unwrap()
+        }
+    }
+
     pub fn get(&self, index: usize) -> Option<T> {
         if index >= self.size {
             return None;
         }
-        let version = self.versions[index];
-        let actual_index = index & ((1 << version) - 1);
-        let (segment_index, within_segment_index) = self.inner_indices(actual_index);
-        self.segments[segment_index].get(within_segment_index, self.nonce[actual_index])
+        let (segment_index, within_segment_index) = self.inner_indices(index);
+        match self.segments[segment_index].get_written(within_segment_index) {
+            Some(value) => Some(value),
+            None => Some(self.get_unwritten(index)),
+        }
+    }
+
+    /**
+     * Same as `get` for one index of each of several vectors, e.g., the pages of a path, with the pages read in a
+     * single batch. Only the pages not written since their fork take further reads.
+     */
+    pub fn get_batch(reads: &[(&Self, usize)]) -> Vec<T> {
+        let inner_reads: Vec<(&EncVec<T, S>, usize)> = reads
+            .iter()
+            .map(|(vec, index)| {
+                assert!(*index < vec.size);
+                let (segment_index, within_segment_index) = vec.inner_indices(*index);
+                (&vec.segments[segment_index], within_segment_index)
+            })
+            .collect();
+        EncVec::get_written_batch(&inner_reads)
+            .into_iter()
+            .zip(reads)
+            .map(|(value, (vec, index))| value.unwrap_or_else(|| vec.get_unwritten(*index)))
+            .collect()
+    }
+
+    // the pages forked from this index that still share it keep the current value, and the pages forked from them
+    // in turn now share their copy; each copy is encrypted at its own index, which is authenticated with it
+    fn fork_before_write(&self, index: usize) {
+        let mut fork_offset = (index + 1).next_power_of_two().max(MIN_SEGMENT_SIZE);
+        let mut current_value = None;
+        while index + fork_offset < self.size {
+            let to_idx = index + fork_offset;
+            if !self.is_written(to_idx) {
+                let (to_segment_index, to_within_segment_index) = self.inner_indices(to_idx);
+                // a page never written reads as zeroes, which are written explicitly
+                let value = current_value.get_or_insert_with(|| self.get(index).unwrap());
+                self.segments[to_segment_index].put(to_within_segment_index, value);
+            }
+            fork_offset *= 2;
+        }
     }
 
     pub fn set(&mut self, index: usize, value: &T) {
         if index >= self.size {
             return;
         }
-        let version = self.versions[index];
-        let version_size = 1 << version;
-        if version_size != self.size {
-            // fork the original version to other indices
-            let original_index = index & (version_size - 1);
-            // TODO: avoid decrypt and re-encrypt
-            let (from_segment_index, from_within_segment_index) =
-                self.inner_indices(original_index);
-            let original_value = self.segments[from_segment_index]
-                .raw_get(from_within_segment_index)
-                .unwrap();
-            self.versions[original_index] = self.log_size;
-            let mut to_idx = original_index + version_size;
-            while to_idx < self.size {
-                if to_idx != index {
-                    let (to_segment_index, to_within_segment_index) = self.inner_indices(to_idx);
-                    self.segments[to_segment_index]
-                        .raw_put(to_within_segment_index, &original_value);
-                }
-                self.versions[to_idx] = self.log_size;
-                self.nonce[to_idx] = self.nonce[original_index];
-                to_idx += version_size;
-            }
-        }
+        self.written = true;
+        self.fork_before_write(index);
         let (segment_index, within_segment_index) = self.inner_indices(index);
-        self.nonce[index] = rand::random();
-        self.segments[segment_index].put(within_segment_index, value, self.nonce[index]);
+        self.segments[segment_index].put(within_segment_index, value);
+    }
+
+    /**
+     * Same as `set` for one index of each of several vectors, with the pages written in a single batch.
+     */
+    pub fn set_batch(writes: &mut [(&mut Self, usize, &T)]) {
+        for (vec, index, _) in writes.iter_mut() {
+            assert!(*index < vec.size);
+            vec.written = true;
+            vec.fork_before_write(*index);
+        }
+        let inner_writes: Vec<(&EncVec<T, S>, usize, &T)> = writes
+            .iter()
+            .map(|(vec, index, value)| {
+                let (segment_index, within_segment_index) = vec.inner_indices(*index);
+                (&vec.segments[segment_index], within_segment_index, *value)
+            })
+            .collect();
+        EncVec::put_batch(&inner_writes);
+    }
+
+    /**
+     * Overwrite every index with the given values in sequential order, discarding any pending forks.
+     */
+    pub fn overwrite_all(&mut self, values: &[T]) {
+        assert_eq!(values.len(), self.size);
+        self.written = true;
+        for (index, value) in values.iter().enumerate() {
+            let (segment_index, within_segment_index) = self.inner_indices(index);
+            self.segments[segment_index].put(within_segment_index, value);
+        }
     }
 
     pub fn capacity(&self) -> usize {
         self.size
     }
+
+    /**
+     * The pages of the hash trees of the segments and the bytes of hashes kept in memory, see
+     * `EncVec::hash_usage`.
+     */
+    pub fn hash_usage(&self) -> (usize, usize) {
+        self.segments
+            .iter()
+            .map(|segment| segment.hash_usage())
+            .fold((0, 0), |(pages, bytes), (segment_pages, segment_bytes)| {
+                (pages + segment_pages, bytes + segment_bytes)
+            })
+    }
 }
 
+#[cfg(test)]
 mod tests {
-    use crate::params::MIN_SEGMENT_SIZE;
+    use crate::params::{MIN_SEGMENT_SIZE, PAGE_SIZE};
+    use crate::storage::memstore::MemStore;
+    use crate::storage::mmapstore::MmapStore;
+    use crate::storage::storage::BlockStorage;
+    #[cfg(target_os = "linux")]
+    use crate::storage::uringstore::UringStore;
+    use crate::tree::cipher::{CipherKind, StorageKey};
     use crate::tree::segvec::SegmentedVec;
     #[test]
     fn it_works() {
@@ -140,4 +358,106 @@ mod tests {
         assert_eq!(vec.get(MIN_SEGMENT_SIZE * 4 - 1), Some(47));
         assert_eq!(vec.get(MIN_SEGMENT_SIZE * 4), None);
     }
+
+    #[test]
+    fn test_lazy_fork() {
+        for cipher in [CipherKind::Plain, CipherKind::Aes256Gcm] {
+            check_lazy_fork::<MemStore>(cipher);
+            check_lazy_fork::<MmapStore>(cipher);
+            #[cfg(target_os = "linux")]
+            check_lazy_fork::<UringStore>(cipher);
+        }
+    }
+
+    fn check_lazy_fork<S: BlockStorage>(cipher: CipherKind) {
+        let mut vec = SegmentedVec::<u64, S>::with_cipher(cipher);
+        let mut ref_vec = vec![0u64; MIN_SEGMENT_SIZE];
+        vec.set(3, &1);
+        ref_vec[3] = 1;
+        for round in 0..3 {
+            vec.double_size_and_fork_self();
+            ref_vec.extend_from_within(..);
+            // writing a page or one of its forks does not change the others
+            for index in [
+                3,
+                5,
+                MIN_SEGMENT_SIZE + 3,
+                ref_vec.len() - MIN_SEGMENT_SIZE + 5,
+            ] {
+                let value = (round * 100 + index) as u64;
+                vec.set(index, &value);
+                ref_vec[index] = value;
+            }
+            for (index, value) in ref_vec.iter().enumerate() {
+                assert_eq!(vec.get(index), Some(*value));
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_usage() {
+        assert_eq!(SegmentedVec::<u64>::new().hash_usage(), (0, 0));
+        let mut vec = SegmentedVec::<u64>::with_cipher(CipherKind::Aes256Gcm);
+        // a hash per page in the level, a hash per page of the level kept in memory
+        let level_pages = MIN_SEGMENT_SIZE * 32 / PAGE_SIZE;
+        assert_eq!(vec.hash_usage(), (level_pages, level_pages * 32));
+        vec.double_size_and_fork_self();
+        assert_eq!(vec.hash_usage(), (level_pages * 2, level_pages * 64));
+    }
+
+    #[test]
+    #[should_panic(expected = "integrity check failure!")]
+    fn test_zeroed_fork() {
+        let mut vec = SegmentedVec::<u64>::with_cipher(CipherKind::Aes256Gcm);
+        vec.set(5, &1);
+        vec.double_size_and_fork_self();
+        vec.set(MIN_SEGMENT_SIZE + 5, &2);
+        // the written fork zeroed by the host must not read as the value of its fork source
+        vec.segments[1].raw_put(5, &[0; PAGE_SIZE]);
+        vec.get(MIN_SEGMENT_SIZE + 5);
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let mut vec =
+            SegmentedVec::<u64>::with_key(CipherKind::Aes256Gcm, &StorageKey::from([1; 32]));
+        let mut ref_vec = vec![0u64; MIN_SEGMENT_SIZE];
+        for index in (0..MIN_SEGMENT_SIZE).step_by(7) {
+            vec.set(index, &(index as u64));
+            ref_vec[index] = index as u64;
+        }
+        vec.rotate_key(&StorageKey::from([2; 32]));
+        assert_eq!(vec.sweep_keys(MIN_SEGMENT_SIZE / 2), MIN_SEGMENT_SIZE / 2);
+        // the pages under both keys are forked into a new segment and written during the rotation
+        vec.double_size_and_fork_self();
+        ref_vec.extend_from_within(..);
+        for index in [3, MIN_SEGMENT_SIZE - 3, MIN_SEGMENT_SIZE + 7] {
+            vec.set(index, &1);
+            ref_vec[index] = 1;
+        }
+        for (index, value) in ref_vec.iter().enumerate() {
+            assert_eq!(vec.get(index), Some(*value));
+        }
+        // the next rotations wait for the current one, the last one queued replacing the others, and a page under a
+        // dropped key could not be read
+        vec.rotate_key(&StorageKey::from([4; 32]));
+        vec.rotate_key(&StorageKey::from([3; 32]));
+        assert_eq!(vec.key.as_bytes(), &[2; 32]);
+        assert_eq!(vec.sweep_keys(1000), 1000);
+        for (index, value) in ref_vec.iter().enumerate() {
+            assert_eq!(vec.get(index), Some(*value));
+        }
+        while vec.sweep_keys(1000) > 0 {}
+        assert_eq!(vec.key.as_bytes(), &[3; 32]);
+        assert!(vec.prev_key.is_none() && vec.next_key.is_none());
+        assert_eq!(vec.sweep_keys(1000), 0);
+        for (index, value) in ref_vec.iter().enumerate() {
+            assert_eq!(vec.get(index), Some(*value));
+        }
+        // a vector never written changes its key at once
+        let mut vec =
+            SegmentedVec::<u64>::with_key(CipherKind::Aes256Gcm, &StorageKey::from([1; 32]));
+        vec.rotate_key(&StorageKey::from([2; 32]));
+        assert_eq!(vec.sweep_keys(1), 0);
+    }
 }
@@ -0,0 +1,179 @@
+use crate::error::DBError;
+use crate::storage::memstore::MemStore;
+use crate::storage::storage::BlockStorage;
+use crate::{DBConfig, ObliviousDB};
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+
+// fixed-size little-endian integers and no trailing bytes, so that each key has a single encoding
+fn encoding() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+        .reject_trailing_bytes()
+}
+
+fn encode<T: Serialize + ?Sized>(val: &T) -> Result<Vec<u8>, DBError> {
+    Ok(encoding().serialize(val)?)
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, DBError> {
+    Ok(encoding().deserialize(bytes)?)
+}
+
+/**
+ * A typed view of an `ObliviousDB`. Keys and values are encoded with bincode, and every access goes through the
+ * same oblivious path as the byte interface. Keys must have a canonical encoding, e.g., no hash maps.
+ */
+pub struct TypedDB<K, V, S: BlockStorage = MemStore> {
+    db: ObliviousDB<S>,
+    phantom: PhantomData<(K, V)>,
+}
+
+impl<K: Serialize, V: Serialize + DeserializeOwned> TypedDB<K, V> {
+    pub fn new() -> Self {
+        Self::from_db(ObliviousDB::new())
+    }
+
+    pub fn with_config(config: DBConfig) -> Self {
+        Self::from_db(ObliviousDB::with_config(config))
+    }
+}
+
+impl<K: Serialize, V: Serialize + DeserializeOwned, S: BlockStorage> TypedDB<K, V, S> {
+    pub fn from_db(db: ObliviousDB<S>) -> Self {
+        Self {
+            db,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>, DBError> {
+        let raw_value = self.db.try_get(&encode(key)?)?;
+        raw_value.map(|raw| decode(&raw)).transpose()
+    }
+
+    pub fn insert(&self, key: &K, value: &V) -> Result<Option<V>, DBError> {
+        let raw_value = self.db.try_insert(encode(key)?, encode(value)?)?;
+        raw_value.map(|raw| decode(&raw)).transpose()
+    }
+
+    pub fn remove(&self, key: &K) -> Result<Option<V>, DBError> {
+        let raw_value = self.db.try_remove(&encode(key)?)?;
+        raw_value.map(|raw| decode(&raw)).transpose()
+    }
+
+    pub fn bulk_load<I: IntoIterator<Item = (K, V)>>(&self, items: I) -> Result<(), DBError> {
+        let raw_items = items
+            .into_iter()
+            .map(|(key, value)| Ok((encode(&key)?, encode(&value)?)))
+            .collect::<Result<Vec<_>, DBError>>()?;
+        self.db.bulk_load(raw_items)
+    }
+
+    pub fn inner(&self) -> &ObliviousDB<S> {
+        &self.db
+    }
+
+    pub fn into_inner(self) -> ObliviousDB<S> {
+        self.db
+    }
+}
+
+impl<K: Serialize, V: Serialize + DeserializeOwned> Default for TypedDB<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Serialize + DeserializeOwned, V: Serialize + DeserializeOwned, S: BlockStorage>
+    TypedDB<K, V, S>
+{
+    /**
+     * Collect all the decoded key-value pairs with a single sweep, see `ObliviousDB::for_each`.
+     */
+    pub fn iter(&self) -> Result<std::vec::IntoIter<(K, V)>, DBError> {
+        let mut entries = Vec::new();
+        let mut ret = Ok(());
+        self.db.for_each(|key, value| {
+            if ret.is_ok() {
+                match (decode(key), decode(value)) {
+                    (Ok(key), Ok(value)) => entries.push((key, value)),
+                    (Err(err), _) | (_, Err(err)) => ret = Err(err),
+                }
+            }
+        });
+        ret.map(|_| entries.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyStorage;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    struct Account {
+        nonce: u64,
+        balance: u128,
+        code_hash: [u8; 32],
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    struct StorageSlot {
+        address: [u8; 20],
+        slot: [u8; 32],
+    }
+
+    #[test]
+    fn typed_db_test() {
+        let db = TypedDB::<[u8; 20], Account>::new();
+        for i in 0..200u64 {
+            let account = Account {
+                nonce: i,
+                balance: i as u128 * 7,
+                code_hash: [i as u8; 32],
+            };
+            assert_eq!(db.insert(&[i as u8; 20], &account), Ok(None));
+        }
+        let account = db.get(&[3; 20]).unwrap().unwrap();
+        assert_eq!(account.nonce, 3);
+        assert_eq!(account.balance, 21);
+        assert_eq!(db.remove(&[3; 20]).unwrap(), Some(account));
+        assert_eq!(db.get(&[3; 20]), Ok(None));
+    }
+
+    #[test]
+    fn typed_db_struct_key_test() {
+        let db = TypedDB::<StorageSlot, Vec<u8>>::with_config(DBConfig {
+            key_storage: KeyStorage::Full,
+            ..Default::default()
+        });
+        let items: Vec<_> = (0..100u8)
+            .map(|i| {
+                let key = StorageSlot {
+                    address: [i; 20],
+                    slot: [i / 2; 32],
+                };
+                (key, vec![i; i as usize])
+            })
+            .collect();
+        db.bulk_load(items.clone()).unwrap();
+        for (key, value) in items.iter() {
+            assert_eq!(db.get(key).unwrap().as_ref(), Some(value));
+        }
+        let mut entries: Vec<_> = db.iter().unwrap().collect();
+        entries.sort_by_key(|(key, _)| key.address);
+        assert_eq!(entries, items);
+    }
+
+    #[test]
+    fn typed_db_decode_error_test() {
+        let db = TypedDB::<u32, u64>::new();
+        db.inner().insert(encode(&1u32).unwrap(), [1, 2, 3]);
+        assert!(matches!(db.get(&1), Err(DBError::Encoding(_))));
+    }
+}
@@ -0,0 +1,107 @@
+/*
+ * Constant-time helpers. The results are computed with arithmetic on masks instead of branches or early exits, so
+ * that the timing and the branch trace do not depend on the values compared or moved.
+ */
+use bytemuck::Pod;
+use std::hint::black_box;
+
+// returns whether a == b without branching on the values
+pub fn ct_eq(a: usize, b: usize) -> bool {
+    let x = a ^ b;
+    black_box(((x | x.wrapping_neg()) >> (usize::BITS - 1)) as u8) == 0
+}
+
+// returns whether a < b without branching on the values
+pub fn ct_lt(a: usize, b: usize) -> bool {
+    let borrow = (!a & b) | (!(a ^ b) & a.wrapping_sub(b));
+    black_box((borrow >> (usize::BITS - 1)) as u8) == 1
+}
+
+// returns whether two slices of the same length are equal, always reading all the bytes
+pub fn ct_eq_bytes(a: &[u8], b: &[u8]) -> bool {
+    assert_eq!(a.len(), b.len());
+    let mut diff = 0u8;
+    for (x, y) in a.iter().zip(b) {
+        diff |= x ^ y;
+    }
+    black_box(diff) == 0
+}
+
+// returns whether `a` starts with `prefix`, reading all the bytes of the prefix; only the lengths are public
+pub fn ct_starts_with(a: &[u8], prefix: &[u8]) -> bool {
+    a.len() >= prefix.len() && ct_eq_bytes(&a[..prefix.len()], prefix)
+}
+
+// compares two plain values byte by byte in constant time
+pub fn ct_eq_pod<T: Pod>(a: &T, b: &T) -> bool {
+    ct_eq_bytes(bytemuck::bytes_of(a), bytemuck::bytes_of(b))
+}
+
+// copies src to dst if cond is true, touching dst in any case
+pub fn cmov<T: Pod>(cond: bool, dst: &mut T, src: &T) {
+    let mask = black_box(0u8.wrapping_sub(cond as u8));
+    let dst_bytes = bytemuck::bytes_of_mut(dst);
+    let src_bytes = bytemuck::bytes_of(src);
+    for (d, s) in dst_bytes.iter_mut().zip(src_bytes) {
+        *d ^= mask & (*d ^ *s);
+    }
+}
+
+// swaps a and b if cond is true, touching both in any case
+pub fn cswap<T: Pod>(cond: bool, a: &mut T, b: &mut T) {
+    let mask = black_box(0u8.wrapping_sub(cond as u8));
+    let a_bytes = bytemuck::bytes_of_mut(a);
+    let b_bytes = bytemuck::bytes_of_mut(b);
+    for (x, y) in a_bytes.iter_mut().zip(b_bytes.iter_mut()) {
+        let diff = mask & (*x ^ *y);
+        *x ^= diff;
+        *y ^= diff;
+    }
+}
+
+// returns a if cond is true and b otherwise
+pub fn ct_select<T: Pod>(cond: bool, a: T, b: T) -> T {
+    let mut ret = b;
+    cmov(cond, &mut ret, &a);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ct_eq() {
+        assert!(ct_eq(3, 3));
+        assert!(!ct_eq(3, 4));
+        assert!(!ct_eq(0, usize::MAX));
+        assert!(ct_lt(3, 4));
+        assert!(!ct_lt(4, 3));
+        assert!(!ct_lt(3, 3));
+        assert!(ct_lt(0, usize::MAX));
+        assert!(!ct_lt(usize::MAX, 1));
+        assert!(ct_eq_bytes(b"abc", b"abc"));
+        assert!(!ct_eq_bytes(b"abc", b"abd"));
+        assert!(ct_starts_with(b"abcd", b"abc"));
+        assert!(!ct_starts_with(b"abd", b"abc"));
+        assert!(!ct_starts_with(b"ab", b"abc"));
+        assert!(ct_eq_pod(&[1u64, 2], &[1, 2]));
+        assert!(!ct_eq_pod(&[1u64, 2], &[1, 3]));
+    }
+
+    #[test]
+    fn test_cmov() {
+        let mut dst = [1u64, 2];
+        cmov(false, &mut dst, &[3, 4]);
+        assert_eq!(dst, [1, 2]);
+        cmov(true, &mut dst, &[3, 4]);
+        assert_eq!(dst, [3, 4]);
+        assert_eq!(ct_select(true, 5u32, 6), 5);
+        assert_eq!(ct_select(false, 5u32, 6), 6);
+        let (mut a, mut b) = (1u64, 2u64);
+        cswap(false, &mut a, &mut b);
+        assert_eq!((a, b), (1, 2));
+        cswap(true, &mut a, &mut b);
+        assert_eq!((a, b), (2, 1));
+    }
+}
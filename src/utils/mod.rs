@@ -1 +1,3 @@
+pub mod ct;
+pub mod osort;
 pub mod utils;
@@ -0,0 +1,123 @@
+/*
+ * Bitonic sorting network. The sequence of compared positions only depends on the length of the input, and every
+ * comparison swaps with a conditional move, so neither the branches nor the memory accesses reveal the order.
+ * Bytes are moved with a compaction network instead, which is cheaper when the order to keep is the input order.
+ */
+use super::ct::{cswap, ct_lt};
+use bytemuck::Pod;
+use std::hint::black_box;
+use zeroize::Zeroizing;
+
+// sorts the items in ascending order of key, the order of items with equal keys is unspecified
+pub fn bitonic_sort_by_key<T: Pod, F: Fn(&T) -> usize>(items: &mut [T], key: &F) {
+    bitonic_sort(items, true, key);
+}
+
+fn bitonic_sort<T: Pod, F: Fn(&T) -> usize>(items: &mut [T], ascending: bool, key: &F) {
+    let n = items.len();
+    if n <= 1 {
+        return;
+    }
+    let half = n / 2;
+    let (left, right) = items.split_at_mut(half);
+    bitonic_sort(left, !ascending, key);
+    bitonic_sort(right, ascending, key);
+    bitonic_merge(items, ascending, key);
+}
+
+// works for any length, by comparing with the largest power of 2 below the length instead of the half
+fn bitonic_merge<T: Pod, F: Fn(&T) -> usize>(items: &mut [T], ascending: bool, key: &F) {
+    let n = items.len();
+    if n <= 1 {
+        return;
+    }
+    let m = 1 << (usize::BITS - 1 - (n - 1).leading_zeros());
+    let (left, right) = items.split_at_mut(m);
+    for i in 0..n - m {
+        let out_of_order = ct_lt(key(&right[i]), key(&left[i])) == ascending;
+        cswap(out_of_order, &mut left[i], &mut right[i]);
+    }
+    bitonic_merge(left, ascending, key);
+    bitonic_merge(right, ascending, key);
+}
+
+/**
+ * The bytes for which `keep` holds, moved to the front in their order and followed by zeroes. Each byte moves left by
+ * the number of bytes dropped before it, one power of 2 per pass, and every pass reads and writes every position, so
+ * neither the branches nor the memory accesses depend on which bytes are kept. The moves never collide, as the
+ * distances only grow along the bytes kept.
+ */
+pub fn compact_bytes<F: Fn(usize) -> bool>(bytes: &[u8], keep: &F) -> Zeroizing<Vec<u8>> {
+    let n = bytes.len();
+    assert!(n < 1 << 23);
+    // a byte kept, its flag above it and its distance above the flag; zero once dropped
+    let mut items = Zeroizing::new(Vec::with_capacity(n));
+    let mut dropped = 0u32;
+    for (i, byte) in bytes.iter().enumerate() {
+        let kept = keep(i) as u32;
+        items.push(kept.wrapping_neg() & (dropped << 9 | 1 << 8 | *byte as u32));
+        dropped += kept ^ 1;
+    }
+    let mut moved = Zeroizing::new(vec![0u32; n]);
+    let mut bit = 0;
+    while 1 << bit < n {
+        let step = 1 << bit;
+        let shift = black_box(9 + bit);
+        for ((out, item), next) in moved[..n - step]
+            .iter_mut()
+            .zip(&items[..n - step])
+            .zip(&items[step..])
+        {
+            let take = ((next >> shift) & (next >> 8) & 1).wrapping_neg();
+            let stay = (!(item >> shift) & (item >> 8) & 1).wrapping_neg();
+            *out = (next & take) | (item & stay);
+        }
+        for (out, item) in moved[n - step..].iter_mut().zip(&items[n - step..]) {
+            let stay = (!(item >> shift) & (item >> 8) & 1).wrapping_neg();
+            *out = item & stay;
+        }
+        std::mem::swap(&mut items, &mut moved);
+        bit += 1;
+    }
+    Zeroizing::new(items.iter().map(|item| *item as u8).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::random;
+
+    #[test]
+    fn test_bitonic_sort() {
+        for n in [0, 1, 2, 3, 7, 8, 100, 1000] {
+            let mut items: Vec<[usize; 2]> = (0..n).map(|i| [random::<usize>() % 50, i]).collect();
+            let mut expected = items.clone();
+            bitonic_sort_by_key(&mut items, &|item: &[usize; 2]| item[0]);
+            expected.sort_by_key(|item| item[0]);
+            let keys: Vec<usize> = items.iter().map(|item| item[0]).collect();
+            let expected_keys: Vec<usize> = expected.iter().map(|item| item[0]).collect();
+            assert_eq!(keys, expected_keys);
+            let mut ids: Vec<usize> = items.iter().map(|item| item[1]).collect();
+            ids.sort();
+            assert_eq!(ids, (0..n).collect::<Vec<usize>>());
+        }
+    }
+
+    #[test]
+    fn test_compact_bytes() {
+        for n in [0, 1, 2, 3, 100, 5000] {
+            let bytes: Vec<u8> = (0..n).map(|_| random()).collect();
+            let keep: Vec<bool> = (0..n).map(|_| random::<u8>() < 85).collect();
+            let expected: Vec<u8> = bytes
+                .iter()
+                .zip(&keep)
+                .filter(|(_, kept)| **kept)
+                .map(|(byte, _)| *byte)
+                .collect();
+            let compacted = compact_bytes(&bytes, &|i| keep[i]);
+            assert_eq!(compacted.len(), n);
+            assert_eq!(compacted[..expected.len()], expected[..]);
+            assert!(compacted[expected.len()..].iter().all(|byte| *byte == 0));
+        }
+    }
+}
@@ -1,18 +1,40 @@
 use bytemuck::{Pod, Zeroable};
 use rand::distributions::{Distribution, Standard};
-use rand::rngs::OsRng;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::fmt::Debug;
+use zeroize::Zeroize;
 
-// Define a struct that wraps the RNG
+/**
+ * Source of the randomness of the database: the positions, the salts, the eviction choices and the nonces. It is a
+ * CSPRNG seeded by the OS, or, with `from_seed`, a deterministic stream that makes the runs of tests and fuzzers
+ * reproducible, and must not be used in production since it makes the positions predictable.
+ * Each component draws from its own source, forked from the source of the component that creates it, so that the
+ * streams do not depend on the order in which threads draw from them.
+ */
 pub struct RandGen {
-    rng: OsRng,
+    rng: StdRng,
 }
 
 impl RandGen {
     pub fn new() -> Self {
         RandGen {
-            rng: OsRng::default(),
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    pub fn from_seed(seed: u64) -> Self {
+        RandGen {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /**
+     * A source for a new component, seeded from this one.
+     */
+    pub fn fork(&mut self) -> Self {
+        RandGen {
+            rng: StdRng::from_rng(&mut self.rng).unwrap(),
         }
     }
 
@@ -25,10 +47,27 @@ impl RandGen {
     }
 }
 
+impl Default for RandGen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub fn get_low_bits(value: usize, num_bits: u8) -> usize {
     value & ((1 << num_bits) - 1)
 }
 
+/**
+ * Clear a vector of plain values and zero its whole allocation, including the slots past its length that removed
+ * values were left in. The allocations dropped when the vector grew are not covered.
+ */
+pub fn wipe_vec<T: Copy>(vec: &mut Vec<T>) {
+    vec.clear();
+    let num_bytes = vec.capacity() * std::mem::size_of::<T>();
+    // zeroes are only written, so the padding of T is never read
+    unsafe { std::slice::from_raw_parts_mut(vec.as_mut_ptr() as *mut u8, num_bytes) }.zeroize();
+}
+
 pub trait SimpleVal:
     Clone + Copy + Pod + Zeroable + PartialEq + Eq + std::marker::Send + std::marker::Sync
 {